@@ -1,13 +1,14 @@
 //! Examples for luis_sys usage.
 
 use env_logger;
-use futures::Stream;
+use futures::{Future, Stream};
 use log::{error, info};
 
 use luis_sys::{
-    builder::RecognizerConfig, events::*, CancellationResult, Result,
+    builder::RecognizerConfig, events::*, lexicon::*, normalize::*, ssml::*,
+    CancellationResult, Result,
 };
-use std::env;
+use std::{env, time::Duration};
 use tokio;
 
 const TEXT: &str = "你好！";
@@ -55,6 +56,69 @@ fn synthesis_once(factory: &RecognizerConfig) -> Result {
     Ok(())
 }
 
+#[allow(dead_code)]
+fn ssml_synthesis_once(factory: &RecognizerConfig) -> Result {
+    info!("Synchronous TTS of SSML document");
+    let mut doc = Ssml::new("zh-CN");
+    doc.voice("zh-CN-XiaoxiaoNeural", |c| {
+        c.text(TEXT).pause(Duration::from_millis(300)).prosody(
+            Prosody::new().rate("-10%").pitch("+2st"),
+            |c| {
+                c.text(TEXT2);
+            },
+        );
+    });
+    info!("SSML: {}", doc.to_xml()?);
+    let mut synth = factory.synthesizer()?;
+    let rr = synth.ssml_synthesis_once(&doc)?;
+    info!("Synthesized: {:?} bytes", rr.audio_data_length());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn lexicon_synthesis_once(factory: &RecognizerConfig) -> Result {
+    info!("Synchronous TTS with custom pronunciation");
+    let mut lexicon = Lexicon::new("en-US", PhoneticAlphabet::Ipa);
+    lexicon
+        .alias("Contoso Ltd.", "Contoso Limited")
+        .phoneme("Xbox", "ˈɛksbɒks");
+    info!("PLS: {}", lexicon.to_pls()?);
+    let mut doc = Ssml::new("en-US");
+    doc.voice("en-US-AriaNeural", |c| {
+        c.pronounce("Buy an Xbox at Contoso Ltd.", &lexicon);
+    });
+    let mut synth = factory.synthesizer()?;
+    let rr = synth.ssml_synthesis_once(&doc)?;
+    info!("Synthesized: {:?} bytes", rr.audio_data_length());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn normalized_synthesis_once(factory: &RecognizerConfig) -> Result {
+    info!("Synchronous TTS of normalized text");
+    let normalizer = Normalizer::for_language("en-US");
+    let text = "Dr. Smith paid $12.50 on 2019-08-07 at 10:30.";
+    info!("Normalized: {}", normalizer.to_text(text));
+    let mut synth = factory.synthesizer()?;
+    let rr = synth.normalized_synthesis_once(text, &normalizer)?;
+    info!("Synthesized: {:?} bytes", rr.audio_data_length());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn synthesis_to_file(factory: &RecognizerConfig) -> Result {
+    info!("Synchronous TTS to file");
+    let mut synth = factory.synthesizer()?;
+    let format = synth.output_format()?;
+    let path = format!("tts_simple.{}", format.extension());
+    let rr = synth.synthesize_to_file(TEXT, &path)?;
+    info!(
+        "Synthesized: {} bytes of {:?} to {}",
+        rr.audio_length, rr.format, path
+    );
+    Ok(())
+}
+
 #[allow(dead_code)]
 fn synthesis_stream(factory: &RecognizerConfig) -> Result {
     info!("Asynchronous TTS, streaming Event object");
@@ -78,3 +142,24 @@ fn synthesis_stream(factory: &RecognizerConfig) -> Result {
     tokio::run(promise);
     Ok(())
 }
+
+#[allow(dead_code)]
+fn synthesis_tasks(factory: &RecognizerConfig) -> Result {
+    info!("Asynchronous TTS, progress and outcome of each request");
+    let mut synth = factory.synthesizer()?;
+    let first = synth.synthesize(TEXT)?;
+    let second = synth.synthesize(TEXT2)?;
+    let progress = second.progress_stream()?.for_each(|p| {
+        info!(
+            "{:?} {:?}: {} bytes, first audio after {:?}",
+            p.id, p.state, p.bytes, p.first_audio_latency
+        );
+        Ok(())
+    });
+    let promise = first
+        .join(second)
+        .map(|(a, b)| info!("outcomes: {:?}, {:?}", a, b))
+        .map_err(|err| error!("synthesis failed: {}", err));
+    tokio::run(progress.join(promise).map(|_| ()));
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Voice catalog from the service or a local fixture.
+
+use env_logger;
+use log::info;
+
+use luis_sys::{builder::RecognizerConfig, voices::*, Result};
+use std::{env, time::Duration};
+
+const FIXTURE: &str = "examples/fixtures/voices.json";
+
+fn main() {
+    env::set_var("RUST_LOG", "debug,luis_sys=trace");
+    env_logger::init();
+    catalog_test().unwrap();
+}
+
+fn catalog_test() -> Result {
+    // Replace by `VoicesEndpoint::from_subscription` for the service.
+    let voices = Voices::new(
+        || VoiceCatalog::from_file(FIXTURE),
+        Duration::from_secs(3600),
+    );
+    let catalog = voices.catalog()?;
+    info!("{} voices of {:?}", catalog.len(), catalog.locales());
+
+    for query in &["en-GB female neural", "en male", "zh cheerful", "en-US"] {
+        info!("{}: {}", query, voices.select_name(query)?);
+    }
+
+    let query = VoiceQuery::new().locale("zh-CN").gender(Gender::Male);
+    for voice in catalog.filter(&query) {
+        info!("{:?}: {} {:?}", query, voice.short_name, voice.style_list);
+    }
+
+    let mut factory =
+        RecognizerConfig::from_subscription("YourSubscriptionKey", "Region")?;
+    factory.put_synth_voice_name(voices.select_name("en-GB female")?)?;
+    info!("synthesis voice: {}", factory.synth_voice_name()?);
+    Ok(())
+}
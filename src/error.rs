@@ -16,6 +16,12 @@ pub enum SpxError {
     NoMatch(Value),
     #[fail(display = "error occured with text: {}", _0)]
     Other(String),
+    #[fail(display = "invalid configuration of {}: {}", _0, _1)]
+    InvalidConfig(String, String),
+    #[fail(display = "invalid value of property {}: {:?}", _0, _1)]
+    InvalidProperty(String, String),
+    #[fail(display = "invalid SSML {}: {}", _0, _1)]
+    InvalidSsml(String, String),
     #[fail(display = "there is nothing")]
     IsNothing,
     #[fail(display = "an interior nul byte was found")]
@@ -148,5 +148,15 @@ macro_rules! FlattenProps {
                 self.props.put_by_name(name, value)
             }
         }
+
+        /// Show the handle and effective properties.
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("handle", &(self.handle as usize))
+                    .field("props", &self.props)
+                    .finish()
+            }
+        }
     };
 }
@@ -1,12 +1,19 @@
 //! Struct and trait to retrieve or set a property value from a property collection.
 
 use crate::speech_api::*;
-use crate::{error::Unimplemented, hr, Result, SmartHandle};
+use crate::{
+    error::{InvalidProperty, Unimplemented},
+    hr, Result, SmartHandle, SpxError,
+};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     ffi::{CStr, CString},
     fmt,
     os::raw::c_int,
     ptr::null,
+    str::FromStr,
+    time::Duration,
 };
 
 /// Behavior of the property bag.
@@ -86,9 +93,754 @@ impl PropertyBag for Properties {
     }
 }
 
-/// Do nothing, just derive a Debug trait.
+/// Show the effective values of known properties, secrets are redacted.
 impl fmt::Debug for Properties {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Properties {{handle: {}, ...}}.", self.handle as usize)
+        let mut d = f.debug_struct("Properties");
+        d.field("handle", &(self.handle as usize));
+        match self.snapshot() {
+            Ok(snapshot) => d.field("values", &snapshot.values),
+            Err(err) => d.field("error", &err.to_string()),
+        };
+        d.finish()
+    }
+}
+
+/// Known properties of the speech service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Property {
+    SubscriptionKey,
+    Endpoint,
+    Region,
+    AuthorizationToken,
+    AuthorizationType,
+    EndpointId,
+    ProxyHostName,
+    ProxyPort,
+    ProxyUserName,
+    ProxyPassword,
+    TranslationToLanguages,
+    TranslationVoice,
+    TranslationFeatures,
+    IntentRegion,
+    RecoMode,
+    RecoLanguage,
+    SessionId,
+    SynthLanguage,
+    SynthVoice,
+    SynthOutputFormat,
+    InitialSilenceTimeout,
+    EndSilenceTimeout,
+    SegmentationSilenceTimeout,
+    EnableAudioLogging,
+    AutoDetectSourceLanguages,
+    AutoDetectSourceLanguageResult,
+    LanguageIdMode,
+    RequestDetailedResult,
+    RequestProfanityFilter,
+    ProfanityOption,
+    PostProcessingOption,
+    RequestWordLevelTimestamps,
+    StablePartialResultThreshold,
+    OutputFormatOption,
+    TranslationRequestStablePartialResult,
+    JsonResult,
+    JsonErrorDetails,
+    RecognitionLatency,
+    CancellationReason,
+    CancellationReasonText,
+    CancellationReasonDetailedText,
+    IntentJsonResult,
+    DeviceNameForCapture,
+    LogFilename,
+}
+
+/// Type of the property value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PropertyKind {
+    Bool,
+    /// Duration in milliseconds.
+    Millis,
+    Number,
+    Text,
+    Url,
+    /// BCP-47 language tag.
+    Language,
+    /// Comma separated language tags.
+    Languages,
+    /// One of the listed names.
+    Choice(&'static [&'static str]),
+}
+
+use self::Property::*;
+
+impl Property {
+    /// All the known properties.
+    pub fn all() -> &'static [Property] {
+        &[
+            SubscriptionKey,
+            Endpoint,
+            Region,
+            AuthorizationToken,
+            AuthorizationType,
+            EndpointId,
+            ProxyHostName,
+            ProxyPort,
+            ProxyUserName,
+            ProxyPassword,
+            TranslationToLanguages,
+            TranslationVoice,
+            TranslationFeatures,
+            IntentRegion,
+            RecoMode,
+            RecoLanguage,
+            SessionId,
+            SynthLanguage,
+            SynthVoice,
+            SynthOutputFormat,
+            InitialSilenceTimeout,
+            EndSilenceTimeout,
+            SegmentationSilenceTimeout,
+            EnableAudioLogging,
+            AutoDetectSourceLanguages,
+            AutoDetectSourceLanguageResult,
+            LanguageIdMode,
+            RequestDetailedResult,
+            RequestProfanityFilter,
+            ProfanityOption,
+            PostProcessingOption,
+            RequestWordLevelTimestamps,
+            StablePartialResultThreshold,
+            OutputFormatOption,
+            TranslationRequestStablePartialResult,
+            JsonResult,
+            JsonErrorDetails,
+            RecognitionLatency,
+            CancellationReason,
+            CancellationReasonText,
+            CancellationReasonDetailedText,
+            IntentJsonResult,
+            DeviceNameForCapture,
+            LogFilename,
+        ]
+    }
+
+    /// Identity in the SDK.
+    pub fn id(self) -> PropertyId {
+        match self {
+            SubscriptionKey => PropertyId_SpeechServiceConnection_Key,
+            Endpoint => PropertyId_SpeechServiceConnection_Endpoint,
+            Region => PropertyId_SpeechServiceConnection_Region,
+            AuthorizationToken => PropertyId_SpeechServiceAuthorization_Token,
+            AuthorizationType => PropertyId_SpeechServiceAuthorization_Type,
+            EndpointId => PropertyId_SpeechServiceConnection_EndpointId,
+            ProxyHostName => PropertyId_SpeechServiceConnection_ProxyHostName,
+            ProxyPort => PropertyId_SpeechServiceConnection_ProxyPort,
+            ProxyUserName => PropertyId_SpeechServiceConnection_ProxyUserName,
+            ProxyPassword => PropertyId_SpeechServiceConnection_ProxyPassword,
+            TranslationToLanguages => {
+                PropertyId_SpeechServiceConnection_TranslationToLanguages
+            }
+            TranslationVoice => {
+                PropertyId_SpeechServiceConnection_TranslationVoice
+            }
+            TranslationFeatures => {
+                PropertyId_SpeechServiceConnection_TranslationFeatures
+            }
+            IntentRegion => PropertyId_SpeechServiceConnection_IntentRegion,
+            RecoMode => PropertyId_SpeechServiceConnection_RecoMode,
+            RecoLanguage => PropertyId_SpeechServiceConnection_RecoLanguage,
+            SessionId => PropertyId_Speech_SessionId,
+            SynthLanguage => PropertyId_SpeechServiceConnection_SynthLanguage,
+            SynthVoice => PropertyId_SpeechServiceConnection_SynthVoice,
+            SynthOutputFormat => {
+                PropertyId_SpeechServiceConnection_SynthOutputFormat
+            }
+            InitialSilenceTimeout => {
+                PropertyId_SpeechServiceConnection_InitialSilenceTimeoutMs
+            }
+            EndSilenceTimeout => {
+                PropertyId_SpeechServiceConnection_EndSilenceTimeoutMs
+            }
+            SegmentationSilenceTimeout => {
+                PropertyId_Speech_SegmentationSilenceTimeoutMs
+            }
+            EnableAudioLogging => {
+                PropertyId_SpeechServiceConnection_EnableAudioLogging
+            }
+            AutoDetectSourceLanguages => {
+                PropertyId_SpeechServiceConnection_AutoDetectSourceLanguages
+            }
+            AutoDetectSourceLanguageResult => {
+                PropertyId_SpeechServiceConnection_AutoDetectSourceLanguageResult
+            }
+            LanguageIdMode => PropertyId_SpeechServiceConnection_LanguageIdMode,
+            RequestDetailedResult => {
+                PropertyId_SpeechServiceResponse_RequestDetailedResultTrueFalse
+            }
+            RequestProfanityFilter => {
+                PropertyId_SpeechServiceResponse_RequestProfanityFilterTrueFalse
+            }
+            ProfanityOption => PropertyId_SpeechServiceResponse_ProfanityOption,
+            PostProcessingOption => {
+                PropertyId_SpeechServiceResponse_PostProcessingOption
+            }
+            RequestWordLevelTimestamps => {
+                PropertyId_SpeechServiceResponse_RequestWordLevelTimestamps
+            }
+            StablePartialResultThreshold => {
+                PropertyId_SpeechServiceResponse_StablePartialResultThreshold
+            }
+            OutputFormatOption => {
+                PropertyId_SpeechServiceResponse_OutputFormatOption
+            }
+            TranslationRequestStablePartialResult => {
+                PropertyId_SpeechServiceResponse_TranslationRequestStablePartialResult
+            }
+            JsonResult => PropertyId_SpeechServiceResponse_JsonResult,
+            JsonErrorDetails => {
+                PropertyId_SpeechServiceResponse_JsonErrorDetails
+            }
+            RecognitionLatency => {
+                PropertyId_SpeechServiceResponse_RecognitionLatencyMs
+            }
+            CancellationReason => PropertyId_CancellationDetails_Reason,
+            CancellationReasonText => PropertyId_CancellationDetails_ReasonText,
+            CancellationReasonDetailedText => {
+                PropertyId_CancellationDetails_ReasonDetailedText
+            }
+            IntentJsonResult => {
+                PropertyId_LanguageUnderstandingServiceResponse_JsonResult
+            }
+            DeviceNameForCapture => PropertyId_AudioConfig_DeviceNameForCapture,
+            LogFilename => PropertyId_Speech_LogFilename,
+        }
+    }
+
+    /// Type of the value.
+    pub fn kind(self) -> PropertyKind {
+        match self {
+            Endpoint => PropertyKind::Url,
+            ProxyPort | StablePartialResultThreshold => PropertyKind::Number,
+            RecoLanguage | SynthLanguage | AutoDetectSourceLanguageResult => {
+                PropertyKind::Language
+            }
+            TranslationToLanguages | AutoDetectSourceLanguages => {
+                PropertyKind::Languages
+            }
+            InitialSilenceTimeout
+            | EndSilenceTimeout
+            | SegmentationSilenceTimeout
+            | RecognitionLatency => PropertyKind::Millis,
+            EnableAudioLogging
+            | RequestDetailedResult
+            | RequestProfanityFilter
+            | RequestWordLevelTimestamps
+            | TranslationRequestStablePartialResult => PropertyKind::Bool,
+            RecoMode => PropertyKind::Choice(&[
+                "INTERACTIVE",
+                "CONVERSATION",
+                "DICTATION",
+            ]),
+            LanguageIdMode => PropertyKind::Choice(&["AtStart", "Continuous"]),
+            ProfanityOption => {
+                PropertyKind::Choice(&["Masked", "Removed", "Raw"])
+            }
+            PostProcessingOption => PropertyKind::Choice(&["TrueText"]),
+            OutputFormatOption => PropertyKind::Choice(&["Simple", "Detailed"]),
+            _ => PropertyKind::Text,
+        }
+    }
+
+    /// Name of the property in snake case.
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        for (i, c) in format!("{:?}", self).chars().enumerate() {
+            if c.is_ascii_uppercase() {
+                if i > 0 {
+                    name.push('_');
+                }
+                name.push(c.to_ascii_lowercase());
+            } else {
+                name.push(c);
+            }
+        }
+        name
+    }
+
+    /// Look up the property by its name.
+    pub fn from_name(name: &str) -> Option<Property> {
+        let name = name.to_lowercase().replace('-', "_");
+        Property::all().iter().cloned().find(|p| p.name() == name)
+    }
+
+    /// Credentials never shown in diagnostics.
+    pub fn is_secret(self) -> bool {
+        match self {
+            SubscriptionKey | AuthorizationToken | ProxyPassword => true,
+            _ => false,
+        }
+    }
+
+    /// Parse and check the text value.
+    pub fn parse(self, text: &str) -> Result<PropertyValue> {
+        let text = text.trim();
+        let invalid = || InvalidProperty(self.name(), text.to_string());
+        let value = match self.kind() {
+            PropertyKind::Bool => match text.to_lowercase().as_str() {
+                "true" | "1" => PropertyValue::Bool(true),
+                "false" | "0" => PropertyValue::Bool(false),
+                _ => return Err(invalid()),
+            },
+            PropertyKind::Millis => {
+                let ms = text.parse().map_err(|_| invalid())?;
+                PropertyValue::Millis(Duration::from_millis(ms))
+            }
+            PropertyKind::Number => {
+                PropertyValue::Number(text.parse().map_err(|_| invalid())?)
+            }
+            PropertyKind::Text => PropertyValue::Text(text.to_string()),
+            PropertyKind::Url => {
+                let valid = text
+                    .find("://")
+                    .map_or(false, |pos| pos > 0 && text.len() > pos + 3);
+                if !valid {
+                    return Err(invalid());
+                }
+                PropertyValue::Url(text.to_string())
+            }
+            PropertyKind::Language => {
+                if !is_language_tag(text) {
+                    return Err(invalid());
+                }
+                PropertyValue::Language(text.to_string())
+            }
+            PropertyKind::Languages => {
+                let langs: Vec<String> = text
+                    .split(',')
+                    .map(|lang| lang.trim().to_string())
+                    .collect();
+                if !langs.iter().all(|lang| is_language_tag(lang)) {
+                    return Err(invalid());
+                }
+                PropertyValue::Languages(langs)
+            }
+            PropertyKind::Choice(names) => {
+                match names.iter().find(|n| n.eq_ignore_ascii_case(text)) {
+                    Some(name) => PropertyValue::Choice(name),
+                    None => return Err(invalid()),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Property {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Property::from_name(s).ok_or_else(|| {
+            InvalidProperty(s.to_string(), String::from("unknown property"))
+        })
+    }
+}
+
+/// Handling of profanity in the results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Profanity {
+    /// Replace the letters of profane words with stars.
+    Masked,
+    /// Remove profane words.
+    Removed,
+    /// Keep profane words as is.
+    Raw,
+}
+
+impl Profanity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Profanity::Masked => "Masked",
+            Profanity::Removed => "Removed",
+            Profanity::Raw => "Raw",
+        }
+    }
+}
+
+impl FromStr for Profanity {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Property::ProfanityOption.parse(s)? {
+            PropertyValue::Choice("Removed") => Ok(Profanity::Removed),
+            PropertyValue::Choice("Raw") => Ok(Profanity::Raw),
+            _ => Ok(Profanity::Masked),
+        }
+    }
+}
+
+/// Format of the recognition results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResultFormat {
+    Simple,
+    Detailed,
+}
+
+impl ResultFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResultFormat::Simple => "Simple",
+            ResultFormat::Detailed => "Detailed",
+        }
+    }
+}
+
+impl FromStr for ResultFormat {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Property::OutputFormatOption.parse(s)? {
+            PropertyValue::Choice("Detailed") => Ok(ResultFormat::Detailed),
+            _ => Ok(ResultFormat::Simple),
+        }
+    }
+}
+
+/// Typed value of the property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Millis(Duration),
+    Number(u32),
+    Text(String),
+    Url(String),
+    Language(String),
+    Languages(Vec<String>),
+    Choice(&'static str),
+}
+
+impl PropertyValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            PropertyValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_duration(&self) -> Option<Duration> {
+        match *self {
+            PropertyValue::Millis(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<u32> {
+        match *self {
+            PropertyValue::Number(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropertyValue::Bool(v) => write!(f, "{}", v),
+            PropertyValue::Millis(v) => write!(f, "{}", v.as_millis()),
+            PropertyValue::Number(v) => write!(f, "{}", v),
+            PropertyValue::Text(v)
+            | PropertyValue::Url(v)
+            | PropertyValue::Language(v) => write!(f, "{}", v),
+            PropertyValue::Languages(v) => write!(f, "{}", v.join(",")),
+            PropertyValue::Choice(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Typed access of the known properties, empty value is taken as not set.
+pub trait TypedProperties: PropertyBag {
+    fn get_property(&self, prop: Property) -> Result<Option<PropertyValue>> {
+        let text = self.get_by_id(prop.id())?;
+        if text.is_empty() {
+            return Ok(None);
+        }
+        prop.parse(&text).map(Some)
+    }
+
+    /// Check the value then put it.
+    fn put_property(&self, prop: Property, value: &PropertyValue) -> Result {
+        let text = value.to_string();
+        let parsed = prop.parse(&text)?;
+        if std::mem::discriminant(&parsed) != std::mem::discriminant(value) {
+            return Err(InvalidProperty(prop.name(), text));
+        }
+        self.put_by_id(prop.id(), text)
+    }
+
+    /// Parse and check the text value then put it.
+    fn put_property_str(&self, prop: Property, text: &str) -> Result {
+        let value = prop.parse(text)?;
+        self.put_by_id(prop.id(), value.to_string())
+    }
+
+    fn get_bool(&self, prop: Property) -> Result<Option<bool>> {
+        Ok(self.get_property(prop)?.and_then(|v| v.as_bool()))
+    }
+
+    fn put_bool(&self, prop: Property, value: bool) -> Result {
+        self.put_property(prop, &PropertyValue::Bool(value))
+    }
+
+    fn get_duration(&self, prop: Property) -> Result<Option<Duration>> {
+        Ok(self.get_property(prop)?.and_then(|v| v.as_duration()))
+    }
+
+    fn put_duration(&self, prop: Property, value: Duration) -> Result {
+        self.put_property(prop, &PropertyValue::Millis(value))
+    }
+
+    fn put_text(&self, prop: Property, value: &str) -> Result {
+        self.put_property(prop, &PropertyValue::Text(value.to_string()))
+    }
+
+    fn get_number(&self, prop: Property) -> Result<Option<u32>> {
+        Ok(self.get_property(prop)?.and_then(|v| v.as_number()))
+    }
+
+    fn put_number(&self, prop: Property, value: u32) -> Result {
+        self.put_property(prop, &PropertyValue::Number(value))
+    }
+
+    /// Value of any kind in text.
+    fn get_text(&self, prop: Property) -> Result<Option<String>> {
+        Ok(self.get_property(prop)?.map(|v| v.to_string()))
+    }
+
+    /// Capture all the known properties which are set.
+    fn snapshot(&self) -> Result<PropertySnapshot> {
+        let mut values = BTreeMap::new();
+        for prop in Property::all() {
+            let value = self.get_by_id(prop.id())?;
+            if value.is_empty() {
+                continue;
+            }
+            let value = if prop.is_secret() {
+                redact(&value)
+            } else {
+                value
+            };
+            values.insert(prop.name(), value);
+        }
+        Ok(PropertySnapshot { values })
+    }
+}
+
+impl<T: PropertyBag> TypedProperties for T {}
+
+/// Values of the properties at some point, secrets are redacted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PropertySnapshot {
+    pub values: BTreeMap<String, String>,
+}
+
+impl PropertySnapshot {
+    pub fn get(&self, prop: Property) -> Option<&str> {
+        self.values.get(&prop.name()).map(String::as_str)
+    }
+
+    /// Changes from this snapshot to the other one.
+    pub fn diff(&self, other: &PropertySnapshot) -> Vec<PropertyChange> {
+        let mut names: Vec<&String> =
+            self.values.keys().chain(other.values.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let before = self.values.get(name);
+                let after = other.values.get(name);
+                if before == after {
+                    return None;
+                }
+                Some(PropertyChange {
+                    name: name.clone(),
+                    before: before.cloned(),
+                    after: after.cloned(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for PropertySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.values {
+            writeln!(f, "{} = {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Changed property between snapshots, `None` if not set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyChange {
+    pub name: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl fmt::Display for PropertyChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |v: &Option<String>| match v {
+            Some(v) => v.clone(),
+            None => String::from("(unset)"),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.name,
+            show(&self.before),
+            show(&self.after)
+        )
+    }
+}
+
+/// Offset basis of the 64-bit FNV-1a hash.
+pub(crate) const FNV_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a hash, stable across builds unlike the std hasher.
+pub(crate) fn fnv1a(data: &[u8], basis: u64) -> u64 {
+    data.iter().fold(basis, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Hide the secret but keep a fingerprint to tell the changes.
+fn redact(secret: &str) -> String {
+    let hash = fnv1a(secret.as_bytes(), FNV_BASIS);
+    format!("<redacted {:08x}>", hash as u32)
+}
+
+/// Rough check of BCP-47 language tag, e.g. "en-US" or "zh-Hans".
+pub fn is_language_tag(lang: &str) -> bool {
+    let mut parts = lang.split('-');
+    let primary = parts.next().unwrap_or_default();
+    primary.len() >= 2
+        && primary.len() <= 8
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            !part.is_empty()
+                && part.len() <= 8
+                && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+
+    #[derive(Default)]
+    struct Bag(RefCell<HashMap<PropertyId, String>>);
+
+    impl PropertyBag for Bag {
+        fn get_by_id(&self, id: PropertyId) -> Result<String> {
+            Ok(self.0.borrow().get(&id).cloned().unwrap_or_default())
+        }
+
+        fn put_by_id<T: ToString>(&self, id: PropertyId, value: T) -> Result {
+            self.0.borrow_mut().insert(id, value.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn property_from_str() {
+        let prop: Property = "proxy-host-name".parse().unwrap();
+        assert_eq!(prop, Property::ProxyHostName);
+        match "proxy_hostname".parse::<Property>() {
+            Err(InvalidProperty(name, message)) => {
+                assert_eq!(name, "proxy_hostname");
+                assert_eq!(message, "unknown property");
+            }
+            other => panic!("unexpected property: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typed_setters_check_the_kind() {
+        let bag = Bag::default();
+        bag.put_text(Property::ProxyHostName, "proxy").unwrap();
+        bag.put_number(Property::ProxyPort, 8080).unwrap();
+        bag.put_text(Property::ProxyPassword, " secret ").unwrap();
+        assert_eq!(bag.get_number(Property::ProxyPort).unwrap(), Some(8080));
+        assert_eq!(
+            bag.get_by_id(Property::ProxyPassword.id()).unwrap(),
+            " secret "
+        );
+        assert!(bag.put_text(Property::ProxyPort, "8080").is_err());
+        assert!(bag
+            .put_property_str(Property::Endpoint, "example.com")
+            .is_err());
+        assert_eq!(bag.get_text(Property::Endpoint).unwrap(), None);
+    }
+
+    #[test]
+    fn redact_is_stable() {
+        assert_eq!(redact("secret"), "<redacted c020c951>");
+        let bag = Bag::default();
+        bag.put_text(Property::ProxyPassword, "secret").unwrap();
+        bag.put_text(Property::ProxyUserName, "user").unwrap();
+        let snapshot = bag.snapshot().unwrap();
+        assert_eq!(snapshot.values["proxy_password"], "<redacted c020c951>");
+        assert_eq!(snapshot.values["proxy_user_name"], "user");
+    }
+
+    #[test]
+    fn profanity_from_str() {
+        for profanity in
+            &[Profanity::Masked, Profanity::Removed, Profanity::Raw]
+        {
+            assert_eq!(
+                profanity.as_str().parse::<Profanity>().unwrap(),
+                *profanity
+            );
+        }
+        assert_eq!(" raw ".parse::<Profanity>().unwrap(), Profanity::Raw);
+        match "hidden".parse::<Profanity>() {
+            Err(InvalidProperty(name, value)) => {
+                assert_eq!(name, Property::ProfanityOption.name());
+                assert_eq!(value, "hidden");
+            }
+            other => panic!("unexpected profanity: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn result_format_from_str() {
+        for format in &[ResultFormat::Simple, ResultFormat::Detailed] {
+            assert_eq!(
+                format.as_str().parse::<ResultFormat>().unwrap(),
+                *format
+            );
+        }
+        assert_eq!(
+            "DETAILED".parse::<ResultFormat>().unwrap(),
+            ResultFormat::Detailed
+        );
+        match "verbose".parse::<ResultFormat>() {
+            Err(InvalidProperty(name, value)) => {
+                assert_eq!(name, Property::OutputFormatOption.name());
+                assert_eq!(value, "verbose");
+            }
+            other => panic!("unexpected format: {:?}", other),
+        }
     }
 }
@@ -1,21 +1,26 @@
 //! Represents specific audio configuration, such as microphone, file, or custom audio streams.
 //!
 
+use super::recorder::Recorder;
 use crate::speech_api::*;
 use crate::{
     error, hr, properties::Properties, DeriveHandle, FlattenProps, Handle,
-    Result, SmartHandle, INVALID_HANDLE, NULL_HANDLE,
+    Result, SmartHandle, SpxError, INVALID_HANDLE, NULL_HANDLE,
 };
+use failure::Fail;
+use futures::{task::AtomicTask, Async, Poll, Stream};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::VecDeque,
     ffi::CString,
-    io::{Cursor, Read, Write},
+    io::{self, Read, Write},
     os::raw::{c_char, c_int, c_void},
     slice::{from_raw_parts, from_raw_parts_mut},
     sync::{
         mpsc::{channel, Receiver, Sender, TryRecvError},
-        Arc, Weak,
+        Arc, Condvar, Mutex, Weak,
     },
+    time::{Duration, Instant},
 };
 
 /// Creates an audio stream format object with the specified PCM waveformat characteristics.
@@ -37,6 +42,29 @@ impl Default for AudioSpec {
     }
 }
 
+impl AudioSpec {
+    /// Size of one second audio.
+    pub fn bytes_per_second(&self) -> u64 {
+        u64::from(self.rate)
+            * u64::from(self.channels)
+            * u64::from(self.bits / 8)
+    }
+
+    /// Duration of the audio in bytes.
+    pub fn to_duration(&self, bytes: u64) -> Duration {
+        let bps = self.bytes_per_second().max(1);
+        Duration::from_secs(bytes / bps)
+            + Duration::from_nanos((bytes % bps) * 1_000_000_000 / bps)
+    }
+
+    /// Size of the audio in the duration.
+    pub fn to_bytes(&self, duration: Duration) -> u64 {
+        let bps = self.bytes_per_second();
+        duration.as_secs() * bps
+            + u64::from(duration.subsec_nanos()) * bps / 1_000_000_000
+    }
+}
+
 impl From<(u32, u8, u8)> for AudioSpec {
     fn from(trio: (u32, u8, u8)) -> Self {
         AudioSpec {
@@ -201,6 +229,33 @@ impl AudioStream for Audio {
         }
     }
 
+    /// Output audio data via created stream with timeout.
+    fn read_timeout(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        if let Some(stream) = &mut self.stream {
+            stream.read_timeout(buffer, timeout)
+        } else {
+            Err(error::IsNothing)
+        }
+    }
+
+    /// Reader of the created output stream.
+    fn reader(&self) -> Option<OutputReader> {
+        self.stream.as_ref().and_then(|stream| stream.reader())
+    }
+
+    /// Record the audio read from the created stream.
+    fn set_recorder(&mut self, recorder: Recorder) -> Result {
+        if let Some(stream) = &mut self.stream {
+            stream.set_recorder(recorder)
+        } else {
+            Err(error::Unimplemented)
+        }
+    }
+
     /// Close stream and release resource.
     fn close(&mut self) -> Result {
         if let Some(stream) = &mut self.stream {
@@ -222,86 +277,31 @@ pub trait AudioStream: Handle {
         Err(error::Unimplemented)
     }
 
+    /// Read audio data, wait at most timeout if nothing is available.
+    fn read_timeout(
+        &mut self,
+        _buffer: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        Err(error::Unimplemented)
+    }
+
+    /// Shared reader for blocking or asynchronous output.
+    fn reader(&self) -> Option<OutputReader> {
+        None
+    }
+
+    /// Record the audio when it is read by the SDK, for pull streams.
+    fn set_recorder(&mut self, _recorder: Recorder) -> Result {
+        Err(error::Unimplemented)
+    }
+
     /// Close the stream gracefully.
     fn close(&mut self) -> Result {
         Ok(())
     }
 }
 
-// pub struct StreamReader {
-//     receiver: Receiver<Vec<u8>>,
-//     buffer: Option<Cursor<Vec<u8>>>,
-//     closing: bool,
-// }
-
-// impl StreamReader {
-//     pub fn new(receiver: Receiver<Vec<u8>>) -> Self {
-//         StreamReader {
-//             receiver,
-//             buffer: None,
-//             closing: false,
-//         }
-//     }
-// }
-
-// impl Read for StreamReader {
-//     fn read(&mut self, buffer: &mut [u8]) -> IoResult<usize> {
-//         if self.closing {
-//             return Ok(0);
-//         }
-
-//         let buf_size = buffer.len();
-//         let mut read_size = 0;
-//         if let Some(cache) = &mut self.buffer {
-//             read_size = cache.read(buffer)?;
-//             if read_size < buf_size {
-//                 self.buffer = None;
-//             } else {
-//                 return Ok(read_size);
-//             }
-//         }
-
-//         while buf_size > read_size {
-//             match self.receiver.try_recv() {
-//                 Ok(data) => {
-//                     let data_size = data.len();
-//                     if data_size == 0 {
-//                         self.closing = true;
-//                         break;
-//                     }
-//                     let mut cache = Cursor::new(data);
-//                     let sz = cache.read(&mut buffer[read_size..])?;
-//                     read_size = read_size + sz;
-//                     if sz < data_size {
-//                         self.buffer = Some(cache);
-//                         break;
-//                     }
-//                 }
-//                 Err(TryRecvError::Empty) => (),
-//                 Err(TryRecvError::Disconnected) => {
-//                     return Err(IoError::from(ErrorKind::ConnectionAborted))
-//                 }
-//             }
-//         }
-
-//         if read_size > 0 {
-//             return Ok(read_size);
-//         }
-
-//         let cache = self
-//             .receiver
-//             .recv()
-//             .map_err(|err| IoError::new(ErrorKind::BrokenPipe, err))?;
-//         if cache.len() == 0 {
-//             self.closing = true;
-//             return Ok(0);
-//         } else {
-//             self.buffer = Some(Cursor::new(cache));
-//             return self.read(buffer);
-//         }
-//     }
-// }
-
 DeriveHandle!(
     PullAudioInputStream,
     SPXAUDIOSTREAMHANDLE,
@@ -313,7 +313,26 @@ DeriveHandle!(
 pub struct PullAudioInputStream {
     handle: SPXAUDIOSTREAMHANDLE,
     writer: Sender<Vec<u8>>,
-    _reader: Arc<Receiver<Vec<u8>>>,
+    source: Arc<PullSource>,
+}
+
+/// Audio queued for the read callback, and the recording tap of the audio
+/// read.
+struct PullSource {
+    reader: Receiver<Vec<u8>>,
+    recorder: Mutex<Option<Recorder>>,
+}
+
+impl PullSource {
+    fn record(&self, data: &[u8]) {
+        if let Ok(recorder) = self.recorder.lock() {
+            if let Some(Err(err)) =
+                recorder.as_ref().map(|r| r.write_audio(data))
+            {
+                log::error!("failed to record audio: {}", err);
+            }
+        }
+    }
 }
 
 impl PullAudioInputStream {
@@ -326,8 +345,11 @@ impl PullAudioInputStream {
             af.handle()
         ))?;
         let (writer, reader) = channel();
-        let _reader = Arc::new(reader);
-        let r = Box::new(Arc::downgrade(&_reader));
+        let source = Arc::new(PullSource {
+            reader,
+            recorder: Mutex::new(None),
+        });
+        let r = Box::new(Arc::downgrade(&source));
         let context = Box::into_raw(r) as *mut c_void;
         hr!(pull_audio_input_stream_set_callbacks(
             hstream,
@@ -338,7 +360,7 @@ impl PullAudioInputStream {
         Ok(PullAudioInputStream {
             handle: hstream,
             writer,
-            _reader,
+            source,
         })
     }
 }
@@ -353,13 +375,20 @@ impl AudioStream for PullAudioInputStream {
     fn close(&mut self) -> Result {
         self.write(&mut [])
     }
+
+    /// Record the audio when it is read by the SDK.
+    fn set_recorder(&mut self, recorder: Recorder) -> Result {
+        *self.source.recorder.lock().map_err(|_| error::Poisoned)? =
+            Some(recorder);
+        Ok(())
+    }
 }
 
 unsafe extern "C" fn on_stream_close(context: *mut c_void) {
     log::debug!("Pull stream close event fired.");
     if !context.is_null() {
         // Auto release the Box and weak pointer.
-        Box::from_raw(context as *mut Weak<Receiver<Vec<u8>>>);
+        Box::from_raw(context as *mut Weak<PullSource>);
     }
 }
 
@@ -372,9 +401,10 @@ unsafe extern "C" fn on_stream_read(
         log::error!("Unknown context with NULL pointer when read stream.");
         return 0;
     }
-    let ctx = Box::from_raw(context as *mut Weak<Receiver<Vec<u8>>>);
+    let ctx = Box::from_raw(context as *mut Weak<PullSource>);
     let ctx = Box::leak(ctx); // avoid auto release.
-    if let Some(r) = ctx.upgrade() {
+    if let Some(source) = ctx.upgrade() {
+        let r = &source.reader;
         let mut buf = from_raw_parts_mut(buffer, size as usize);
         let data = match r.try_recv() {
             Ok(data) => data,
@@ -401,6 +431,7 @@ unsafe extern "C" fn on_stream_read(
             );
             0
         } else {
+            source.record(&data);
             buf.write(&data).unwrap()
         } as c_int;
     }
@@ -451,9 +482,7 @@ DeriveHandle!(
 /// Push output stream.
 pub struct PushAudioOutputStream {
     handle: SPXAUDIOSTREAMHANDLE,
-    _writer: Arc<Sender<Vec<u8>>>,
-    reader: Receiver<Vec<u8>>,
-    buffer: Option<Cursor<Vec<u8>>>,
+    reader: OutputReader,
 }
 
 impl PushAudioOutputStream {
@@ -465,9 +494,8 @@ impl PushAudioOutputStream {
             &mut hstream,
             af.handle()
         ))?;
-        let (writer, reader) = channel();
-        let _writer = Arc::new(writer);
-        let w = Box::new(Arc::downgrade(&_writer));
+        let reader = OutputReader::default();
+        let w = Box::new(Arc::downgrade(&reader.shared));
         let context = Box::into_raw(w) as *mut c_void;
         hr!(push_audio_output_stream_set_callbacks(
             hstream,
@@ -477,48 +505,173 @@ impl PushAudioOutputStream {
         ))?;
         Ok(PushAudioOutputStream {
             handle: hstream,
-            _writer,
             reader,
-            buffer: None,
         })
     }
 }
 
 impl AudioStream for PushAudioOutputStream {
     fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        let buf_size = buffer.len();
-        let mut read_size = 0;
-        if let Some(cache) = &mut self.buffer {
-            read_size = cache.read(buffer)?;
-            if read_size < buf_size {
-                self.buffer = None;
-            } else {
-                return Ok(read_size);
-            }
+        self.reader.read_blocking(buffer)
+    }
+
+    fn read_timeout(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        self.reader.read_timeout(buffer, timeout)
+    }
+
+    fn reader(&self) -> Option<OutputReader> {
+        Some(self.reader.clone())
+    }
+}
+
+/// Audio data written by the SDK and not yet consumed.
+#[derive(Default)]
+struct OutputBuffer {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+/// State shared between the output stream callbacks and the readers.
+#[derive(Default)]
+struct OutputShared {
+    buffer: Mutex<OutputBuffer>,
+    ready: Condvar,
+    task: AtomicTask,
+}
+
+impl OutputShared {
+    fn push(&self, data: &[u8]) -> Result {
+        let mut buffer = self.buffer.lock().map_err(|_| error::Poisoned)?;
+        buffer.data.extend(data);
+        self.ready.notify_all();
+        self.task.notify();
+        Ok(())
+    }
+
+    fn close(&self) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.closed = true;
         }
-        while buf_size > read_size {
-            match self.reader.try_recv() {
-                Ok(data) => {
-                    let data_size = data.len();
-                    if data_size == 0 {
-                        break;
-                    }
-                    let mut cache = Cursor::new(data);
-                    let sz = cache.read(&mut buffer[read_size..])?;
-                    read_size = read_size + sz;
-                    if sz < data_size {
-                        self.buffer = Some(cache);
-                        break;
-                    }
-                }
-                Err(TryRecvError::Empty) => (),
-                Err(TryRecvError::Disconnected) => {
-                    return Err(error::Other("disconnected".to_string()));
-                }
+        self.ready.notify_all();
+        self.task.notify();
+    }
+}
+
+/// Reader of push output stream. None of the read methods spin: blocking
+/// reads park on a condition variable and asynchronous reads are woken by the
+/// write callback of the stream. Read size 0 means end of the stream.
+#[derive(Clone, Default)]
+pub struct OutputReader {
+    shared: Arc<OutputShared>,
+}
+
+impl OutputReader {
+    /// Move buffered data to the output buffer.
+    fn drain(buffer: &mut OutputBuffer, out: &mut [u8]) -> usize {
+        let sz = out.len().min(buffer.data.len());
+        for (dst, src) in out.iter_mut().zip(buffer.data.drain(..sz)) {
+            *dst = src;
+        }
+        sz
+    }
+
+    /// Block until some data is available or the stream is closed.
+    pub fn read_blocking(&self, out: &mut [u8]) -> Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        let mut buffer =
+            self.shared.buffer.lock().map_err(|_| error::Poisoned)?;
+        while buffer.data.is_empty() && !buffer.closed {
+            buffer = self
+                .shared
+                .ready
+                .wait(buffer)
+                .map_err(|_| error::Poisoned)?;
+        }
+        Ok(Self::drain(&mut buffer, out))
+    }
+
+    /// Block at most timeout for data, return WouldBlock if nothing arrived.
+    pub fn read_timeout(
+        &self,
+        out: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        let deadline = Instant::now() + timeout;
+        let mut buffer =
+            self.shared.buffer.lock().map_err(|_| error::Poisoned)?;
+        while buffer.data.is_empty() && !buffer.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(error::WouldBlock);
             }
+            buffer = self
+                .shared
+                .ready
+                .wait_timeout(buffer, deadline - now)
+                .map_err(|_| error::Poisoned)?
+                .0;
         }
+        Ok(Self::drain(&mut buffer, out))
+    }
 
-        Ok(read_size)
+    /// Non-blocking read for futures. Current task is notified by the next
+    /// write or close of the stream. Only one task could wait at a time.
+    pub fn poll_read(&self, out: &mut [u8]) -> Poll<usize, SpxError> {
+        if out.is_empty() {
+            return Ok(Async::Ready(0));
+        }
+        self.shared.task.register();
+        let mut buffer =
+            self.shared.buffer.lock().map_err(|_| error::Poisoned)?;
+        if buffer.data.is_empty() && !buffer.closed {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(Self::drain(&mut buffer, out)))
+    }
+
+    /// Check if the stream is closed and all data is consumed.
+    pub fn is_finished(&self) -> bool {
+        self.shared
+            .buffer
+            .lock()
+            .map(|buffer| buffer.closed && buffer.data.is_empty())
+            .unwrap_or(true)
+    }
+}
+
+impl Read for OutputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_blocking(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.compat()))
+    }
+}
+
+/// Stream of audio chunks, all available data is yielded at once.
+impl Stream for OutputReader {
+    type Item = Vec<u8>;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, SpxError> {
+        self.shared.task.register();
+        let mut buffer =
+            self.shared.buffer.lock().map_err(|_| error::Poisoned)?;
+        if !buffer.data.is_empty() {
+            let chunk = buffer.data.drain(..).collect();
+            Ok(Async::Ready(Some(chunk)))
+        } else if buffer.closed {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
     }
 }
 
@@ -526,7 +679,10 @@ unsafe extern "C" fn on_output_stream_close(context: *mut c_void) {
     log::debug!("Output stream close event fired.");
     if !context.is_null() {
         // Auto release the Box and weak pointer.
-        Box::from_raw(context as *mut Weak<Sender<Vec<u8>>>);
+        let ctx = Box::from_raw(context as *mut Weak<OutputShared>);
+        if let Some(shared) = ctx.upgrade() {
+            shared.close();
+        }
     }
 }
 
@@ -539,13 +695,13 @@ unsafe extern "C" fn on_stream_write(
         log::error!("Unknown context with NULL pointer when write stream.");
         return 0;
     }
-    let ctx = Box::from_raw(context as *mut Weak<Sender<Vec<u8>>>);
+    let ctx = Box::from_raw(context as *mut Weak<OutputShared>);
     let ctx = Box::leak(ctx); // avoid auto release.
-    if let Some(s) = ctx.upgrade() {
+    if let Some(shared) = ctx.upgrade() {
         let buf = from_raw_parts(buffer, size as usize);
-        match s.send(buf.to_owned()) {
+        match shared.push(buf) {
             Ok(()) => return size as c_int,
-            Err(err) => log::error!("Audio input stream read error: {}", err),
+            Err(err) => log::error!("Audio output stream write error: {}", err),
         }
     }
     log::error!("Cannot get stream reader!");
@@ -638,3 +794,152 @@ impl AudioStreamFormat {
         AudioStreamFormat { handle }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::{spawn, Notify, NotifyHandle};
+    use std::{sync::atomic::AtomicUsize, sync::atomic::Ordering, thread};
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Notify for Counter {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn audio_spec_converts_sizes() {
+        let spec = AudioSpec::default();
+        assert_eq!(spec.bytes_per_second(), 32_000);
+        assert_eq!(spec.to_duration(32_000), Duration::from_secs(1));
+        assert_eq!(spec.to_duration(48_016), Duration::from_micros(1_500_500));
+        assert_eq!(spec.to_bytes(Duration::from_millis(1_500)), 48_000);
+        assert_eq!(spec.to_bytes(Duration::from_micros(1_500_500)), 48_016);
+        let stereo = AudioSpec::from((8_000, 8, 2));
+        assert_eq!(stereo.to_bytes(Duration::from_millis(250)), 4_000);
+        assert_eq!(stereo.to_duration(4_000), Duration::from_millis(250));
+        let empty = AudioSpec::from((0, 16, 1));
+        assert_eq!(empty.to_duration(100), Duration::from_secs(100));
+        assert_eq!(empty.to_bytes(Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn read_timeout_expires_without_data() {
+        let reader = OutputReader::default();
+        let mut buf = [0; 4];
+        let start = Instant::now();
+        match reader.read_timeout(&mut buf, Duration::from_millis(20)) {
+            Err(SpxError::WouldBlock) => {}
+            other => panic!("unexpected read: {:?}", other),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn read_timeout_returns_data_in_time() {
+        let reader = OutputReader::default();
+        let shared = reader.shared.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            shared.push(b"ab").unwrap();
+        });
+        let mut buf = [0; 4];
+        let n = reader
+            .read_timeout(&mut buf, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(&buf[..n], b"ab");
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn read_blocking_reaches_eof_after_close() {
+        let mut reader = OutputReader::default();
+        reader.shared.push(b"abc").unwrap();
+        reader.shared.close();
+        assert!(!reader.is_finished());
+
+        let mut buf = [0; 2];
+        assert_eq!(reader.read_blocking(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"ab");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"c");
+        assert_eq!(reader.read_blocking(&mut buf).unwrap(), 0);
+        assert!(reader.is_finished());
+    }
+
+    #[test]
+    fn read_blocking_wakes_on_close() {
+        let reader = OutputReader::default();
+        let shared = reader.shared.clone();
+        let closer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            shared.close();
+        });
+        let mut buf = [0; 4];
+        assert_eq!(reader.read_blocking(&mut buf).unwrap(), 0);
+        closer.join().unwrap();
+    }
+
+    #[test]
+    fn pending_poll_is_woken_by_write_and_close() {
+        let reader = OutputReader::default();
+        let shared = reader.shared.clone();
+        let counter = Arc::new(Counter::default());
+        let handle = NotifyHandle::from(counter.clone());
+        let mut stream = spawn(reader);
+
+        match stream.poll_stream_notify(&handle, 0) {
+            Ok(Async::NotReady) => {}
+            other => panic!("unexpected poll: {:?}", other),
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        shared.push(b"xyz").unwrap();
+        assert!(counter.0.load(Ordering::SeqCst) >= 1);
+        match stream.poll_stream_notify(&handle, 0) {
+            Ok(Async::Ready(Some(chunk))) => assert_eq!(chunk, b"xyz"),
+            other => panic!("unexpected poll: {:?}", other),
+        }
+
+        match stream.poll_stream_notify(&handle, 0) {
+            Ok(Async::NotReady) => {}
+            other => panic!("unexpected poll: {:?}", other),
+        }
+        let notified = counter.0.load(Ordering::SeqCst);
+        shared.close();
+        assert!(counter.0.load(Ordering::SeqCst) > notified);
+        match stream.poll_stream_notify(&handle, 0) {
+            Ok(Async::Ready(None)) => {}
+            other => panic!("unexpected poll: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn poll_read_is_woken_by_write() {
+        let reader = OutputReader::default();
+        let shared = reader.shared.clone();
+        let counter = Arc::new(Counter::default());
+        let handle = NotifyHandle::from(counter.clone());
+        let mut buf = [0; 8];
+        let mut task = spawn(futures::future::poll_fn(move || {
+            reader
+                .poll_read(&mut buf)
+                .map(|ready| ready.map(|n| buf[..n].to_vec()))
+        }));
+
+        match task.poll_future_notify(&handle, 0) {
+            Ok(Async::NotReady) => {}
+            other => panic!("unexpected poll: {:?}", other),
+        }
+        shared.push(b"hi").unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        match task.poll_future_notify(&handle, 0) {
+            Ok(Async::Ready(data)) => assert_eq!(data, b"hi"),
+            other => panic!("unexpected poll: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Authorization token providers and the refresher of live token users.
+//!
+//! Tokens issued by the service expire in ten minutes. The refresher fetches
+//! a new token before the expiry and puts it into the configuration and every
+//! attached recognizer or synthesizer which is still alive.
+
+use crate::{
+    error::{Other, Poisoned},
+    properties::{Properties, Property, PropertyBag},
+    Result,
+};
+use std::{
+    sync::{Arc, Condvar, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Lifetime of the token issued by the service.
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(600);
+
+/// Authorization token with its lifetime.
+#[derive(Debug, Clone)]
+pub struct AuthToken {
+    pub token: String,
+    pub expires_in: Duration,
+}
+
+impl AuthToken {
+    /// Token with the lifetime of the service.
+    pub fn new<T: Into<String>>(token: T) -> Self {
+        AuthToken {
+            token: token.into(),
+            expires_in: TOKEN_LIFETIME,
+        }
+    }
+}
+
+/// Source of authorization tokens.
+pub trait TokenProvider: Send + Sync {
+    fn fetch(&self) -> Result<AuthToken>;
+}
+
+/// Caller supplied provider.
+impl<F> TokenProvider for F
+where
+    F: Fn() -> Result<AuthToken> + Send + Sync,
+{
+    fn fetch(&self) -> Result<AuthToken> {
+        self()
+    }
+}
+
+/// Issue tokens from subscription key by the token service.
+pub struct StsTokenProvider {
+    endpoint: String,
+    subscription: String,
+    client: reqwest::Client,
+}
+
+impl StsTokenProvider {
+    /// Token service of the region.
+    pub fn from_subscription(subscription: &str, region: &str) -> Self {
+        let endpoint = format!(
+            "https://{}.api.cognitive.microsoft.com/sts/v1.0/issueToken",
+            region
+        );
+        StsTokenProvider::from_endpoint(&endpoint, subscription)
+    }
+
+    /// Custom token service, e.g. for private endpoints.
+    pub fn from_endpoint(endpoint: &str, subscription: &str) -> Self {
+        StsTokenProvider {
+            endpoint: endpoint.to_string(),
+            subscription: subscription.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+impl TokenProvider for StsTokenProvider {
+    fn fetch(&self) -> Result<AuthToken> {
+        let mut resp = self
+            .client
+            .post(&self.endpoint)
+            .header("Ocp-Apim-Subscription-Key", self.subscription.as_str())
+            .header("Content-Length", "0")
+            .send()?;
+        let status = resp.status();
+        let body = resp.text()?;
+        if !status.is_success() {
+            return Err(Other(format!(
+                "token service returned {}: {}",
+                status, body
+            )));
+        }
+        Ok(AuthToken::new(body.trim()))
+    }
+}
+
+/// Owner of the property bag which receives refreshed tokens.
+pub trait TokenTarget {
+    fn token_props(&self) -> Weak<Properties>;
+}
+
+/// Keep the token of attached targets up to date in a background thread.
+/// Clones share the same token and targets, the thread is stopped when the
+/// last clone is dropped.
+#[derive(Clone)]
+pub struct TokenRefresher {
+    inner: Arc<Refresher>,
+}
+
+impl TokenRefresher {
+    /// Fetch the first token then start refreshing it the margin before
+    /// expiry.
+    pub fn start<P>(provider: P, margin: Duration) -> Result<Self>
+    where
+        P: TokenProvider + 'static,
+    {
+        let token = provider.fetch()?;
+        let state = State {
+            expiry: Instant::now() + token.expires_in,
+            token: token.token,
+            targets: Vec::new(),
+            stopped: false,
+            forced: false,
+        };
+        let shared = Arc::new(Shared {
+            provider: Box::new(provider),
+            margin,
+            state: Mutex::new(state),
+            wake: Condvar::new(),
+            push: Mutex::new(()),
+        });
+        {
+            let shared = shared.clone();
+            thread::spawn(move || shared.run());
+        }
+        Ok(TokenRefresher {
+            inner: Arc::new(Refresher { shared }),
+        })
+    }
+
+    /// Current token.
+    pub fn token(&self) -> Result<String> {
+        let state = self.inner.shared.state.lock().map_err(|_| Poisoned)?;
+        Ok(state.token.clone())
+    }
+
+    /// Put current token to the target and keep it refreshed while alive.
+    pub fn attach<T: TokenTarget>(&self, target: &T) -> Result {
+        let shared = &self.inner.shared;
+        let props = target.token_props();
+        let _push = shared.push.lock().map_err(|_| Poisoned)?;
+        let token = {
+            let mut state = shared.state.lock().map_err(|_| Poisoned)?;
+            state.targets.retain(|t| t.upgrade().is_some());
+            state.targets.push(props.clone());
+            state.token.clone()
+        };
+        if let Some(props) = props.upgrade() {
+            props.put_by_id(Property::AuthorizationToken.id(), token)?;
+        }
+        Ok(())
+    }
+
+    /// Fetch a new token now.
+    pub fn refresh(&self) -> Result {
+        let token = self.inner.shared.provider.fetch()?;
+        self.inner.shared.update(token)
+    }
+
+    /// Wake the background thread to refresh as soon as possible.
+    pub fn refresh_later(&self) -> Result {
+        let mut state = self.inner.shared.state.lock().map_err(|_| Poisoned)?;
+        state.forced = true;
+        self.inner.shared.wake.notify_all();
+        Ok(())
+    }
+}
+
+/// Handle of the background thread.
+struct Refresher {
+    shared: Arc<Shared>,
+}
+
+/// The thread is detached rather than joined, so dropping never waits for
+/// a fetch in flight. The thread exits once it sees the stop, and never
+/// puts the token fetched after the stop.
+impl Drop for Refresher {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.stopped = true;
+        }
+        self.shared.wake.notify_all();
+    }
+}
+
+struct State {
+    token: String,
+    expiry: Instant,
+    targets: Vec<Weak<Properties>>,
+    stopped: bool,
+    forced: bool,
+}
+
+struct Shared {
+    provider: Box<dyn TokenProvider>,
+    margin: Duration,
+    state: Mutex<State>,
+    wake: Condvar,
+    /// Serialize putting tokens to the targets, outside of the state lock.
+    push: Mutex<()>,
+}
+
+impl Shared {
+    /// Replace the token and push it to the live targets.
+    fn update(&self, token: AuthToken) -> Result {
+        let _push = self.push.lock().map_err(|_| Poisoned)?;
+        let targets: Vec<_> = {
+            let mut state = self.state.lock().map_err(|_| Poisoned)?;
+            if state.stopped {
+                return Ok(());
+            }
+            state.expiry = Instant::now() + token.expires_in;
+            state.token = token.token.clone();
+            state.targets.retain(|t| t.upgrade().is_some());
+            state.targets.iter().filter_map(|t| t.upgrade()).collect()
+        };
+        for props in &targets {
+            props.put_by_id(Property::AuthorizationToken.id(), &token.token)?;
+        }
+        log::debug!("token is refreshed for {} targets", targets.len());
+        Ok(())
+    }
+
+    fn run(&self) {
+        let mut retry = Duration::from_secs(1);
+        loop {
+            if !self.wait() {
+                return;
+            }
+            let result = self.provider.fetch().and_then(|t| self.update(t));
+            match result {
+                Ok(()) => retry = Duration::from_secs(1),
+                Err(err) => {
+                    log::warn!("failed to refresh token: {}", err);
+                    if !self.sleep(retry) {
+                        return;
+                    }
+                    retry = (retry * 2).min(Duration::from_secs(30));
+                    if let Ok(mut state) = self.state.lock() {
+                        state.forced = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Wait until the refresh is due, false if stopped.
+    fn wait(&self) -> bool {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        loop {
+            if state.stopped {
+                return false;
+            }
+            if state.forced {
+                state.forced = false;
+                return true;
+            }
+            let due = state.expiry.checked_sub(self.margin);
+            let now = Instant::now();
+            let timeout = match due {
+                Some(due) if due > now => due - now,
+                _ => return true,
+            };
+            state = match self.wake.wait_timeout(state, timeout) {
+                Ok((state, _)) => state,
+                Err(_) => return false,
+            };
+        }
+    }
+
+    /// Sleep before retry, false if stopped.
+    fn sleep(&self, delay: Duration) -> bool {
+        let begin = Instant::now();
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        while !state.stopped && begin.elapsed() < delay {
+            let rest = delay - begin.elapsed();
+            state = match self.wake.wait_timeout(state, rest) {
+                Ok((state, _)) => state,
+                Err(_) => return false,
+            };
+        }
+        !state.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread::JoinHandle,
+    };
+
+    /// Stand-in token service answering each request with the next
+    /// response, returns its URL and the received request heads.
+    fn serve(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/sts/v1.0/issueToken",
+            listener.local_addr().unwrap()
+        );
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = conn.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..n]);
+                }
+                requests.push(String::from_utf8_lossy(&head).into_owned());
+                write!(
+                    conn,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn sts_provider_issues_token() {
+        let (url, server) = serve(vec![(200, "token-1\n")]);
+        let provider = StsTokenProvider::from_endpoint(&url, "key-1");
+        let token = provider.fetch().unwrap();
+        assert_eq!(token.token, "token-1");
+        assert_eq!(token.expires_in, TOKEN_LIFETIME);
+
+        let requests = server.join().unwrap();
+        let head = requests[0].to_lowercase();
+        assert!(head.starts_with("post /sts/v1.0/issuetoken "));
+        assert!(head.contains("ocp-apim-subscription-key: key-1\r\n"));
+    }
+
+    #[test]
+    fn sts_provider_reports_service_error() {
+        let (url, server) = serve(vec![(401, "access denied")]);
+        let provider = StsTokenProvider::from_endpoint(&url, "bad");
+        match provider.fetch() {
+            Err(Other(msg)) => {
+                assert!(msg.contains("401"), "{}", msg);
+                assert!(msg.contains("access denied"), "{}", msg);
+            }
+            other => panic!("unexpected token: {:?}", other.map(|t| t.token)),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn refresher_fetches_on_demand() {
+        let (url, server) = serve(vec![(200, "first"), (200, "second")]);
+        let provider = StsTokenProvider::from_endpoint(&url, "key");
+        let refresher =
+            TokenRefresher::start(provider, Duration::from_secs(60)).unwrap();
+        assert_eq!(refresher.token().unwrap(), "first");
+
+        refresher.refresh_later().unwrap();
+        let begin = Instant::now();
+        while refresher.token().unwrap() == "first" {
+            assert!(begin.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(refresher.token().unwrap(), "second");
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refresher_retries_failed_fetch() {
+        let (url, server) =
+            serve(vec![(200, "first"), (503, "busy"), (200, "third")]);
+        let provider = StsTokenProvider::from_endpoint(&url, "key");
+        let refresher =
+            TokenRefresher::start(provider, Duration::from_secs(60)).unwrap();
+        refresher.refresh_later().unwrap();
+        let begin = Instant::now();
+        while refresher.token().unwrap() != "third" {
+            assert!(begin.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn drop_does_not_wait_for_fetch() {
+        let (entered, fetching) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let calls = Mutex::new(0);
+        let provider = move || {
+            let mut calls = calls.lock().unwrap();
+            *calls += 1;
+            if *calls > 1 {
+                entered.send(()).unwrap();
+                blocked.lock().unwrap().recv().ok();
+                return Ok(AuthToken::new("late"));
+            }
+            Ok(AuthToken::new("first"))
+        };
+        let refresher =
+            TokenRefresher::start(provider, Duration::from_secs(60)).unwrap();
+        let shared = refresher.inner.shared.clone();
+        refresher.refresh_later().unwrap();
+        fetching.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let begin = Instant::now();
+        drop(refresher);
+        assert!(begin.elapsed() < Duration::from_secs(1));
+
+        // The token fetched after the stop is discarded.
+        release.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(shared.state.lock().unwrap().token, "first");
+    }
+}
@@ -2,18 +2,29 @@
 
 use super::{
     audio::{Audio, AudioSpec},
+    auth::{TokenRefresher, TokenTarget},
     events::Flags,
+    format::OutputFormat,
+    language::{
+        AutoDetectSourceLanguageConfig, LanguageIdMode, SourceLanguage,
+    },
+    lexicon::Lexicon,
     recognizer::{IntentTrigger, Model, Recognizer},
+    recorder::{Recorder, RecorderConfig},
     synthesizer::*,
+    vad::{VadConfig, VoiceDetector},
 };
 use crate::{
     hr,
-    properties::{Properties, PropertyBag},
+    properties::{Properties, Property, PropertyBag, TypedProperties},
     speech_api::*,
     DeriveHandle, FlattenProps, Handle, Result, INVALID_HANDLE,
 };
 use serde::{Deserialize, Serialize};
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    sync::{Arc, Weak},
+};
 
 /// Generate getter and setter for plain attribute with type conversition.
 macro_rules! DefineAttribute {
@@ -45,11 +56,11 @@ macro_rules! SimpleAttribute {
 macro_rules! DefineProperty {
     ($getter:ident, $setter:ident, $id:expr) => (
         pub fn $getter(&self) -> Result<String> {
-            self.get_by_id($id)
+            self.get_by_id($id.id())
         }
 
         pub fn $setter<T: ToString>(&mut self, v: T) -> Result<&mut Self> {
-            self.put_by_id($id, v)?;
+            self.put_property_str($id, &v.to_string())?;
             Ok(self)
         }
     )
@@ -70,10 +81,16 @@ pub struct RecognizerConfig {
     pull_mode: bool,
     model_id: String,
     intents: Vec<String>,
+    phrase_hints: Vec<String>,
     target_languages: Vec<String>,
     timeout: u32,
+    vad: Option<VadConfig>,
+    recorder: Option<RecorderConfig>,
+    refresher: Option<TokenRefresher>,
+    source_languages: Vec<SourceLanguage>,
+    language_id_mode: LanguageIdMode,
     handle: SPXSPEECHCONFIGHANDLE,
-    props: Properties,
+    props: Arc<Properties>,
 }
 
 impl RecognizerConfig {
@@ -82,15 +99,21 @@ impl RecognizerConfig {
         hr!(speech_config_get_property_bag(handle, &mut hprops))?;
         Ok(RecognizerConfig {
             handle,
-            props: Properties::new(hprops),
+            props: Arc::new(Properties::new(hprops)),
             flags: Flags::Recognized,
             audio_spec: None,
             audio_file_path: String::new(),
             pull_mode: false,
             model_id: String::new(),
             intents: Vec::new(),
+            phrase_hints: Vec::new(),
             target_languages: Vec::new(),
             timeout: UINT32_MAX,
+            vad: None,
+            recorder: None,
+            refresher: None,
+            source_languages: Vec::new(),
+            language_id_mode: LanguageIdMode::AtStart,
         })
     }
 
@@ -111,6 +134,7 @@ impl RecognizerConfig {
     /// Note: The caller needs to ensure that the authorization token is valid. Before the authorization token expires, the caller needs to refresh it by calling this setter with a new valid token.
     /// As configuration values are copied when creating a new recognizer, the new token value will not apply to recognizers that have already been created.
     /// For recognizers that have been created before, you need to set authorization token of the corresponding recognizer to refresh the token. Otherwise, the recognizers will encounter errors during recognition.
+    /// `set_token_refresher` does the refresh for the config and the recognizers generated from it.
     pub fn from_authorization_token(token: &str, region: &str) -> Result<Self> {
         let mut handle = INVALID_HANDLE;
         let token = CString::new(token)?;
@@ -145,17 +169,34 @@ impl RecognizerConfig {
     pub fn recognizer(&self) -> Result<Recognizer> {
         let audio = self.audio_input()?;
         let mut rh = INVALID_HANDLE;
-        hr!(recognizer_create_speech_recognizer_from_config(
-            &mut rh,
-            self.handle,
-            audio.handle(),
-        ))?;
-        Ok(Recognizer::new(
+        match self.auto_detect_config()? {
+            Some(lang) => {
+                hr!(recognizer_create_speech_recognizer_from_auto_detect_source_lang_config(
+                    &mut rh,
+                    self.handle,
+                    lang.handle(),
+                    audio.handle(),
+                ))?;
+            }
+            None => {
+                hr!(recognizer_create_speech_recognizer_from_config(
+                    &mut rh,
+                    self.handle,
+                    audio.handle(),
+                ))?;
+            }
+        }
+        let mut reco = Recognizer::new(
             rh,
             audio,
             self.flags | Flags::Speech,
             self.timeout,
-        ))
+        )?;
+        self.apply_phrase_hints(&reco)?;
+        self.apply_vad(&mut reco)?;
+        self.apply_recorder(&mut reco)?;
+        self.apply_refresher(&reco)?;
+        Ok(reco)
     }
 
     /// Generate a simple speech recognizer.
@@ -167,11 +208,16 @@ impl RecognizerConfig {
             self.handle,
             audio.handle(),
         ))?;
-        Synthesizer::new(sh, audio, self.flags | Flags::Synthesis)
+        let synth = Synthesizer::new(sh, audio, self.flags | Flags::Synthesis)?;
+        self.apply_refresher(&synth)?;
+        Ok(synth)
     }
 
     /// Generate a recognizer with speech and intent recognition.
     pub fn intent_recognizer(&self) -> Result<Recognizer> {
+        if !self.source_languages.is_empty() {
+            log::warn!("source language identification is ignored by intent recognizer");
+        }
         let audio = self.audio_input()?;
         let mut rh = INVALID_HANDLE;
         hr!(recognizer_create_intent_recognizer_from_config(
@@ -180,13 +226,17 @@ impl RecognizerConfig {
             audio.handle(),
         ))?;
 
-        let reco = Recognizer::new(
+        let mut reco = Recognizer::new(
             rh,
             audio,
             self.flags | Flags::Intent,
             self.timeout,
-        );
+        )?;
         self.apply_intents(&reco)?;
+        self.apply_phrase_hints(&reco)?;
+        self.apply_vad(&mut reco)?;
+        self.apply_recorder(&mut reco)?;
+        self.apply_refresher(&reco)?;
         Ok(reco)
     }
 
@@ -195,18 +245,34 @@ impl RecognizerConfig {
         self.apply_target_languages()?;
         let audio = self.audio_input()?;
         let mut rh = INVALID_HANDLE;
-        hr!(recognizer_create_translation_recognizer_from_config(
-            &mut rh,
-            self.handle,
-            audio.handle(),
-        ))?;
+        match self.auto_detect_config()? {
+            Some(lang) => {
+                hr!(recognizer_create_translation_recognizer_from_auto_detect_source_lang_config(
+                    &mut rh,
+                    self.handle,
+                    lang.handle(),
+                    audio.handle(),
+                ))?;
+            }
+            None => {
+                hr!(recognizer_create_translation_recognizer_from_config(
+                    &mut rh,
+                    self.handle,
+                    audio.handle(),
+                ))?;
+            }
+        }
 
-        let reco = Recognizer::new(
+        let mut reco = Recognizer::new(
             rh,
             audio,
             self.flags | Flags::Translation,
             self.timeout,
-        );
+        )?;
+        self.apply_phrase_hints(&reco)?;
+        self.apply_vad(&mut reco)?;
+        self.apply_recorder(&mut reco)?;
+        self.apply_refresher(&reco)?;
         Ok(reco)
     }
 
@@ -247,6 +313,117 @@ impl RecognizerConfig {
         self
     }
 
+    /// Client side voice activity detection of push mode input.
+    pub fn vad(&self) -> Option<VadConfig> {
+        self.vad
+    }
+
+    /// Enable voice activity detection of push mode input, events of
+    /// Flags::VoiceDetection are posted to the event stream.
+    pub fn set_vad<T: Into<Option<VadConfig>>>(&mut self, v: T) -> &mut Self {
+        self.vad = v.into();
+        self
+    }
+
+    /// Attach voice detector to the recognizer of push mode audio stream.
+    fn apply_vad(&self, reco: &mut Recognizer) -> Result {
+        match (self.vad, self.audio_spec) {
+            (Some(vad), Some(ref spec))
+                if !self.pull_mode && self.audio_file_path.is_empty() =>
+            {
+                reco.set_voice_detector(VoiceDetector::new(vad, spec)?);
+                Ok(())
+            }
+            (Some(_), _) => {
+                log::warn!("voice detection only works with push stream");
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Recording of the audio input and results per session.
+    pub fn recorder(&self) -> Option<&RecorderConfig> {
+        self.recorder.as_ref()
+    }
+
+    /// Record stream input audio and results of the generated recognizers.
+    pub fn set_recorder<T: Into<Option<RecorderConfig>>>(
+        &mut self,
+        v: T,
+    ) -> &mut Self {
+        self.recorder = v.into();
+        self
+    }
+
+    /// Attach recording tap to the recognizer of audio stream.
+    fn apply_recorder(&self, reco: &mut Recognizer) -> Result {
+        match (&self.recorder, self.audio_spec) {
+            (Some(cfg), Some(spec)) if self.audio_file_path.is_empty() => {
+                reco.set_recorder(Recorder::new(cfg.clone(), spec)?);
+                Ok(())
+            }
+            (Some(_), _) => {
+                log::warn!("recording only works with audio stream");
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep the authorization token of this configuration and the
+    /// recognizers or synthesizers generated from it refreshed.
+    pub fn set_token_refresher(
+        &mut self,
+        refresher: TokenRefresher,
+    ) -> Result<&mut Self> {
+        refresher.attach(self)?;
+        self.refresher = Some(refresher);
+        Ok(self)
+    }
+
+    fn apply_refresher<T: TokenTarget>(&self, target: &T) -> Result {
+        match self.refresher {
+            Some(ref refresher) => refresher.attach(target),
+            None => Ok(()),
+        }
+    }
+
+    /// Candidates of the source language identified automatically by
+    /// recognizer and translator. The language of each result is reported.
+    DefineAttribute!(
+        source_languages,
+        set_source_languages,
+        Vec<SourceLanguage>
+    );
+    /// Shortcut of source languages vector operation.
+    pub fn add_source_language<T: Into<SourceLanguage>>(
+        &mut self,
+        source: T,
+    ) -> &mut Self {
+        self.source_languages.push(source.into());
+        self
+    }
+    /// Identify the language at start or continuously.
+    SimpleAttribute!(language_id_mode, set_language_id_mode, LanguageIdMode);
+
+    /// Configuration of language identification, None if not enabled.
+    /// The mode is cleared from the shared properties when not enabled.
+    fn auto_detect_config(
+        &self,
+    ) -> Result<Option<AutoDetectSourceLanguageConfig>> {
+        if self.source_languages.is_empty() {
+            self.props.put_by_id(Property::LanguageIdMode.id(), "")?;
+            return Ok(None);
+        }
+        self.props.put_property_str(
+            Property::LanguageIdMode,
+            self.language_id_mode.as_str(),
+        )?;
+        AutoDetectSourceLanguageConfig::from_sources(&self.source_languages)
+            .map(Some)
+    }
+
     /// Streaming mode of audio input. Pull mode is true, push mode is false.
     SimpleAttribute!(pull_mode, set_pull_mode, bool);
     /// Bitmask flags for events handlers.
@@ -290,6 +467,30 @@ impl RecognizerConfig {
         Ok(())
     }
 
+    /// Phrases more likely to be recognized, e.g. brand names.
+    DefineAttribute!(phrase_hints, set_phrase_hints, Vec<String>);
+    /// Shortcut of phrase hints vector operation.
+    pub fn add_phrase_hint(&mut self, phrase: &str) -> Result<&mut Self> {
+        self.phrase_hints.push(phrase.to_string());
+        Ok(self)
+    }
+    /// Add the graphemes of the lexicon as phrase hints.
+    pub fn add_lexicon(&mut self, lexicon: &Lexicon) -> Result<&mut Self> {
+        for phrase in lexicon.phrases() {
+            if !self.phrase_hints.contains(&phrase) {
+                self.phrase_hints.push(phrase);
+            }
+        }
+        Ok(self)
+    }
+    /// Add phrase hints from configuration to generated recognizer.
+    fn apply_phrase_hints(&self, reco: &Recognizer) -> Result {
+        if self.phrase_hints.is_empty() {
+            return Ok(());
+        }
+        reco.add_phrase_hints(&self.phrase_hints)
+    }
+
     /// Can translate one speech source to multiple languages simultaneously.
     DefineAttribute!(target_languages, set_target_languages, Vec<String>);
     /// Shortcut of intents vector operation.
@@ -300,95 +501,96 @@ impl RecognizerConfig {
     /// Apply target languages to generate translator.
     fn apply_target_languages(&self) -> Result {
         let tl = self.target_languages.join(",");
-        self.props.put_by_id(
-            PropertyId_SpeechServiceConnection_TranslationToLanguages,
-            tl,
-        )
+        self.props
+            .put_property_str(Property::TranslationToLanguages, &tl)
     }
 
     /// The input language of the speech recognizer.
     DefineProperty!(
         trans_voice_name,
         put_trans_voice_name,
-        PropertyId_SpeechServiceConnection_TranslationVoice
+        Property::TranslationVoice
     );
 
     /// The input language of the speech recognizer.
     DefineProperty!(
         synth_voice_name,
         put_synth_voice_name,
-        PropertyId_SpeechServiceConnection_SynthVoice
+        Property::SynthVoice
     );
 
     /// The input language of the speech recognizer.
     DefineProperty!(
         translation_features,
         put_translation_features,
-        PropertyId_SpeechServiceConnection_TranslationFeatures
+        Property::TranslationFeatures
     );
 
     /// The input language of the speech recognizer.
-    DefineProperty!(
-        language,
-        put_language,
-        PropertyId_SpeechServiceConnection_RecoLanguage
-    );
+    DefineProperty!(language, put_language, Property::RecoLanguage);
 
     /// The input language of the speech recognizer.
     DefineProperty!(
         synth_language,
         put_synth_language,
-        PropertyId_SpeechServiceConnection_SynthLanguage
+        Property::SynthLanguage
     );
 
-    /// The endpoint ID of the speech recognizer.
+    /// Authorization token to connect the service.
     DefineProperty!(
-        endpoint,
-        put_endpoint,
-        PropertyId_SpeechServiceConnection_EndpointId
+        authorization_token,
+        put_authorization_token,
+        Property::AuthorizationToken
     );
 
+    /// The endpoint ID of the speech recognizer.
+    DefineProperty!(endpoint, put_endpoint, Property::EndpointId);
+
     /// Detailed output format or not.
     DefineProperty!(
         detailed_result,
         put_detailed_result,
-        PropertyId_SpeechServiceResponse_RequestDetailedResultTrueFalse
+        Property::RequestDetailedResult
     );
 
     /// Subset of proxy configuration
     pub fn put_proxy(&mut self, proxy: &ProxyConfig) -> Result<&mut Self> {
-        self.props.put_by_id(
-            PropertyId_SpeechServiceConnection_ProxyHostName,
-            &proxy.host_name,
-        )?;
-        self.props.put_by_id(
-            PropertyId_SpeechServiceConnection_ProxyPort,
-            &proxy.port.to_string(),
-        )?;
-        self.props.put_by_id(
-            PropertyId_SpeechServiceConnection_ProxyUserName,
-            &proxy.user_name,
-        )?;
-        self.props.put_by_id(
-            PropertyId_SpeechServiceConnection_ProxyPassword,
-            &proxy.password,
-        )?;
+        self.props
+            .put_text(Property::ProxyHostName, &proxy.host_name)?;
+        self.props.put_number(Property::ProxyPort, proxy.port)?;
+        self.props
+            .put_text(Property::ProxyUserName, &proxy.user_name)?;
+        self.props
+            .put_text(Property::ProxyPassword, &proxy.password)?;
 
         Ok(self)
     }
 
-    pub fn put_synth_audio_format(&mut self, mode: u32) -> Result<&mut Self> {
-        hr!(speech_config_set_audio_output_format(self.handle, mode))?;
+    /// Output format of the synthesized audio.
+    pub fn put_synth_audio_format(
+        &mut self,
+        format: OutputFormat,
+    ) -> Result<&mut Self> {
+        hr!(speech_config_set_audio_output_format(
+            self.handle,
+            format.id()
+        ))?;
         Ok(self)
     }
 }
 
 FlattenProps!(RecognizerConfig);
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+impl TokenTarget for RecognizerConfig {
+    fn token_props(&self) -> Weak<Properties> {
+        Arc::downgrade(&self.props)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProxyConfig {
-    host_name: String,
-    port: u32,
-    user_name: String,
-    password: String,
+    pub host_name: String,
+    pub port: u32,
+    pub user_name: String,
+    pub password: String,
 }
@@ -0,0 +1,678 @@
+//! Cache of synthesized audio in front of the synthesizer.
+//!
+//! Audio is keyed by the normalized text or SSML, the voice, the language
+//! and the output format. Storage is pluggable, with an in-memory LRU and an
+//! on-disk directory, both limited by time to live and size.
+
+use super::{
+    events::{Flags, Synthesis},
+    format::{strip_riff_header, Container, OutputFormat},
+    ssml::AsSsml,
+    synthesizer::Synthesizer,
+};
+use crate::{
+    error::Poisoned,
+    properties::{fnv1a, Property, PropertyBag, FNV_BASIS},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Limits of the cache storage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CachePolicy {
+    /// Entries older than this are synthesized again.
+    pub ttl: Option<Duration>,
+    /// Upper bound of the entries.
+    pub max_entries: Option<usize>,
+    /// Upper bound of the audio size in bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl CachePolicy {
+    fn is_expired(&self, audio: &CachedAudio) -> bool {
+        match (self.ttl, audio.created.elapsed()) {
+            (Some(ttl), Ok(age)) => age > ttl,
+            _ => false,
+        }
+    }
+}
+
+/// What the audio is synthesized from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// Text or SSML with whitespace collapsed.
+    pub input: String,
+    pub is_ssml: bool,
+    pub voice: String,
+    pub language: String,
+    pub format: OutputFormat,
+}
+
+impl CacheKey {
+    pub fn new(
+        input: &str,
+        is_ssml: bool,
+        voice: &str,
+        language: &str,
+        format: OutputFormat,
+    ) -> Self {
+        CacheKey {
+            input: input.split_whitespace().collect::<Vec<_>>().join(" "),
+            is_ssml,
+            voice: voice.to_string(),
+            language: language.to_string(),
+            format,
+        }
+    }
+
+    /// Stable digest of the key, used as the storage key and the file name.
+    pub fn digest(&self) -> String {
+        let text = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.format, self.voice, self.language, self.is_ssml, self.input
+        );
+        let high = fnv1a(text.as_bytes(), FNV_BASIS);
+        let low = fnv1a(text.as_bytes(), 0x8422_2325_cbf2_9ce4);
+        format!("{:016x}{:016x}", high, low)
+    }
+}
+
+/// Audio kept in the cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedAudio {
+    pub key: CacheKey,
+    #[serde(skip)]
+    pub audio: Vec<u8>,
+    pub created: SystemTime,
+}
+
+/// Storage of the cached audio by the digest of keys.
+pub trait CacheStorage: Send + Sync {
+    /// Audio of the digest, None if missing or expired.
+    fn get(&self, digest: &str) -> Result<Option<CachedAudio>>;
+
+    /// Keep the audio, evict others beyond the limits.
+    fn put(&self, digest: &str, audio: CachedAudio) -> Result;
+
+    fn remove(&self, digest: &str) -> Result;
+
+    fn clear(&self) -> Result;
+}
+
+struct Slot {
+    audio: CachedAudio,
+    used: u64,
+}
+
+struct Lru {
+    slots: HashMap<String, Slot>,
+    /// Digests by the time of last use.
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: u64,
+}
+
+impl Lru {
+    fn remove(&mut self, digest: &str) {
+        if let Some(slot) = self.slots.remove(digest) {
+            self.order.remove(&slot.used);
+            self.bytes -= slot.audio.audio.len() as u64;
+        }
+    }
+
+    fn touch(&mut self, digest: &str) {
+        self.clock += 1;
+        if let Some(slot) = self.slots.get_mut(digest) {
+            self.order.remove(&slot.used);
+            slot.used = self.clock;
+            self.order.insert(self.clock, digest.to_string());
+        }
+    }
+}
+
+/// In-memory storage evicting the least recently used audio.
+pub struct MemoryCache {
+    policy: CachePolicy,
+    lru: Mutex<Lru>,
+}
+
+impl MemoryCache {
+    pub fn new(policy: CachePolicy) -> Self {
+        MemoryCache {
+            policy,
+            lru: Mutex::new(Lru {
+                slots: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: 0,
+                bytes: 0,
+            }),
+        }
+    }
+}
+
+impl CacheStorage for MemoryCache {
+    fn get(&self, digest: &str) -> Result<Option<CachedAudio>> {
+        let mut lru = self.lru.lock().map_err(|_| Poisoned)?;
+        let expired = match lru.slots.get(digest) {
+            Some(slot) => self.policy.is_expired(&slot.audio),
+            None => return Ok(None),
+        };
+        if expired {
+            lru.remove(digest);
+            return Ok(None);
+        }
+        lru.touch(digest);
+        Ok(lru.slots.get(digest).map(|slot| slot.audio.clone()))
+    }
+
+    fn put(&self, digest: &str, audio: CachedAudio) -> Result {
+        let mut lru = self.lru.lock().map_err(|_| Poisoned)?;
+        lru.remove(digest);
+        lru.bytes += audio.audio.len() as u64;
+        lru.slots
+            .insert(digest.to_string(), Slot { audio, used: 0 });
+        lru.touch(digest);
+        loop {
+            let over = self
+                .policy
+                .max_entries
+                .map_or(false, |max| lru.slots.len() > max)
+                || self.policy.max_bytes.map_or(false, |max| lru.bytes > max);
+            let oldest = match lru.order.values().next() {
+                Some(oldest) if over => oldest.clone(),
+                _ => break,
+            };
+            lru.remove(&oldest);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, digest: &str) -> Result {
+        self.lru.lock().map_err(|_| Poisoned)?.remove(digest);
+        Ok(())
+    }
+
+    fn clear(&self) -> Result {
+        let mut lru = self.lru.lock().map_err(|_| Poisoned)?;
+        lru.slots.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+        Ok(())
+    }
+}
+
+/// Storage of audio files framed by their format in a directory, with a
+/// JSON sidecar of the key per file. The oldest files are evicted first.
+pub struct DiskCache {
+    directory: PathBuf,
+    policy: CachePolicy,
+    /// Serialize the eviction.
+    lock: Mutex<()>,
+}
+
+impl DiskCache {
+    pub fn new<P: Into<PathBuf>>(
+        directory: P,
+        policy: CachePolicy,
+    ) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(DiskCache {
+            directory,
+            policy,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn meta_path(&self, digest: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", digest))
+    }
+
+    fn audio_path(&self, digest: &str, format: OutputFormat) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", digest, format.extension()))
+    }
+
+    fn read_meta(&self, digest: &str) -> Result<Option<CachedAudio>> {
+        match fs::read(self.meta_path(digest)) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Digests with creation time and audio size, oldest first.
+    fn entries(&self) -> Result<Vec<(SystemTime, String, u64)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let digest = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(digest) => digest.to_string(),
+                None => continue,
+            };
+            if let Some(meta) = self.read_meta(&digest)? {
+                let size =
+                    fs::metadata(self.audio_path(&digest, meta.key.format))
+                        .map(|m| m.len())
+                        .unwrap_or_default();
+                entries.push((meta.created, digest, size));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+impl CacheStorage for DiskCache {
+    fn get(&self, digest: &str) -> Result<Option<CachedAudio>> {
+        let mut meta = match self.read_meta(digest)? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        if self.policy.is_expired(&meta) {
+            self.remove(digest)?;
+            return Ok(None);
+        }
+        match fs::read(self.audio_path(digest, meta.key.format)) {
+            Ok(framed) => {
+                // Raw samples are framed by a WAV header on disk.
+                meta.audio = match meta.key.format.container() {
+                    Container::Raw => strip_riff_header(&framed).to_vec(),
+                    _ => framed,
+                };
+                Ok(Some(meta))
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn put(&self, digest: &str, audio: CachedAudio) -> Result {
+        let _lock = self.lock.lock().map_err(|_| Poisoned)?;
+        audio.key.format.write_file(
+            self.audio_path(digest, audio.key.format),
+            &audio.audio,
+        )?;
+        fs::write(self.meta_path(digest), serde_json::to_vec(&audio)?)?;
+
+        let mut entries = self.entries()?;
+        let mut bytes: u64 = entries.iter().map(|e| e.2).sum();
+        let mut count = entries.len();
+        entries.reverse();
+        while let Some((_, oldest, size)) = entries.pop() {
+            let over = self.policy.max_entries.map_or(false, |max| count > max)
+                || self.policy.max_bytes.map_or(false, |max| bytes > max);
+            if !over {
+                break;
+            }
+            self.remove(&oldest)?;
+            count -= 1;
+            bytes -= size;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, digest: &str) -> Result {
+        if let Some(meta) = self.read_meta(digest)? {
+            let path = self.audio_path(digest, meta.key.format);
+            if let Err(err) = fs::remove_file(path) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+            fs::remove_file(self.meta_path(digest))?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> Result {
+        for (_, digest, _) in self.entries()? {
+            self.remove(&digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hits and misses of the cache.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Synthesizer returning the cached audio of the same input.
+pub struct CachedSynthesizer {
+    synth: Synthesizer,
+    storage: Box<dyn CacheStorage>,
+    stats: CacheStats,
+}
+
+impl CachedSynthesizer {
+    pub fn new<S>(synth: Synthesizer, storage: S) -> Self
+    where
+        S: CacheStorage + 'static,
+    {
+        CachedSynthesizer {
+            synth,
+            storage: Box::new(storage),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Key of the input with the current voice, language and format.
+    pub fn key(&self, input: &str, is_ssml: bool) -> Result<CacheKey> {
+        Ok(CacheKey::new(
+            input,
+            is_ssml,
+            &self.synth.get_by_id(Property::SynthVoice.id())?,
+            &self.synth.get_by_id(Property::SynthLanguage.id())?,
+            self.synth.output_format()?,
+        ))
+    }
+
+    /// Synthesize plain text, or return the cached audio.
+    pub fn synthesis_once(&mut self, text: &str) -> Result<Synthesis> {
+        let key = self.key(text, false)?;
+        self.cached(key, |synth| synth.synthesis_once(text))
+    }
+
+    /// Synthesize SSML, or return the cached audio.
+    pub fn ssml_synthesis_once<S: AsSsml + ?Sized>(
+        &mut self,
+        ssml: &S,
+    ) -> Result<Synthesis> {
+        let ssml = ssml.as_ssml()?;
+        let key = self.key(&ssml, true)?;
+        self.cached(key, |synth| synth.ssml_synthesis_once(ssml.as_ref()))
+    }
+
+    fn cached<F>(&mut self, key: CacheKey, f: F) -> Result<Synthesis>
+    where
+        F: FnOnce(&mut Synthesizer) -> Result<super::events::SynthEventResult>,
+    {
+        let digest = key.digest();
+        match self.storage.get(&digest) {
+            Ok(Some(cached)) if cached.key == key => {
+                self.stats.hits += 1;
+                return Ok(Synthesis {
+                    flag: Flags::Synthesized,
+                    id: digest,
+                    reason: Flags::Synthesized | Flags::Synthesis,
+                    audio_length: cached.audio.len(),
+                    audio_data: cached.audio,
+                    format: Some(key.format),
+                });
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("failed to read cached audio: {}", err),
+        }
+        self.stats.misses += 1;
+
+        let mut synthesis =
+            f(&mut self.synth)?.synthesis(Flags::Synthesized)?;
+        synthesis.format = Some(key.format);
+        if synthesis.reason.contains(Flags::Synthesized)
+            && !synthesis.audio_data.is_empty()
+        {
+            let audio = CachedAudio {
+                key,
+                audio: synthesis.audio_data.clone(),
+                created: SystemTime::now(),
+            };
+            if let Err(err) = self.storage.put(&digest, audio) {
+                log::warn!("failed to cache audio: {}", err);
+            }
+        }
+        Ok(synthesis)
+    }
+
+    /// Drop the cached audio of the plain text.
+    pub fn invalidate(&self, text: &str) -> Result {
+        self.storage.remove(&self.key(text, false)?.digest())
+    }
+
+    pub fn clear(&self) -> Result {
+        self.storage.clear()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn synthesizer(&mut self) -> &mut Synthesizer {
+        &mut self.synth
+    }
+
+    pub fn into_inner(self) -> Synthesizer {
+        self.synth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn key(input: &str) -> CacheKey {
+        CacheKey::new(
+            input,
+            false,
+            "en-US-JennyNeural",
+            "en-US",
+            OutputFormat::Raw16Khz16BitMonoPcm,
+        )
+    }
+
+    fn audio(input: &str, size: usize, age: Duration) -> CachedAudio {
+        CachedAudio {
+            key: key(input),
+            audio: vec![1; size],
+            created: SystemTime::now() - age,
+        }
+    }
+
+    fn policy(
+        ttl: Option<Duration>,
+        max_entries: Option<usize>,
+        max_bytes: Option<u64>,
+    ) -> CachePolicy {
+        CachePolicy {
+            ttl,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    fn cached(storage: &dyn CacheStorage, digest: &str) -> bool {
+        storage.get(digest).unwrap().is_some()
+    }
+
+    #[test]
+    fn digest_is_stable() {
+        let digest = key("Hello  world.\n").digest();
+        assert_eq!(digest, key("Hello world.").digest());
+        assert_eq!(digest.len(), 32);
+        assert_eq!(digest, "7a22b2c9bc3b2b6f2723a438a776cf24");
+
+        let mut other = key("Hello world.");
+        other.is_ssml = true;
+        assert_ne!(other.digest(), digest);
+        other = key("Hello world.");
+        other.voice = String::from("en-US-GuyNeural");
+        assert_ne!(other.digest(), digest);
+        other = key("Hello world.");
+        other.format = OutputFormat::Riff16Khz16BitMonoPcm;
+        assert_ne!(other.digest(), digest);
+    }
+
+    #[test]
+    fn memory_cache_evicts_least_recently_used() {
+        let cache = MemoryCache::new(policy(None, Some(2), None));
+        let hour = Duration::from_secs(3600);
+        cache.put("a", audio("a", 4, hour)).unwrap();
+        cache.put("b", audio("b", 4, hour)).unwrap();
+        // Reading a makes b the least recently used.
+        assert!(cached(&cache, "a"));
+        cache.put("c", audio("c", 4, hour)).unwrap();
+        assert!(!cached(&cache, "b"));
+        assert!(cached(&cache, "a"));
+        assert!(cached(&cache, "c"));
+
+        // Replacing an entry doesn't count it twice.
+        cache.put("a", audio("a", 8, hour)).unwrap();
+        assert!(cached(&cache, "c"));
+        assert_eq!(cache.get("a").unwrap().unwrap().audio.len(), 8);
+
+        cache.remove("a").unwrap();
+        assert!(!cached(&cache, "a"));
+        cache.clear().unwrap();
+        assert!(!cached(&cache, "c"));
+    }
+
+    #[test]
+    fn memory_cache_limits_bytes() {
+        let cache = MemoryCache::new(policy(None, None, Some(10)));
+        let age = Duration::from_secs(0);
+        cache.put("a", audio("a", 4, age)).unwrap();
+        cache.put("b", audio("b", 4, age)).unwrap();
+        cache.put("c", audio("c", 4, age)).unwrap();
+        assert!(!cached(&cache, "a"));
+        assert!(cached(&cache, "b"));
+        assert!(cached(&cache, "c"));
+
+        // Audio over the limit alone isn't kept.
+        cache.put("d", audio("d", 12, age)).unwrap();
+        assert!(!cached(&cache, "b"));
+        assert!(!cached(&cache, "c"));
+        assert!(!cached(&cache, "d"));
+    }
+
+    #[test]
+    fn memory_cache_expires_entries() {
+        let cache =
+            MemoryCache::new(policy(Some(Duration::from_secs(60)), None, None));
+        cache
+            .put("old", audio("old", 4, Duration::from_secs(120)))
+            .unwrap();
+        cache
+            .put("new", audio("new", 4, Duration::from_secs(0)))
+            .unwrap();
+        assert!(!cached(&cache, "old"));
+        assert!(cached(&cache, "new"));
+    }
+
+    /// Directory of the test, removed when it is dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!(
+                "luis-cache-{}-{}",
+                name,
+                process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn files(dir: &TempDir) -> usize {
+        fs::read_dir(&dir.0).unwrap().count()
+    }
+
+    #[test]
+    fn disk_cache_keeps_audio() {
+        let dir = TempDir::new("keep");
+        let cache = DiskCache::new(&dir.0, CachePolicy::default()).unwrap();
+        let kept = audio("Hello.", 6, Duration::from_secs(0));
+        let digest = kept.key.digest();
+        cache.put(&digest, kept.clone()).unwrap();
+        // Raw audio is framed on disk, and stripped when read.
+        assert!(dir.0.join(format!("{}.wav", digest)).exists());
+        let read = cache.get(&digest).unwrap().unwrap();
+        assert_eq!(read.key, kept.key);
+        assert_eq!(read.audio, kept.audio);
+        assert_eq!(read.created, kept.created);
+
+        // Another cache of the directory reads it too.
+        let reopened = DiskCache::new(&dir.0, CachePolicy::default()).unwrap();
+        assert!(cached(&reopened, &digest));
+
+        cache.remove(&digest).unwrap();
+        assert!(!cached(&cache, &digest));
+        assert_eq!(files(&dir), 0);
+    }
+
+    #[test]
+    fn disk_cache_evicts_oldest() {
+        let dir = TempDir::new("evict");
+        let cache =
+            DiskCache::new(&dir.0, policy(None, Some(2), None)).unwrap();
+        let hour = Duration::from_secs(3600);
+        cache.put("a", audio("a", 4, hour * 3)).unwrap();
+        cache.put("b", audio("b", 4, hour)).unwrap();
+        cache.put("c", audio("c", 4, hour * 2)).unwrap();
+        assert!(!cached(&cache, "a"));
+        assert!(cached(&cache, "b"));
+        assert!(cached(&cache, "c"));
+        assert_eq!(files(&dir), 4);
+
+        cache.clear().unwrap();
+        assert_eq!(files(&dir), 0);
+    }
+
+    #[test]
+    fn disk_cache_limits_bytes() {
+        let dir = TempDir::new("bytes");
+        // Files are framed by a header of 44 bytes.
+        let cache =
+            DiskCache::new(&dir.0, policy(None, None, Some(100))).unwrap();
+        let hour = Duration::from_secs(3600);
+        cache.put("a", audio("a", 4, hour * 2)).unwrap();
+        cache.put("b", audio("b", 4, hour)).unwrap();
+        cache
+            .put("c", audio("c", 4, Duration::from_secs(0)))
+            .unwrap();
+        assert!(!cached(&cache, "a"));
+        assert!(cached(&cache, "b"));
+        assert!(cached(&cache, "c"));
+    }
+
+    #[test]
+    fn disk_cache_expires_entries() {
+        let dir = TempDir::new("expire");
+        let cache = DiskCache::new(
+            &dir.0,
+            policy(Some(Duration::from_secs(60)), None, None),
+        )
+        .unwrap();
+        cache
+            .put("old", audio("old", 4, Duration::from_secs(120)))
+            .unwrap();
+        cache
+            .put("new", audio("new", 4, Duration::from_secs(0)))
+            .unwrap();
+        assert!(!cached(&cache, "old"));
+        assert!(cached(&cache, "new"));
+        // The expired files are removed.
+        assert_eq!(files(&dir), 2);
+    }
+}
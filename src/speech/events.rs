@@ -2,20 +2,27 @@
 
 #![allow(non_upper_case_globals)]
 
+use super::{format::OutputFormat, vad::Activity};
 use crate::{
     get_cf_string, hr,
     properties::{Properties, PropertyBag},
     speech_api::*,
     DeriveHandle, FlattenProps, Handle, Result, SpxError, INVALID_HANDLE,
 };
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use serde_json::{self, json, Value};
-use std::{ffi::CStr, os::raw::c_char, ptr::null_mut, slice, time::Duration};
+use std::{
+    ffi::CStr, fmt, os::raw::c_char, ptr::null_mut, slice, str::FromStr,
+    time::Duration,
+};
 
 const SPXERR_BUFFER_TOO_SMALL: usize = 0x019;
 
 bitflags! {
-    #[derive(Default, Deserialize)]
+    #[derive(Default)]
     pub struct Flags: u64 {
         const Connected =                       0b0001;
         const Disconnected =                    0b0010;
@@ -39,6 +46,13 @@ bitflags! {
         const SynthesisEvent =   0b0111_0000_0000_0000;
         const Canceled =    0b0100_0000_0000_0000_0000;
         const NoMatch =     0b1000_0000_0000_0000_0000;
+        const VoiceStart =     0b0001_0000_0000_0000_0000_0000;
+        const VoiceEnd =       0b0010_0000_0000_0000_0000_0000;
+        const VoiceDetection = 0b0011_0000_0000_0000_0000_0000;
+        const WordBoundary =    0b0001_0000_0000_0000_0000_0000_0000;
+        const Viseme =          0b0010_0000_0000_0000_0000_0000_0000;
+        const BookmarkReached = 0b0100_0000_0000_0000_0000_0000_0000;
+        const SynthesisMark =   0b0111_0000_0000_0000_0000_0000_0000;
     }
 }
 
@@ -53,6 +67,103 @@ impl Serialize for Flags {
     }
 }
 
+/// Parse the readable output of flags, e.g. "Recognized | Speech".
+impl FromStr for Flags {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = Flags::empty();
+        for name in s.split('|').map(str::trim) {
+            flags |= match name {
+                "Connected" => Flags::Connected,
+                "Disconnected" => Flags::Disconnected,
+                "Connection" => Flags::Connection,
+                "SessionStarted" => Flags::SessionStarted,
+                "SessionStopped" => Flags::SessionStopped,
+                "Session" => Flags::Session,
+                "SpeechStartDetected" => Flags::SpeechStartDetected,
+                "SpeechEndDetected" => Flags::SpeechEndDetected,
+                "SpeechDetection" => Flags::SpeechDetection,
+                "Recognizing" => Flags::Recognizing,
+                "Recognized" => Flags::Recognized,
+                "Recognition" => Flags::Recognition,
+                "Speech" => Flags::Speech,
+                "Intent" => Flags::Intent,
+                "Translation" => Flags::Translation,
+                "Synthesis" => Flags::Synthesis,
+                "Synthesizing" => Flags::Synthesizing,
+                "Synthesized" => Flags::Synthesized,
+                "SynthesisStart" => Flags::SynthesisStart,
+                "SynthesisEvent" => Flags::SynthesisEvent,
+                "Canceled" => Flags::Canceled,
+                "NoMatch" => Flags::NoMatch,
+                "VoiceStart" => Flags::VoiceStart,
+                "VoiceEnd" => Flags::VoiceEnd,
+                "VoiceDetection" => Flags::VoiceDetection,
+                "WordBoundary" => Flags::WordBoundary,
+                "Viseme" => Flags::Viseme,
+                "BookmarkReached" => Flags::BookmarkReached,
+                "SynthesisMark" => Flags::SynthesisMark,
+                "" | "(empty)" => Flags::empty(),
+                _ if name.starts_with("0x") => {
+                    let bits = u64::from_str_radix(&name[2..], 16)?;
+                    Flags::from_bits_truncate(bits)
+                }
+                _ => {
+                    return Err(SpxError::Other(format!(
+                        "unknown flag: {}",
+                        name
+                    )))
+                }
+            };
+        }
+        Ok(flags)
+    }
+}
+
+/// Accept the readable string, raw bits or the bits structure.
+impl<'de> Deserialize<'de> for Flags {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FlagsVisitor;
+
+        impl<'de> Visitor<'de> for FlagsVisitor {
+            type Value = Flags;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("flags names or bits")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Flags, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Flags, E> {
+                Ok(Flags::from_bits_truncate(v))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Flags, A::Error> {
+                let mut bits = 0;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "bits" {
+                        bits = map.next_value()?;
+                    } else {
+                        map.next_value::<de::IgnoredAny>()?;
+                    }
+                }
+                Ok(Flags::from_bits_truncate(bits))
+            }
+        }
+
+        deserializer.deserialize_any(FlagsVisitor)
+    }
+}
+
 /// Convert from underlying integer const of result reason.
 impl From<Result_Reason> for Flags {
     fn from(reason: Result_Reason) -> Self {
@@ -109,7 +220,7 @@ where
 }
 
 /// Make NoMatch reason readable.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Matching {
     Matched,
     NotRecognized,
@@ -139,9 +250,10 @@ impl From<Result_NoMatchReason> for Matching {
 }
 
 /// Output of recognition
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Recognition {
     pub flag: Flags,
+    #[serde(default)]
     pub session: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
@@ -163,6 +275,9 @@ pub struct Recognition {
     pub translations: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub synthesis: Option<Vec<u8>>,
+    /// Source language identified automatically.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
 }
 
 impl Recognition {
@@ -174,8 +289,368 @@ impl Recognition {
 
 impl ToJson for Recognition {}
 
+/// Event with all the data extracted, no underlying handle is kept.
+/// There is one variant for each category of flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "category")]
+pub enum OwnedEvent {
+    Connection {
+        flag: Flags,
+        session: String,
+    },
+    Session {
+        flag: Flags,
+        session: String,
+    },
+    SpeechDetection {
+        flag: Flags,
+        session: String,
+        offset: Duration,
+    },
+    VoiceDetection {
+        flag: Flags,
+        offset: Duration,
+    },
+    Recognition {
+        flag: Flags,
+        session: String,
+        id: String,
+        reason: Flags,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<Duration>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration: Option<Duration>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        intent: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        details: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        translations: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+    },
+    Synthesis {
+        flag: Flags,
+        session: String,
+        id: String,
+        reason: Flags,
+        audio: Vec<u8>,
+    },
+    NoMatch {
+        flag: Flags,
+        session: String,
+        id: String,
+        reason: Flags,
+        matching: Matching,
+    },
+    Canceled {
+        flag: Flags,
+        session: String,
+        id: String,
+        reason: Flags,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<CancellationError>,
+    },
+}
+
+impl OwnedEvent {
+    /// Extract all the data of the event by underlying API.
+    pub fn extract(evt: Event) -> Result<Self> {
+        let flag = evt.flag;
+        let session = evt.session_id()?;
+        if flag.intersects(Flags::Connection) {
+            return Ok(OwnedEvent::Connection { flag, session });
+        }
+        if flag.intersects(Flags::Session) {
+            return Ok(OwnedEvent::Session { flag, session });
+        }
+        if flag.intersects(Flags::SpeechDetection) {
+            let offset = evt.offset()?;
+            return Ok(OwnedEvent::SpeechDetection {
+                flag,
+                session,
+                offset,
+            });
+        }
+
+        let er = EventResult::from_event(evt)?;
+        let id = er.id()?;
+        let reason = er.reason();
+
+        if reason.intersects(Flags::NoMatch) {
+            let matching = er.no_match_reason()?;
+            return Ok(OwnedEvent::NoMatch {
+                flag,
+                session,
+                id,
+                reason,
+                matching,
+            });
+        }
+
+        if reason.intersects(Flags::Canceled) {
+            let error = if er.code()?
+                == Result_CancellationErrorCode_CancellationErrorCode_NoError
+            {
+                None
+            } else {
+                Some(CancellationError {
+                    reason: er.cancellation_reason()?,
+                    code: er.code()?,
+                    details: er.error_details()?,
+                })
+            };
+            return Ok(OwnedEvent::Canceled {
+                flag,
+                session,
+                id,
+                reason,
+                error,
+            });
+        }
+
+        if reason.contains(Flags::Translation | Flags::Synthesis) {
+            let audio = er.synthesis()?;
+            return Ok(OwnedEvent::Synthesis {
+                flag,
+                session,
+                id,
+                reason,
+                audio,
+            });
+        }
+
+        let mut intent = None;
+        let mut details = None;
+        if reason.intersects(Flags::Intent) {
+            let id = er.intent()?;
+            if !id.is_empty() {
+                intent = Some(id);
+            }
+            details = Some(er.details()?);
+        }
+        let translations = if reason.intersects(Flags::Translation) {
+            Some(er.translations()?)
+        } else {
+            None
+        };
+        let (mut offset, mut duration, mut text) = (None, None, None);
+        if reason.intersects(Flags::Recognition) {
+            offset = Some(er.offset()?);
+            duration = Some(er.duration()?);
+            text = Some(er.text()?);
+        }
+
+        Ok(OwnedEvent::Recognition {
+            flag,
+            session,
+            id,
+            reason,
+            offset,
+            duration,
+            text,
+            intent,
+            details,
+            translations,
+            language: er.language()?,
+        })
+    }
+
+    /// Flag of the event source.
+    pub fn flag(&self) -> Flags {
+        match self {
+            OwnedEvent::Connection { flag, .. }
+            | OwnedEvent::Session { flag, .. }
+            | OwnedEvent::SpeechDetection { flag, .. }
+            | OwnedEvent::VoiceDetection { flag, .. }
+            | OwnedEvent::Recognition { flag, .. }
+            | OwnedEvent::Synthesis { flag, .. }
+            | OwnedEvent::NoMatch { flag, .. }
+            | OwnedEvent::Canceled { flag, .. } => *flag,
+        }
+    }
+
+    /// Session id of the event, empty for local events.
+    pub fn session(&self) -> &str {
+        match self {
+            OwnedEvent::VoiceDetection { .. } => "",
+            OwnedEvent::Connection { session, .. }
+            | OwnedEvent::Session { session, .. }
+            | OwnedEvent::SpeechDetection { session, .. }
+            | OwnedEvent::Recognition { session, .. }
+            | OwnedEvent::Synthesis { session, .. }
+            | OwnedEvent::NoMatch { session, .. }
+            | OwnedEvent::Canceled { session, .. } => session,
+        }
+    }
+
+    /// Convert to the output of recognition, cancellation with error is
+    /// converted to error.
+    pub fn into_recognition(self) -> Result<Recognition> {
+        let mut r = Recognition::default();
+        match self {
+            OwnedEvent::Connection { flag, session }
+            | OwnedEvent::Session { flag, session } => {
+                r.flag = flag;
+                r.session = session;
+            }
+            OwnedEvent::SpeechDetection {
+                flag,
+                session,
+                offset,
+            } => {
+                r.flag = flag;
+                r.session = session;
+                r.offset = Some(offset);
+            }
+            OwnedEvent::VoiceDetection { flag, offset } => {
+                r.flag = flag;
+                r.offset = Some(offset);
+            }
+            OwnedEvent::Recognition {
+                flag,
+                session,
+                id,
+                reason,
+                offset,
+                duration,
+                text,
+                intent,
+                details,
+                translations,
+                language,
+            } => {
+                r.flag = flag;
+                r.session = session;
+                r.id = Some(id);
+                r.reason = Some(reason);
+                r.offset = offset;
+                r.duration = duration;
+                r.text = text;
+                if intent.is_some() {
+                    r.matching = Some(Matching::Matched);
+                }
+                r.intent = intent;
+                r.details = details;
+                r.translations = translations;
+                r.language = language;
+            }
+            OwnedEvent::Synthesis {
+                flag,
+                session,
+                id,
+                reason,
+                audio,
+            } => {
+                r.flag = flag;
+                r.session = session;
+                r.id = Some(id);
+                r.reason = Some(reason);
+                if !audio.is_empty() {
+                    r.synthesis = Some(audio);
+                }
+            }
+            OwnedEvent::NoMatch {
+                flag,
+                session,
+                id,
+                reason,
+                matching,
+            } => {
+                r.flag = flag;
+                r.session = session;
+                r.id = Some(id);
+                r.reason = Some(reason);
+                r.matching = Some(matching);
+            }
+            OwnedEvent::Canceled {
+                flag,
+                session,
+                id,
+                reason,
+                error,
+            } => {
+                if let Some(err) = error {
+                    return Err(err.into());
+                }
+                r.flag = flag;
+                r.session = session;
+                r.id = Some(id);
+                r.reason = Some(reason);
+            }
+        }
+        Ok(r)
+    }
+}
+
+/// Best effort conversion of recognition output, e.g. from recorded logs.
+impl From<Recognition> for OwnedEvent {
+    fn from(r: Recognition) -> Self {
+        let flag = r.flag;
+        let reason = r.reason.unwrap_or(flag);
+        let session = r.session;
+        let id = r.id.unwrap_or_default();
+        let offset = r.offset.unwrap_or_default();
+        if flag.intersects(Flags::Connection) {
+            OwnedEvent::Connection { flag, session }
+        } else if flag.intersects(Flags::Session) {
+            OwnedEvent::Session { flag, session }
+        } else if flag.intersects(Flags::SpeechDetection) {
+            OwnedEvent::SpeechDetection {
+                flag,
+                session,
+                offset,
+            }
+        } else if flag.intersects(Flags::VoiceDetection) {
+            OwnedEvent::VoiceDetection { flag, offset }
+        } else if reason.intersects(Flags::NoMatch) {
+            OwnedEvent::NoMatch {
+                flag,
+                session,
+                id,
+                reason,
+                matching: r.matching.unwrap_or(Matching::NotRecognized),
+            }
+        } else if reason.intersects(Flags::Canceled) {
+            OwnedEvent::Canceled {
+                flag,
+                session,
+                id,
+                reason,
+                error: None,
+            }
+        } else if let Some(audio) = r.synthesis {
+            OwnedEvent::Synthesis {
+                flag,
+                session,
+                id,
+                reason,
+                audio,
+            }
+        } else {
+            OwnedEvent::Recognition {
+                flag,
+                session,
+                id,
+                reason,
+                offset: r.offset,
+                duration: r.duration,
+                text: r.text,
+                intent: r.intent,
+                details: r.details,
+                translations: r.translations,
+                language: r.language,
+            }
+        }
+    }
+}
+
+impl ToJson for OwnedEvent {}
+
 /// Refine the cancellation.
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct CancellationError {
     reason: Result_CancellationReason,
     code: Result_CancellationErrorCode,
@@ -183,6 +658,38 @@ pub struct CancellationError {
 }
 impl ToJson for CancellationError {}
 
+impl CancellationError {
+    /// The reason the result was canceled.
+    pub fn reason(&self) -> Result_CancellationReason {
+        self.reason
+    }
+
+    /// The error code of the cancellation.
+    pub fn code(&self) -> Result_CancellationErrorCode {
+        self.code
+    }
+
+    /// The error message of the cancellation.
+    pub fn details(&self) -> &str {
+        &self.details
+    }
+
+    /// Transient failure of connection or service which may be recovered by
+    /// reconnecting.
+    pub fn is_retryable(&self) -> bool {
+        match self.code {
+            Result_CancellationErrorCode_CancellationErrorCode_TooManyRequests
+            | Result_CancellationErrorCode_CancellationErrorCode_ConnectionFailure
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceTimeout
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceError
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceUnavailable => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 /// Refine the NoMatch reason.
 #[derive(Debug, Serialize)]
 pub struct NoMatchError {
@@ -203,117 +710,82 @@ pub struct Event {
     handle: SPXEVENTHANDLE,
     /// Flag of the event source.
     flag: Flags,
+    /// Extracted data or event generated locally without underlying handle,
+    /// or the error of extracting it.
+    local: Option<Result<OwnedEvent>>,
 }
 
 impl Event {
     /// Constructor.
     pub fn new(flag: Flags, handle: SPXEVENTHANDLE) -> Self {
-        Event { flag, handle }
-    }
-
-    /// Yield the output of event.
-    pub fn into_result(self) -> Result<Recognition> {
-        let mut r = Recognition::default();
-        let flag = self.flag;
-        r.flag = flag;
-        r.session = self.session_id()?;
-        if flag.intersects(Flags::Connection | Flags::Session) {
-            return Ok(r);
-        }
-
-        if flag.intersects(Flags::SpeechDetection) {
-            r.offset = Some(self.offset()?);
-            return Ok(r);
+        Event {
+            flag,
+            handle,
+            local: None,
         }
+    }
 
-        let er = EventResult::from_event(self)?;
-        r.id = Some(er.id()?);
-        let reason = er.reason();
-        r.reason = Some(reason);
+    /// Event of client side voice activity detection.
+    pub fn from_activity(activity: Activity) -> Self {
+        let (flag, offset) = match activity {
+            Activity::Start(offset) => (Flags::VoiceStart, offset),
+            Activity::End(offset) => (Flags::VoiceEnd, offset),
+        };
+        Event::from_owned(OwnedEvent::VoiceDetection { flag, offset })
+    }
 
-        if reason.intersects(Flags::NoMatch) {
-            r.matching = Some(er.no_match_reason()?);
-            return Ok(r);
-        }
+    /// Event of extracted result without underlying handle, e.g. replayed.
+    pub fn from_recognition(reco: Recognition) -> Self {
+        Event::from_owned(OwnedEvent::from(reco))
+    }
 
-        if reason.intersects(Flags::Canceled) {
-            if er.code()?
-                == Result_CancellationErrorCode_CancellationErrorCode_NoError
-            {
-                return Ok(r);
-            } else {
-                return er.cancellation_error();
-            }
+    /// Event of extracted data without underlying handle.
+    pub fn from_owned(owned: OwnedEvent) -> Self {
+        Event {
+            flag: owned.flag(),
+            handle: INVALID_HANDLE,
+            local: Some(Ok(owned)),
         }
+    }
 
-        if reason.intersects(Flags::Recognition) {
-            r.text = Some(er.text()?);
-            r.duration = Some(er.duration()?);
-            r.offset = Some(er.offset()?);
+    /// Event of which the data failed to be extracted, the error is
+    /// yielded when it is converted.
+    pub fn from_failure(flag: Flags, err: SpxError) -> Self {
+        Event {
+            flag,
+            handle: INVALID_HANDLE,
+            local: Some(Err(err)),
         }
+    }
 
-        if reason.intersects(Flags::Intent) {
-            let intent = er.intent()?;
-            if !intent.is_empty() {
-                r.matching = Some(Matching::Matched);
-                r.intent = Some(intent);
-            }
-            if reason.intersects(Flags::Intent) {
-                r.details = Some(er.details()?);
-            }
-        }
+    /// Check if the event holds a live underlying handle to extract.
+    pub fn is_valid(&self) -> bool {
+        self.local.is_none()
+            && unsafe { recognizer_event_handle_is_valid(self.handle) }
+    }
 
-        if reason.intersects(Flags::Translation) {
-            let translations = er.translations()?;
-            r.translations = Some(translations);
+    /// Extract all the data of the event, no-op if it is extracted already.
+    pub fn into_owned(mut self) -> Result<OwnedEvent> {
+        match self.local.take() {
+            Some(owned) => owned,
+            None => OwnedEvent::extract(self),
         }
+    }
 
-        if reason.contains(Flags::Translation | Flags::Synthesis) {
-            let sz = er.synthesis_data_length()?;
-            if sz != 0 {
-                let mut buf = Vec::with_capacity(sz);
-                unsafe { buf.set_len(sz) };
-                er.synthesis_data(&mut buf)?;
-                r.synthesis = Some(buf);
-            }
-        }
+    /// Yield the output of event.
+    pub fn into_result(self) -> Result<Recognition> {
+        self.into_owned()?.into_recognition()
+    }
 
-        Ok(r)
+    /// Yield the word boundary, viseme or bookmark of synthesis event.
+    pub fn into_synthesis_mark(self) -> Result<SynthesisMark> {
+        SynthesisMark::extract(self)
     }
 
     /// Yield the output of event.
     pub fn into_synth_result(self) -> Result<Synthesis> {
-        let mut r = Synthesis::default();
         let flag = self.flag;
-        r.flag = flag;
-
-        let es = SynthEventResult::from_event(self)?;
-        r.id = es.id()?;
-        let reason = es.reason();
-        r.reason = reason;
-
-        if reason.intersects(Flags::Canceled) {
-            if es.code()?
-                == Result_CancellationErrorCode_CancellationErrorCode_NoError
-            {
-                return Ok(r);
-            } else {
-                return es.cancellation_error();
-            }
-        }
-
-        if reason.intersects(Flags::Synthesized | Flags::Synthesizing) {
-            let sz = es.audio_data_length()? as usize;
-            if sz != 0 {
-                let mut buf = Vec::with_capacity(sz);
-                unsafe { buf.set_len(sz) };
-                es.audio_data(&mut buf)?;
-                r.audio_data = buf;
-                r.audio_length = sz;
-            }
-        }
-
-        Ok(r)
+        SynthEventResult::from_event(self)?.synthesis(flag)
     }
 }
 
@@ -444,6 +916,14 @@ pub trait SpeechResult: RecognitionResult {
     fn text(&self) -> Result<String> {
         get_cf_string(result_get_text, self.handle(), 1024)
     }
+
+    /// Source language identified automatically, None if not enabled.
+    fn language(&self) -> Result<Option<String>> {
+        let lang = self.get_by_id(
+            PropertyId_SpeechServiceConnection_AutoDetectSourceLanguageResult,
+        )?;
+        Ok(if lang.is_empty() { None } else { Some(lang) })
+    }
 }
 
 /// Speech-to-text and intent recognition result.
@@ -566,17 +1046,21 @@ pub trait CancellationResult: AsrResult {
         self.get_by_id(PropertyId_SpeechServiceResponse_JsonErrorDetails)
     }
 
-    /// Convert cancellation to SpxError.
-    fn cancellation_error<T>(&self) -> Result<T> {
+    /// Reason, code and message of the cancellation.
+    fn cancellation(&self) -> Result<CancellationError> {
         let reason = self.cancellation_reason()?;
         let code = self.code()?;
         let details = self.error_details()?;
-        Err(CancellationError {
+        Ok(CancellationError {
             reason,
             code,
             details,
-        }
-        .into())
+        })
+    }
+
+    /// Convert cancellation to SpxError.
+    fn cancellation_error<T>(&self) -> Result<T> {
+        Err(self.cancellation()?.into())
     }
 }
 
@@ -629,6 +1113,11 @@ impl SynthEventResult {
 
     /// Consume the Event and create its result.
     pub fn from_event(evt: Event) -> Result<Self> {
+        SynthEventResult::from_event_ref(&evt)
+    }
+
+    /// Create the result of the Event, which is kept for others.
+    pub fn from_event_ref(evt: &Event) -> Result<Self> {
         let mut handle = INVALID_HANDLE;
         hr!(synthesizer_synthesis_event_get_result(
             evt.handle(),
@@ -663,6 +1152,38 @@ impl SynthEventResult {
         self.audio_data(&mut buf)?;
         Ok(buf)
     }
+
+    /// Output of the result, the flag tells the event source.
+    pub fn synthesis(&self, flag: Flags) -> Result<Synthesis> {
+        let mut r = Synthesis::default();
+        r.flag = flag;
+        r.id = self.id()?;
+        let reason = self.reason();
+        r.reason = reason;
+
+        if reason.intersects(Flags::Canceled) {
+            if self.code()?
+                == Result_CancellationErrorCode_CancellationErrorCode_NoError
+            {
+                return Ok(r);
+            } else {
+                return self.cancellation_error();
+            }
+        }
+
+        if reason.intersects(Flags::Synthesized | Flags::Synthesizing) {
+            let sz = self.audio_data_length()? as usize;
+            if sz != 0 {
+                let mut buf = Vec::with_capacity(sz);
+                unsafe { buf.set_len(sz) };
+                self.audio_data(&mut buf)?;
+                r.audio_data = buf;
+                r.audio_length = sz;
+            }
+        }
+
+        Ok(r)
+    }
 }
 
 FlattenProps!(SynthEventResult);
@@ -702,6 +1223,202 @@ pub struct Synthesis {
     pub reason: Flags,
     pub audio_length: usize,
     pub audio_data: Vec<u8>,
+    /// Format of the audio data, if known by the synthesizer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
 }
 
 impl ToJson for Synthesis {}
+
+/// Progress of the synthesis, reported before the audio is played.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum SynthesisMark {
+    /// Word is spoken from the offset of audio.
+    WordBoundary {
+        audio_offset: Duration,
+        /// Offset of the word in the input text or SSML, in characters.
+        text_offset: u32,
+        /// Length of the word in characters.
+        length: u32,
+        text: String,
+    },
+    /// Mouth posture from the offset of audio.
+    Viseme { audio_offset: Duration, id: u32 },
+    /// Bookmark of SSML is reached.
+    Bookmark {
+        audio_offset: Duration,
+        text: String,
+    },
+}
+
+impl SynthesisMark {
+    /// Extract the data of word boundary, viseme or bookmark event.
+    pub fn extract(mut evt: Event) -> Result<Self> {
+        let flag = evt.flag();
+        // Marks are not kept in owned events, a local one only carries the
+        // failure of extracting it.
+        if let Some(local) = evt.local.take() {
+            local?;
+            return Err(SpxError::Other(format!(
+                "{:?} is not synthesis mark",
+                flag
+            )));
+        }
+        let mut audio_offset = 0u64;
+        if flag.intersects(Flags::WordBoundary) {
+            let mut text_offset = 0;
+            let mut length = 0;
+            hr!(synthesizer_word_boundary_event_get_values(
+                evt.handle(),
+                &mut audio_offset,
+                &mut text_offset,
+                &mut length
+            ))?;
+            Ok(SynthesisMark::WordBoundary {
+                audio_offset: Duration::from_nanos(audio_offset * 100),
+                text_offset,
+                length,
+                text: synth_event_text(&evt)?,
+            })
+        } else if flag.intersects(Flags::Viseme) {
+            let mut id = 0;
+            hr!(synthesizer_viseme_event_get_values(
+                evt.handle(),
+                &mut audio_offset,
+                &mut id
+            ))?;
+            Ok(SynthesisMark::Viseme {
+                audio_offset: Duration::from_nanos(audio_offset * 100),
+                id,
+            })
+        } else if flag.intersects(Flags::BookmarkReached) {
+            hr!(synthesizer_bookmark_event_get_values(
+                evt.handle(),
+                &mut audio_offset
+            ))?;
+            Ok(SynthesisMark::Bookmark {
+                audio_offset: Duration::from_nanos(audio_offset * 100),
+                text: synth_event_text(&evt)?,
+            })
+        } else {
+            Err(SpxError::Other(format!("{:?} is not synthesis mark", flag)))
+        }
+    }
+
+    /// Offset of the mark in the synthesized audio.
+    pub fn audio_offset(&self) -> Duration {
+        match self {
+            SynthesisMark::WordBoundary { audio_offset, .. }
+            | SynthesisMark::Viseme { audio_offset, .. }
+            | SynthesisMark::Bookmark { audio_offset, .. } => *audio_offset,
+        }
+    }
+}
+
+impl ToJson for SynthesisMark {}
+
+/// Word or bookmark text of synthesis event.
+fn synth_event_text(evt: &Event) -> Result<String> {
+    unsafe {
+        let text = synthesizer_event_get_text(evt.handle());
+        if text.is_null() {
+            return Ok(String::new());
+        }
+        let s = CStr::from_ptr(text).to_string_lossy().into_owned();
+        property_bag_free_string(text);
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_cancellations_are_retryable() {
+        let error = |code| CancellationError {
+            reason: Result_CancellationReason_CancellationReason_Error,
+            code,
+            details: String::new(),
+        };
+        for code in &[
+            Result_CancellationErrorCode_CancellationErrorCode_TooManyRequests,
+            Result_CancellationErrorCode_CancellationErrorCode_ConnectionFailure,
+            Result_CancellationErrorCode_CancellationErrorCode_ServiceTimeout,
+            Result_CancellationErrorCode_CancellationErrorCode_ServiceError,
+            Result_CancellationErrorCode_CancellationErrorCode_ServiceUnavailable,
+        ] {
+            assert!(error(*code).is_retryable(), "{}", code);
+        }
+        for code in &[
+            Result_CancellationErrorCode_CancellationErrorCode_NoError,
+            Result_CancellationErrorCode_CancellationErrorCode_AuthenticationFailure,
+            Result_CancellationErrorCode_CancellationErrorCode_BadRequest,
+            Result_CancellationErrorCode_CancellationErrorCode_Forbidden,
+            Result_CancellationErrorCode_CancellationErrorCode_RuntimeError,
+        ] {
+            assert!(!error(*code).is_retryable(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn recognition_without_text_keeps_fields_empty() {
+        let reco = Recognition {
+            flag: Flags::Recognized,
+            reason: Some(Flags::Intent),
+            intent: Some(String::from("HomeAutomation.TurnOn")),
+            ..Recognition::default()
+        };
+        let owned = OwnedEvent::from(reco);
+        let json = serde_json::to_value(&owned).unwrap();
+        assert!(json.get("text").is_none());
+        assert!(json.get("offset").is_none());
+
+        let back = owned.into_recognition().unwrap();
+        assert_eq!(back.text, None);
+        assert_eq!(back.offset, None);
+        assert_eq!(back.duration, None);
+        match back.matching {
+            Some(Matching::Matched) => {}
+            other => panic!("unexpected matching: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recognition_round_trips_text_and_timing() {
+        let reco = Recognition {
+            flag: Flags::Recognized,
+            session: String::from("s1"),
+            reason: Some(Flags::Recognized),
+            offset: Some(Duration::from_millis(500)),
+            duration: Some(Duration::from_millis(1200)),
+            text: Some(String::from("hello")),
+            ..Recognition::default()
+        };
+        let json = serde_json::to_string(&OwnedEvent::from(reco)).unwrap();
+        let owned: OwnedEvent = serde_json::from_str(&json).unwrap();
+        let back = owned.into_recognition().unwrap();
+        assert_eq!(back.session, "s1");
+        assert_eq!(back.text.as_ref().unwrap(), "hello");
+        assert_eq!(back.offset, Some(Duration::from_millis(500)));
+        assert_eq!(back.duration, Some(Duration::from_millis(1200)));
+    }
+
+    #[test]
+    fn mark_flags_are_parsed() {
+        let flags: Flags =
+            "WordBoundary | Viseme | BookmarkReached".parse().unwrap();
+        assert_eq!(flags, Flags::SynthesisMark);
+        for flag in
+            &[Flags::WordBoundary, Flags::Viseme, Flags::BookmarkReached]
+        {
+            let json = serde_json::to_string(flag).unwrap();
+            let back: Flags = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, *flag);
+        }
+        let flags: Flags =
+            serde_json::from_str("\"Synthesized | BookmarkReached\"").unwrap();
+        assert_eq!(flags, Flags::Synthesized | Flags::BookmarkReached);
+    }
+}
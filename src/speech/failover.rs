@@ -0,0 +1,797 @@
+//! Failover of the configuration over several regions or endpoints.
+//!
+//! Candidates are tried in order. Each one has a circuit breaker which is
+//! opened by authentication, quota or connection failures, and is probed
+//! again after a cool down.
+//!
+//! Most failures only show up once the session runs, as a canceled event.
+//! [`FailoverConfig::stream`] reports them through the lease and rebuilds
+//! on the next candidate; with [`FailoverConfig::build`] the caller does it
+//! by passing every event to [`Lease::observe`] and building again when it
+//! returns true.
+
+use super::{
+    builder::RecognizerConfig,
+    events::{CancellationError, OwnedEvent},
+    recognizer::Recognizer,
+    synthesizer::Synthesizer,
+};
+use crate::{
+    error::{Other, Poisoned},
+    speech_api::*,
+    Result, SpxError,
+};
+use futures::{Async, Poll, Stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Credential of one region or endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Candidate {
+    Region {
+        subscription: String,
+        region: String,
+    },
+    Endpoint {
+        endpoint: String,
+        subscription: String,
+    },
+}
+
+impl Candidate {
+    /// Name shown in health states.
+    pub fn name(&self) -> &str {
+        match self {
+            Candidate::Region { region, .. } => region,
+            Candidate::Endpoint { endpoint, .. } => endpoint,
+        }
+    }
+
+    /// Create the configuration bound to the candidate.
+    pub fn config(&self) -> Result<RecognizerConfig> {
+        match self {
+            Candidate::Region {
+                subscription,
+                region,
+            } => RecognizerConfig::from_subscription(subscription, region),
+            Candidate::Endpoint {
+                endpoint,
+                subscription,
+            } => RecognizerConfig::from_endpoint(endpoint, subscription),
+        }
+    }
+}
+
+/// Thresholds of the circuit breakers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverPolicy {
+    /// Connection failures in a row to open the circuit.
+    pub failure_threshold: u32,
+    /// Time before the open circuit is probed again.
+    pub cool_down: Duration,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        FailoverPolicy {
+            failure_threshold: 3,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+/// State of the circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CircuitState {
+    /// Healthy, requests go through.
+    Closed,
+    /// Failed, skipped until the cool down ends.
+    Open,
+    /// Cool down ended, the next request probes it. Other requests skip it
+    /// until the probe is reported, or for another cool down.
+    HalfOpen,
+}
+
+/// Kind of failure reported by the service.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FailureKind {
+    Authentication,
+    Quota,
+    Connection,
+}
+
+impl FailureKind {
+    /// Failures caused by the candidate, others are not counted.
+    pub fn from_error(err: &CancellationError) -> Option<Self> {
+        match err.code() {
+            Result_CancellationErrorCode_CancellationErrorCode_AuthenticationFailure
+            | Result_CancellationErrorCode_CancellationErrorCode_Forbidden => {
+                Some(FailureKind::Authentication)
+            }
+            Result_CancellationErrorCode_CancellationErrorCode_TooManyRequests => {
+                Some(FailureKind::Quota)
+            }
+            Result_CancellationErrorCode_CancellationErrorCode_ConnectionFailure
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceTimeout
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceError
+            | Result_CancellationErrorCode_CancellationErrorCode_ServiceUnavailable => {
+                Some(FailureKind::Connection)
+            }
+            _ => None,
+        }
+    }
+
+    /// Failure of the candidate carried by the error, None for local errors
+    /// such as invalid configuration.
+    pub fn from_spx_error(err: &SpxError) -> Option<Self> {
+        match err {
+            SpxError::Cancellation(value) => {
+                serde_json::from_value::<CancellationError>(value.clone())
+                    .ok()
+                    .and_then(|err| FailureKind::from_error(&err))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Health of a candidate visible to the caller.
+#[derive(Debug, Clone, Serialize)]
+pub struct CandidateHealth {
+    pub name: String,
+    pub state: CircuitState,
+    /// Failures in a row.
+    pub failures: u32,
+    pub last_failure: Option<FailureKind>,
+    pub last_error: Option<String>,
+    /// Time left before an open circuit is probed.
+    pub retry_in: Option<Duration>,
+}
+
+struct Breaker {
+    state: CircuitState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    last_failure: Option<FailureKind>,
+    last_error: Option<String>,
+    probed_at: Option<Instant>,
+}
+
+impl Breaker {
+    fn new() -> Self {
+        Breaker {
+            state: CircuitState::Closed,
+            failures: 0,
+            opened_at: None,
+            last_failure: None,
+            last_error: None,
+            probed_at: None,
+        }
+    }
+
+    /// Move open circuit to half open after the cool down.
+    fn refresh(&mut self, policy: &FailoverPolicy) {
+        if let (CircuitState::Open, Some(at)) = (self.state, self.opened_at) {
+            if at.elapsed() >= policy.cool_down {
+                self.state = CircuitState::HalfOpen;
+            }
+        }
+    }
+
+    /// Take the candidate for a request. A half open one is given to a
+    /// single probe at a time, an unreported probe expires after the cool
+    /// down.
+    fn acquire(&mut self, policy: &FailoverPolicy) -> bool {
+        self.refresh(policy);
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                let busy = self
+                    .probed_at
+                    .map_or(false, |at| at.elapsed() < policy.cool_down);
+                if !busy {
+                    self.probed_at = Some(Instant::now());
+                }
+                !busy
+            }
+        }
+    }
+
+    fn succeed(&mut self) {
+        self.state = CircuitState::Closed;
+        self.failures = 0;
+        self.opened_at = None;
+        self.probed_at = None;
+    }
+
+    fn fail(&mut self, kind: FailureKind, msg: &str, policy: &FailoverPolicy) {
+        self.probed_at = None;
+        self.failures += 1;
+        self.last_failure = Some(kind);
+        self.last_error = Some(msg.to_string());
+        let open = match kind {
+            FailureKind::Authentication | FailureKind::Quota => true,
+            FailureKind::Connection => {
+                self.state == CircuitState::HalfOpen
+                    || self.failures >= policy.failure_threshold
+            }
+        };
+        if open {
+            self.state = CircuitState::Open;
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+struct Health {
+    policy: FailoverPolicy,
+    breakers: Vec<Breaker>,
+}
+
+/// Shared settings applied to the configuration of every candidate.
+type Prepare = dyn Fn(&mut RecognizerConfig) -> Result + Send + Sync;
+
+/// Configuration over ordered candidates of regions or endpoints.
+#[derive(Clone)]
+pub struct FailoverConfig {
+    candidates: Vec<Candidate>,
+    health: Arc<Mutex<Health>>,
+    prepare: Option<Arc<Prepare>>,
+}
+
+impl FailoverConfig {
+    /// Candidates in order of preference.
+    pub fn new(candidates: Vec<Candidate>, policy: FailoverPolicy) -> Self {
+        let breakers = candidates.iter().map(|_| Breaker::new()).collect();
+        FailoverConfig {
+            candidates,
+            health: Arc::new(Mutex::new(Health { policy, breakers })),
+            prepare: None,
+        }
+    }
+
+    /// Apply common settings, e.g. language or audio, to the configuration
+    /// of the selected candidate.
+    pub fn set_prepare<F>(&mut self, prepare: F) -> &mut Self
+    where
+        F: Fn(&mut RecognizerConfig) -> Result + Send + Sync + 'static,
+    {
+        self.prepare = Some(Arc::new(prepare));
+        self
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// Circuit states of the candidates in order.
+    pub fn health(&self) -> Result<Vec<CandidateHealth>> {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        let Health { policy, breakers } = &mut *health;
+        let states = self
+            .candidates
+            .iter()
+            .zip(breakers.iter_mut())
+            .map(|(c, b)| {
+                b.refresh(policy);
+                CandidateHealth {
+                    name: c.name().to_string(),
+                    state: b.state,
+                    failures: b.failures,
+                    last_failure: b.last_failure,
+                    last_error: b.last_error.clone(),
+                    retry_in: match (b.state, b.opened_at) {
+                        (CircuitState::Open, Some(at)) => {
+                            policy.cool_down.checked_sub(at.elapsed())
+                        }
+                        _ => None,
+                    },
+                }
+            })
+            .collect();
+        Ok(states)
+    }
+
+    /// Order of the candidates to try: closed ones with fewer failures first,
+    /// then the half open ones.
+    fn ranking(&self) -> Result<Vec<usize>> {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        let Health { policy, breakers } = &mut *health;
+        let mut ranks: Vec<(u32, u32, usize)> = breakers
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, b)| {
+                b.refresh(policy);
+                match b.state {
+                    CircuitState::Closed => Some((0, b.failures, i)),
+                    CircuitState::HalfOpen => Some((1, b.failures, i)),
+                    CircuitState::Open => None,
+                }
+            })
+            .collect();
+        ranks.sort();
+        Ok(ranks.into_iter().map(|(_, _, i)| i).collect())
+    }
+
+    /// Configuration of the healthiest candidate.
+    pub fn select(&self) -> Result<(RecognizerConfig, Lease)> {
+        self.build(Ok)
+    }
+
+    /// Take the candidate for a request, false if it can't be used now.
+    fn acquire(&self, index: usize) -> Result<bool> {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        let Health { policy, breakers } = &mut *health;
+        Ok(breakers[index].acquire(policy))
+    }
+
+    /// Build with the healthiest candidate, fall back to the next one if the
+    /// build fails by the candidate. Local errors are returned at once
+    /// without counting them against the candidate.
+    ///
+    /// Building rarely reaches the service, so the failures of the session
+    /// have to be reported by [`Lease::observe`] on its events, and the
+    /// object built again when it asks to switch. [`stream`] does both.
+    ///
+    /// [`stream`]: #method.stream
+    pub fn build<T, F>(&self, f: F) -> Result<(T, Lease)>
+    where
+        F: Fn(RecognizerConfig) -> Result<T>,
+    {
+        let mut last_err = None;
+        for index in self.ranking()? {
+            if !self.acquire(index)? {
+                continue;
+            }
+            let lease = Lease {
+                index,
+                name: self.candidates[index].name().to_string(),
+                health: self.health.clone(),
+            };
+            let built = self.candidates[index].config().and_then(|mut c| {
+                if let Some(ref prepare) = self.prepare {
+                    prepare(&mut c)?;
+                }
+                f(c)
+            });
+            let err = match built {
+                Ok(v) => return Ok((v, lease)),
+                Err(err) => err,
+            };
+            match FailureKind::from_spx_error(&err) {
+                Some(kind) => {
+                    log::warn!("candidate {} failed: {}", lease.name, err);
+                    lease.report(kind, &err.to_string())?;
+                    last_err = Some(err);
+                }
+                None => {
+                    lease.release()?;
+                    return Err(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            Other(String::from("no healthy candidate is available"))
+        }))
+    }
+
+    /// Speech recognizer of the healthiest candidate.
+    pub fn recognizer(&self) -> Result<(Recognizer, Lease)> {
+        self.build(|c| c.recognizer())
+    }
+
+    /// Intent recognizer of the healthiest candidate.
+    pub fn intent_recognizer(&self) -> Result<(Recognizer, Lease)> {
+        self.build(|c| c.intent_recognizer())
+    }
+
+    /// Translator of the healthiest candidate.
+    pub fn translator(&self) -> Result<(Recognizer, Lease)> {
+        self.build(|c| c.translator())
+    }
+
+    /// Synthesizer of the healthiest candidate.
+    pub fn synthesizer(&self) -> Result<(Synthesizer, Lease)> {
+        self.build(|c| c.synthesizer())
+    }
+
+    /// Events of the object opened by `open`, e.g. a recognizer and its
+    /// started stream, on the healthiest candidate. When the candidate
+    /// fails the object is opened again on the next one and its events
+    /// follow. The last cancellation is passed on if no candidate is left.
+    pub fn stream<T, S, F>(&self, open: F) -> Result<FailoverStream<T, S, F>>
+    where
+        S: Stream<Item = OwnedEvent, Error = SpxError>,
+        F: Fn(RecognizerConfig) -> Result<(T, S)>,
+    {
+        let ((inner, events), lease) = self.build(&open)?;
+        Ok(FailoverStream {
+            config: self.clone(),
+            open,
+            inner,
+            events,
+            lease,
+        })
+    }
+}
+
+/// Events of the opened object, reopened on the next candidate when the
+/// current one fails.
+pub struct FailoverStream<T, S, F> {
+    config: FailoverConfig,
+    open: F,
+    inner: T,
+    events: S,
+    lease: Lease,
+}
+
+impl<T, S, F> FailoverStream<T, S, F>
+where
+    S: Stream<Item = OwnedEvent, Error = SpxError>,
+    F: Fn(RecognizerConfig) -> Result<(T, S)>,
+{
+    /// Object opened on the current candidate.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Object opened on the current candidate, e.g. to write its push
+    /// stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Lease of the current candidate.
+    pub fn lease(&self) -> &Lease {
+        &self.lease
+    }
+
+    /// Open the object on the next healthy candidate.
+    fn switch(&mut self) -> Result {
+        let ((inner, events), lease) = self.config.build(&self.open)?;
+        log::warn!(
+            "candidate {} takes over from {}",
+            lease.name,
+            self.lease.name
+        );
+        self.inner = inner;
+        self.events = events;
+        self.lease = lease;
+        Ok(())
+    }
+}
+
+impl<T, S, F> Stream for FailoverStream<T, S, F>
+where
+    S: Stream<Item = OwnedEvent, Error = SpxError>,
+    F: Fn(RecognizerConfig) -> Result<(T, S)>,
+{
+    type Item = OwnedEvent;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<OwnedEvent>, SpxError> {
+        loop {
+            let evt = match self.events.poll() {
+                Ok(Async::Ready(Some(evt))) => evt,
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => match FailureKind::from_spx_error(&err) {
+                    Some(kind) => {
+                        self.lease.report(kind, &err.to_string())?;
+                        self.switch().map_err(|_| err)?;
+                        continue;
+                    }
+                    None => return Err(err),
+                },
+            };
+            if !self.lease.observe(&evt)? {
+                return Ok(Async::Ready(Some(evt)));
+            }
+            if let Err(err) = self.switch() {
+                log::error!("failover gives up: {}", err);
+                return Ok(Async::Ready(Some(evt)));
+            }
+        }
+    }
+}
+
+/// Link of the built object to its candidate, to report the outcomes.
+#[derive(Clone)]
+pub struct Lease {
+    index: usize,
+    name: String,
+    health: Arc<Mutex<Health>>,
+}
+
+impl Lease {
+    /// Name of the candidate.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Mark the candidate healthy.
+    pub fn succeed(&self) -> Result {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        health.breakers[self.index].succeed();
+        Ok(())
+    }
+
+    /// Give back an unused probe of the candidate.
+    fn release(&self) -> Result {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        health.breakers[self.index].probed_at = None;
+        Ok(())
+    }
+
+    /// Count a failure of the candidate.
+    pub fn report(&self, kind: FailureKind, msg: &str) -> Result {
+        let mut health = self.health.lock().map_err(|_| Poisoned)?;
+        let Health { policy, breakers } = &mut *health;
+        breakers[self.index].fail(kind, msg, policy);
+        Ok(())
+    }
+
+    /// Track the outcome by the event. Return true if the candidate failed
+    /// and the caller should switch to another one.
+    pub fn observe(&self, evt: &OwnedEvent) -> Result<bool> {
+        match evt {
+            OwnedEvent::Canceled {
+                error: Some(err), ..
+            } => match FailureKind::from_error(err) {
+                Some(kind) => {
+                    self.report(kind, err.details())?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+            OwnedEvent::Recognition { .. } | OwnedEvent::Synthesis { .. } => {
+                self.succeed()?;
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech::events::Flags;
+    use futures::stream;
+    use serde_json::json;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    fn cancellation(code: Result_CancellationErrorCode) -> CancellationError {
+        serde_json::from_value(json!({
+            "reason": Result_CancellationReason_CancellationReason_Error,
+            "code": code,
+            "details": "refused",
+        }))
+        .unwrap()
+    }
+
+    fn canceled(code: Result_CancellationErrorCode) -> SpxError {
+        cancellation(code).into()
+    }
+
+    fn canceled_event(code: Result_CancellationErrorCode) -> OwnedEvent {
+        OwnedEvent::Canceled {
+            flag: Flags::Canceled,
+            session: String::from("session"),
+            id: String::from("id"),
+            reason: Flags::Canceled,
+            error: Some(cancellation(code)),
+        }
+    }
+
+    fn recognized(text: &str) -> OwnedEvent {
+        OwnedEvent::Recognition {
+            flag: Flags::Recognized,
+            session: String::from("session"),
+            id: String::from("id"),
+            reason: Flags::Recognized,
+            offset: None,
+            duration: None,
+            text: Some(text.to_string()),
+            intent: None,
+            details: None,
+            translations: None,
+            language: None,
+        }
+    }
+
+    fn text(evt: &OwnedEvent) -> Option<&str> {
+        match evt {
+            OwnedEvent::Recognition { text, .. } => {
+                text.as_ref().map(|t| &t[..])
+            }
+            _ => None,
+        }
+    }
+
+    fn failover_with(policy: FailoverPolicy) -> FailoverConfig {
+        let candidate = |region: &str| Candidate::Region {
+            subscription: String::from("key"),
+            region: region.to_string(),
+        };
+        FailoverConfig::new(
+            vec![candidate("westus"), candidate("eastus")],
+            policy,
+        )
+    }
+
+    fn failover() -> FailoverConfig {
+        failover_with(FailoverPolicy::default())
+    }
+
+    #[test]
+    fn failure_kind_of_errors() {
+        assert_eq!(
+            FailureKind::from_spx_error(&canceled(
+                Result_CancellationErrorCode_CancellationErrorCode_ServiceTimeout
+            )),
+            Some(FailureKind::Connection)
+        );
+        assert_eq!(
+            FailureKind::from_spx_error(&canceled(
+                Result_CancellationErrorCode_CancellationErrorCode_Forbidden
+            )),
+            Some(FailureKind::Authentication)
+        );
+        assert_eq!(
+            FailureKind::from_spx_error(&canceled(
+                Result_CancellationErrorCode_CancellationErrorCode_BadRequest
+            )),
+            None
+        );
+        let local = Other(String::from("invalid audio format"));
+        assert_eq!(FailureKind::from_spx_error(&local), None);
+    }
+
+    #[test]
+    fn candidate_failure_falls_over() {
+        let config = failover();
+        let calls = AtomicUsize::new(0);
+        let (_, lease) = config
+            .build(|_| match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(canceled(
+                    Result_CancellationErrorCode_CancellationErrorCode_TooManyRequests,
+                )),
+                _ => Ok(()),
+            })
+            .unwrap();
+        assert_eq!(lease.name(), "eastus");
+        let health = config.health().unwrap();
+        assert_eq!(health[0].state, CircuitState::Open);
+        assert_eq!(health[0].last_failure, Some(FailureKind::Quota));
+        assert_eq!(health[1].failures, 0);
+    }
+
+    #[test]
+    fn local_error_is_returned_at_once() {
+        let config = failover();
+        let calls = AtomicUsize::new(0);
+        let result = config.build(|_| -> Result {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(Other(String::from("invalid audio format")))
+        });
+        match result {
+            Err(Other(msg)) => assert_eq!(msg, "invalid audio format"),
+            _ => panic!("local error is not returned"),
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for health in config.health().unwrap() {
+            assert_eq!(health.state, CircuitState::Closed);
+            assert_eq!(health.failures, 0);
+            assert_eq!(health.last_failure, None);
+        }
+    }
+
+    #[test]
+    fn observed_failure_rebuilds() {
+        let config = failover();
+        let (_, lease) = config.select().unwrap();
+        assert_eq!(lease.name(), "westus");
+        assert!(!lease.observe(&recognized("hello")).unwrap());
+        let evt = canceled_event(
+            Result_CancellationErrorCode_CancellationErrorCode_AuthenticationFailure,
+        );
+        assert!(lease.observe(&evt).unwrap());
+        let (_, lease) = config.select().unwrap();
+        assert_eq!(lease.name(), "eastus");
+        assert_eq!(config.health().unwrap()[0].state, CircuitState::Open);
+    }
+
+    #[test]
+    fn stream_switches_candidates() {
+        let config = failover();
+        let calls = AtomicUsize::new(0);
+        let stream = config
+            .stream(|_| {
+                let events = match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => vec![
+                        recognized("one"),
+                        canceled_event(
+                            Result_CancellationErrorCode_CancellationErrorCode_TooManyRequests,
+                        ),
+                    ],
+                    _ => vec![recognized("two"), recognized("three")],
+                };
+                Ok(((), stream::iter_ok(events)))
+            })
+            .unwrap();
+        assert_eq!(stream.lease().name(), "westus");
+        let mut stream = stream.wait();
+        let mut texts = Vec::new();
+        while let Some(evt) = stream.next() {
+            texts.push(text(&evt.unwrap()).unwrap().to_string());
+        }
+        assert_eq!(texts, vec!["one", "two", "three"]);
+        assert_eq!(stream.get_ref().lease().name(), "eastus");
+        let health = config.health().unwrap();
+        assert_eq!(health[0].last_failure, Some(FailureKind::Quota));
+        assert_eq!(health[1].state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn stream_passes_last_cancellation() {
+        let config = failover();
+        let stream = config
+            .stream(|_| {
+                let events = vec![canceled_event(
+                    Result_CancellationErrorCode_CancellationErrorCode_Forbidden,
+                )];
+                Ok(((), stream::iter_ok(events)))
+            })
+            .unwrap();
+        let events: Vec<_> = stream.wait().map(|evt| evt.unwrap()).collect();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            OwnedEvent::Canceled { .. } => (),
+            evt => panic!("unexpected event {:?}", evt),
+        }
+        for health in config.health().unwrap() {
+            assert_eq!(health.state, CircuitState::Open);
+        }
+    }
+
+    #[test]
+    fn half_open_takes_one_probe() {
+        let config = failover_with(FailoverPolicy {
+            cool_down: Duration::from_millis(100),
+            ..FailoverPolicy::default()
+        });
+        for _ in 0..2 {
+            let (_, lease) = config.select().unwrap();
+            lease.report(FailureKind::Quota, "throttled").unwrap();
+        }
+        thread::sleep(Duration::from_millis(150));
+        let (_, west) = config.select().unwrap();
+        assert_eq!(west.name(), "westus");
+        let result = config.build(|_| -> Result {
+            Err(Other(String::from("invalid audio format")))
+        });
+        assert!(result.is_err());
+        let (_, east) = config.select().unwrap();
+        assert_eq!(east.name(), "eastus");
+        match config.select() {
+            Err(Other(msg)) => {
+                assert_eq!(msg, "no healthy candidate is available")
+            }
+            _ => panic!("probe is handed out twice"),
+        }
+        west.succeed().unwrap();
+        let (_, lease) = config.select().unwrap();
+        assert_eq!(lease.name(), "westus");
+        assert_eq!(config.health().unwrap()[1].state, CircuitState::HalfOpen);
+    }
+}
@@ -0,0 +1,503 @@
+//! Output formats of the synthesized audio and framing of audio files.
+
+use super::audio::AudioSpec;
+use crate::{Result, SpxError};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{borrow::Cow, fmt, fs, path::Path, str::FromStr};
+
+/// Format tag of mu-law in WAV header.
+const WAVE_FORMAT_MULAW: u16 = 7;
+
+/// Size of canonical PCM WAV header.
+pub(crate) const WAV_HEADER_SIZE: u32 = 44;
+
+/// Data size in WAV header of unknown length.
+const STREAM_LENGTH: u32 = u32::max_value() - WAV_HEADER_SIZE;
+
+/// Output format of the synthesizer, in values of the SDK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Raw8Khz8BitMonoMULaw = 1,
+    Riff16Khz16KbpsMonoSiren = 2,
+    Audio16Khz16KbpsMonoSiren = 3,
+    Audio16Khz32KBitRateMonoMp3 = 4,
+    Audio16Khz128KBitRateMonoMp3 = 5,
+    Audio16Khz64KBitRateMonoMp3 = 6,
+    Audio24Khz48KBitRateMonoMp3 = 7,
+    Audio24Khz96KBitRateMonoMp3 = 8,
+    Audio24Khz160KBitRateMonoMp3 = 9,
+    Raw16Khz16BitMonoTrueSilk = 10,
+    Riff16Khz16BitMonoPcm = 11,
+    Riff8Khz16BitMonoPcm = 12,
+    Riff24Khz16BitMonoPcm = 13,
+    Riff8Khz8BitMonoMULaw = 14,
+    Raw16Khz16BitMonoPcm = 15,
+    Raw24Khz16BitMonoPcm = 16,
+    Raw8Khz16BitMonoPcm = 17,
+    Ogg16Khz16BitMonoOpus = 18,
+    Ogg24Khz16BitMonoOpus = 19,
+}
+
+/// How the audio data is framed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Container {
+    /// WAV with RIFF header.
+    Riff,
+    /// Bare samples or frames.
+    Raw,
+    Ogg,
+    Mp3,
+}
+
+/// Codec of the audio data.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Encoding {
+    Pcm,
+    MuLaw,
+    Siren,
+    Silk,
+    Opus,
+    Mp3,
+}
+
+impl OutputFormat {
+    /// All the formats in values of the SDK.
+    pub fn all() -> &'static [OutputFormat] {
+        use OutputFormat::*;
+        &[
+            Raw8Khz8BitMonoMULaw,
+            Riff16Khz16KbpsMonoSiren,
+            Audio16Khz16KbpsMonoSiren,
+            Audio16Khz32KBitRateMonoMp3,
+            Audio16Khz128KBitRateMonoMp3,
+            Audio16Khz64KBitRateMonoMp3,
+            Audio24Khz48KBitRateMonoMp3,
+            Audio24Khz96KBitRateMonoMp3,
+            Audio24Khz160KBitRateMonoMp3,
+            Raw16Khz16BitMonoTrueSilk,
+            Riff16Khz16BitMonoPcm,
+            Riff8Khz16BitMonoPcm,
+            Riff24Khz16BitMonoPcm,
+            Riff8Khz8BitMonoMULaw,
+            Raw16Khz16BitMonoPcm,
+            Raw24Khz16BitMonoPcm,
+            Raw8Khz16BitMonoPcm,
+            Ogg16Khz16BitMonoOpus,
+            Ogg24Khz16BitMonoOpus,
+        ]
+    }
+
+    /// Value of the SDK.
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        OutputFormat::all().iter().cloned().find(|f| f.id() == id)
+    }
+
+    /// Name of the format in the service, e.g. "riff-16khz-16bit-mono-pcm".
+    pub fn name(self) -> &'static str {
+        use OutputFormat::*;
+        match self {
+            Raw8Khz8BitMonoMULaw => "raw-8khz-8bit-mono-mulaw",
+            Riff16Khz16KbpsMonoSiren => "riff-16khz-16kbps-mono-siren",
+            Audio16Khz16KbpsMonoSiren => "audio-16khz-16kbps-mono-siren",
+            Audio16Khz32KBitRateMonoMp3 => "audio-16khz-32kbitrate-mono-mp3",
+            Audio16Khz128KBitRateMonoMp3 => "audio-16khz-128kbitrate-mono-mp3",
+            Audio16Khz64KBitRateMonoMp3 => "audio-16khz-64kbitrate-mono-mp3",
+            Audio24Khz48KBitRateMonoMp3 => "audio-24khz-48kbitrate-mono-mp3",
+            Audio24Khz96KBitRateMonoMp3 => "audio-24khz-96kbitrate-mono-mp3",
+            Audio24Khz160KBitRateMonoMp3 => "audio-24khz-160kbitrate-mono-mp3",
+            Raw16Khz16BitMonoTrueSilk => "raw-16khz-16bit-mono-truesilk",
+            Riff16Khz16BitMonoPcm => "riff-16khz-16bit-mono-pcm",
+            Riff8Khz16BitMonoPcm => "riff-8khz-16bit-mono-pcm",
+            Riff24Khz16BitMonoPcm => "riff-24khz-16bit-mono-pcm",
+            Riff8Khz8BitMonoMULaw => "riff-8khz-8bit-mono-mulaw",
+            Raw16Khz16BitMonoPcm => "raw-16khz-16bit-mono-pcm",
+            Raw24Khz16BitMonoPcm => "raw-24khz-16bit-mono-pcm",
+            Raw8Khz16BitMonoPcm => "raw-8khz-16bit-mono-pcm",
+            Ogg16Khz16BitMonoOpus => "ogg-16khz-16bit-mono-opus",
+            Ogg24Khz16BitMonoOpus => "ogg-24khz-16bit-mono-opus",
+        }
+    }
+
+    pub fn container(self) -> Container {
+        match self.name().split('-').next() {
+            Some("riff") => Container::Riff,
+            Some("ogg") => Container::Ogg,
+            _ if self.encoding() == Encoding::Mp3 => Container::Mp3,
+            _ => Container::Raw,
+        }
+    }
+
+    pub fn encoding(self) -> Encoding {
+        match self.name().rsplit('-').next() {
+            Some("pcm") => Encoding::Pcm,
+            Some("mulaw") => Encoding::MuLaw,
+            Some("siren") => Encoding::Siren,
+            Some("truesilk") => Encoding::Silk,
+            Some("opus") => Encoding::Opus,
+            _ => Encoding::Mp3,
+        }
+    }
+
+    /// Samples per second.
+    pub fn sample_rate(self) -> u32 {
+        match self.name().split('-').nth(1) {
+            Some("8khz") => 8_000,
+            Some("24khz") => 24_000,
+            _ => 16_000,
+        }
+    }
+
+    /// Sample layout of PCM and mu-law formats.
+    pub fn spec(self) -> Option<AudioSpec> {
+        let bits = match self.encoding() {
+            Encoding::Pcm => 16,
+            Encoding::MuLaw => 8,
+            _ => return None,
+        };
+        Some(AudioSpec {
+            rate: self.sample_rate(),
+            bits,
+            channels: 1,
+        })
+    }
+
+    /// Extension of the framed file.
+    pub fn extension(self) -> &'static str {
+        match (self.container(), self.encoding()) {
+            (Container::Riff, _)
+            | (Container::Raw, Encoding::Pcm)
+            | (Container::Raw, Encoding::MuLaw) => "wav",
+            (Container::Ogg, _) => "ogg",
+            (Container::Mp3, _) => "mp3",
+            (Container::Raw, _) => "raw",
+        }
+    }
+
+    /// Frame the audio data as a file. Bare PCM and mu-law samples get a WAV
+    /// header, and the sizes of an existing RIFF header are fixed, e.g. of
+    /// streamed audio. Others are framed already.
+    pub fn frame<'a>(self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if data.starts_with(b"RIFF") && data.len() >= 12 {
+            let mut framed = data.to_vec();
+            let riff_size = data.len() as u32 - 8;
+            framed[4..8].copy_from_slice(&riff_size.to_le_bytes());
+            if let Some(pos) = data_chunk(data) {
+                let data_size = (data.len() - pos - 8) as u32;
+                framed[pos + 4..pos + 8]
+                    .copy_from_slice(&data_size.to_le_bytes());
+            }
+            return Cow::Owned(framed);
+        }
+        match self.wav_header(data.len() as u32) {
+            Some(mut framed) => {
+                framed.extend_from_slice(data);
+                Cow::Owned(framed)
+            }
+            None => Cow::Borrowed(data),
+        }
+    }
+
+    /// WAV header of PCM and mu-law formats for the data length.
+    pub fn wav_header(self, length: u32) -> Option<Vec<u8>> {
+        let mut header = wav_header(&self.spec()?, length);
+        if self.encoding() == Encoding::MuLaw {
+            header[20..22].copy_from_slice(&WAVE_FORMAT_MULAW.to_le_bytes());
+        }
+        Some(header)
+    }
+
+    /// WAV header of unknown length for streaming, None if the format is
+    /// not RIFF.
+    pub fn stream_header(self) -> Option<Vec<u8>> {
+        if self.container() != Container::Riff {
+            return None;
+        }
+        self.wav_header(STREAM_LENGTH)
+    }
+
+    /// Write the audio data framed as a file.
+    pub fn write_file<P: AsRef<Path>>(self, path: P, data: &[u8]) -> Result {
+        fs::write(path, self.frame(data))?;
+        Ok(())
+    }
+}
+
+/// Default format of the service.
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Riff16Khz16BitMonoPcm
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parse the name in the service, the variant name or the value of SDK.
+impl FromStr for OutputFormat {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        OutputFormat::all()
+            .iter()
+            .cloned()
+            .find(|f| {
+                f.name().eq_ignore_ascii_case(s)
+                    || format!("{:?}", f).eq_ignore_ascii_case(s)
+            })
+            .or_else(|| s.parse().ok().and_then(OutputFormat::from_id))
+            .ok_or_else(|| {
+                SpxError::InvalidProperty(
+                    String::from("synth_output_format"),
+                    s.to_string(),
+                )
+            })
+    }
+}
+
+impl Serialize for OutputFormat {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputFormat {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Canonical 44 bytes header of PCM WAV.
+pub fn wav_header(spec: &AudioSpec, length: u32) -> Vec<u8> {
+    let channels = u16::from(spec.channels);
+    let bits = u16::from(spec.bits);
+    let block_align = channels * bits / 8;
+    let byte_rate = spec.rate * u32::from(block_align);
+    let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(length + WAV_HEADER_SIZE - 8).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&spec.rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&length.to_le_bytes());
+    header
+}
+
+/// Samples of RIFF data without the header, others are returned as is.
+pub fn strip_riff_header(data: &[u8]) -> &[u8] {
+    if !data.starts_with(b"RIFF") || data.len() < 12 {
+        return data;
+    }
+    match data_chunk(data) {
+        Some(pos) => &data[pos + 8..],
+        None => &data[data.len()..],
+    }
+}
+
+/// Position of the data chunk in RIFF data, found by walking the chunks
+/// after "WAVE".
+fn data_chunk(data: &[u8]) -> Option<usize> {
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        if &data[pos..pos + 4] == b"data" {
+            return Some(pos);
+        }
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&data[pos + 4..pos + 8]);
+        let size = u32::from_le_bytes(size) as usize;
+        pos = pos.checked_add(8 + size + size % 2)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, io::Cursor, process};
+
+    /// Samples of 16 bits PCM.
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn read_pcm(file: &[u8]) -> (hound::WavSpec, Vec<i16>) {
+        let mut reader = hound::WavReader::new(Cursor::new(file)).unwrap();
+        let samples = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        (reader.spec(), samples)
+    }
+
+    fn le32(data: &[u8], pos: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[pos..pos + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// RIFF data of 8 kHz PCM with a LIST chunk before the data chunk, and
+    /// the sizes of unknown length as streamed.
+    fn streamed_with_list(samples: &[i16]) -> Vec<u8> {
+        let header =
+            OutputFormat::Riff8Khz16BitMonoPcm.stream_header().unwrap();
+        let mut data = header[..36].to_vec();
+        data.extend_from_slice(b"LIST");
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"INFO");
+        data.extend_from_slice(&header[36..]);
+        data.extend(pcm(samples));
+        data
+    }
+
+    #[test]
+    fn frames_raw_pcm() {
+        let samples = [0, 1, -1, i16::max_value(), i16::min_value()];
+        let data = pcm(&samples);
+        let framed = OutputFormat::Raw24Khz16BitMonoPcm.frame(&data);
+        let (spec, read) = read_pcm(&framed);
+        assert_eq!(spec.sample_rate, 24_000);
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Int);
+        assert_eq!(read, samples);
+        assert_eq!(le32(&framed, 40), 10);
+        assert_eq!(&framed[20..22], &[1, 0]);
+    }
+
+    #[test]
+    fn frames_mulaw() {
+        let samples = [0xffu8, 0x7f, 0x00, 0x80];
+        let framed = OutputFormat::Raw8Khz8BitMonoMULaw.frame(&samples);
+        assert_eq!(&framed[..4], b"RIFF");
+        assert_eq!(le32(&framed, 4), 36 + 4);
+        assert_eq!(&framed[20..22], &WAVE_FORMAT_MULAW.to_le_bytes());
+        assert_eq!(&framed[22..24], &[1, 0]);
+        assert_eq!(le32(&framed, 24), 8_000);
+        assert_eq!(&framed[34..36], &[8, 0]);
+        assert_eq!(le32(&framed, 40), 4);
+        assert_eq!(&framed[44..], &samples);
+        assert_eq!(
+            OutputFormat::Riff8Khz8BitMonoMULaw.wav_header(4).unwrap(),
+            framed[..44].to_vec()
+        );
+    }
+
+    #[test]
+    fn fixes_sizes_of_streamed_riff() {
+        let samples = [3, -3, 300];
+        let mut streamed =
+            OutputFormat::Riff16Khz16BitMonoPcm.stream_header().unwrap();
+        assert_eq!(le32(&streamed, 40), STREAM_LENGTH);
+        streamed.extend(pcm(&samples));
+        let framed = OutputFormat::Riff16Khz16BitMonoPcm.frame(&streamed);
+        assert_eq!(le32(&framed, 4), framed.len() as u32 - 8);
+        assert_eq!(le32(&framed, 40), 6);
+        let (spec, read) = read_pcm(&framed);
+        assert_eq!((spec.sample_rate, spec.bits_per_sample), (16_000, 16));
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn fixes_data_size_after_other_chunks() {
+        let samples = [7, 8, 9, 10];
+        let streamed = streamed_with_list(&samples);
+        assert_eq!(strip_riff_header(&streamed), &pcm(&samples)[..]);
+
+        let framed = OutputFormat::Riff8Khz16BitMonoPcm.frame(&streamed);
+        assert_eq!(le32(&framed, 4), framed.len() as u32 - 8);
+        assert_eq!(le32(&framed, 36 + 12 + 4), 8);
+        let (spec, read) = read_pcm(&framed);
+        assert_eq!(spec.sample_rate, 8_000);
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn walks_padded_chunks() {
+        let mut data = b"RIFF\0\0\0\0WAVEodd \x05\0\0\0abcde\0".to_vec();
+        data.extend_from_slice(b"data\x02\0\0\0\x01\x02");
+        assert_eq!(data_chunk(&data), Some(26));
+        assert_eq!(strip_riff_header(&data), &[1, 2]);
+        let framed = OutputFormat::Riff16Khz16BitMonoPcm.frame(&data);
+        assert_eq!(le32(&framed, 4), 28);
+        assert_eq!(le32(&framed, 30), 2);
+        assert_eq!(data_chunk(b"RIFF\0\0\0\0WAVEfmt \xff\xff\xff\xff"), None);
+    }
+
+    #[test]
+    fn keeps_framed_formats() {
+        let ogg = b"OggS\0\x02";
+        let framed = OutputFormat::Ogg16Khz16BitMonoOpus.frame(ogg);
+        assert_eq!(framed, Cow::Borrowed(&ogg[..]));
+        assert_eq!(
+            OutputFormat::Audio16Khz32KBitRateMonoMp3.wav_header(0),
+            None
+        );
+        assert_eq!(OutputFormat::Raw16Khz16BitMonoPcm.stream_header(), None);
+    }
+
+    /// Framing of `Synthesizer::synthesize_to_file`.
+    #[test]
+    fn writes_files_of_synthesized_audio() {
+        let dir =
+            env::temp_dir().join(format!("luis-format-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let samples = [1, 2, 3, -4];
+
+        let path = dir.join("raw.wav");
+        OutputFormat::Raw8Khz16BitMonoPcm
+            .write_file(&path, &pcm(&samples))
+            .unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 8_000);
+        assert_eq!(reader.len(), 4);
+
+        let path = dir.join("riff.wav");
+        OutputFormat::Riff8Khz16BitMonoPcm
+            .write_file(&path, &streamed_with_list(&samples))
+            .unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let read: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(read, samples);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_names() {
+        for format in OutputFormat::all() {
+            assert_eq!(format.name().parse::<OutputFormat>().unwrap(), *format);
+            assert_eq!(
+                format.id().to_string().parse::<OutputFormat>().unwrap(),
+                *format
+            );
+        }
+        assert_eq!(
+            "riff24khz16bitmonopcm".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Riff24Khz16BitMonoPcm
+        );
+        assert!("wav".parse::<OutputFormat>().is_err());
+    }
+}
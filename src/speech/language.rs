@@ -0,0 +1,257 @@
+//! Automatic identification of the source language among candidates.
+
+use crate::{
+    error::InvalidConfig,
+    hr,
+    properties::{is_language_tag, Property, PropertyValue},
+    speech_api::*,
+    Handle, Result, SmartHandle, SpxError, INVALID_HANDLE,
+};
+use serde::{Deserialize, Serialize};
+use std::{ffi::CString, fmt, str::FromStr};
+
+/// Candidate source language, with the endpoint of its custom model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceLanguage {
+    pub language: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_id: Option<String>,
+}
+
+impl SourceLanguage {
+    pub fn new(language: &str) -> Self {
+        SourceLanguage {
+            language: language.to_string(),
+            endpoint_id: None,
+        }
+    }
+
+    /// Language recognized by the custom model of the endpoint.
+    pub fn with_endpoint(language: &str, endpoint_id: &str) -> Self {
+        SourceLanguage {
+            language: language.to_string(),
+            endpoint_id: Some(endpoint_id.to_string()),
+        }
+    }
+
+    /// Check the language tag and the endpoint id.
+    pub fn validate(&self) -> Result {
+        if !is_language_tag(&self.language) {
+            return Err(InvalidConfig(
+                String::from("source_languages"),
+                format!("invalid language {:?}", self.language),
+            ));
+        }
+        if let Some(ref endpoint_id) = self.endpoint_id {
+            if endpoint_id.trim().is_empty() {
+                return Err(InvalidConfig(
+                    String::from("source_languages"),
+                    format!("empty endpoint id of {}", self.language),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a str> for SourceLanguage {
+    fn from(language: &'a str) -> Self {
+        SourceLanguage::new(language)
+    }
+}
+
+/// When the language is identified.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LanguageIdMode {
+    /// Once at the start of the audio.
+    AtStart,
+    /// Continuously, the language may change between utterances.
+    Continuous,
+}
+
+impl LanguageIdMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LanguageIdMode::AtStart => "AtStart",
+            LanguageIdMode::Continuous => "Continuous",
+        }
+    }
+}
+
+impl Default for LanguageIdMode {
+    fn default() -> Self {
+        LanguageIdMode::AtStart
+    }
+}
+
+impl fmt::Display for LanguageIdMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LanguageIdMode {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Property::LanguageIdMode.parse(s)? {
+            PropertyValue::Choice("Continuous") => {
+                Ok(LanguageIdMode::Continuous)
+            }
+            _ => Ok(LanguageIdMode::AtStart),
+        }
+    }
+}
+
+SmartHandle!(
+    SourceLanguageConfig,
+    SPXSOURCELANGCONFIGHANDLE,
+    source_lang_config_release,
+    source_lang_config_is_handle_valid
+);
+
+impl SourceLanguageConfig {
+    /// Language with the endpoint of custom model.
+    pub fn from_language(source: &SourceLanguage) -> Result<Self> {
+        source.validate()?;
+        let mut handle = INVALID_HANDLE;
+        let lang = CString::new(source.language.as_str())?;
+        let endpoint = source
+            .endpoint_id
+            .as_ref()
+            .map(|e| CString::new(e.as_str()))
+            .transpose()?;
+        hr!(source_lang_config_from_language_and_endpointId(
+            &mut handle,
+            lang.as_ptr(),
+            endpoint.as_ref().map_or(std::ptr::null(), |e| e.as_ptr()),
+        ))?;
+        Ok(SourceLanguageConfig::new(handle))
+    }
+}
+
+SmartHandle!(
+    AutoDetectSourceLanguageConfig,
+    SPXAUTODETECTSOURCELANGCONFIGHANDLE,
+    auto_detect_source_lang_config_release,
+    auto_detect_source_lang_config_is_handle_valid
+);
+
+impl AutoDetectSourceLanguageConfig {
+    /// Candidates of the source language, each language once.
+    pub fn from_sources(sources: &[SourceLanguage]) -> Result<Self> {
+        if sources.is_empty() {
+            return Err(InvalidConfig(
+                String::from("source_languages"),
+                String::from("at least one candidate is required"),
+            ));
+        }
+        for (i, source) in sources.iter().enumerate() {
+            source.validate()?;
+            let duplicate = sources[..i]
+                .iter()
+                .any(|s| s.language.eq_ignore_ascii_case(&source.language));
+            if duplicate {
+                return Err(InvalidConfig(
+                    String::from("source_languages"),
+                    format!("duplicate language {}", source.language),
+                ));
+            }
+        }
+        let mut handle = INVALID_HANDLE;
+        if sources.iter().all(|s| s.endpoint_id.is_none()) {
+            let langs: Vec<&str> =
+                sources.iter().map(|s| s.language.as_str()).collect();
+            let langs = CString::new(langs.join(","))?;
+            hr!(auto_detect_source_lang_config_from_languages(
+                &mut handle,
+                langs.as_ptr()
+            ))?;
+            return Ok(AutoDetectSourceLanguageConfig::new(handle));
+        }
+
+        let first = SourceLanguageConfig::from_language(&sources[0])?;
+        hr!(auto_detect_source_lang_config_from_source_lang_config(
+            &mut handle,
+            first.handle()
+        ))?;
+        let config = AutoDetectSourceLanguageConfig::new(handle);
+        for source in &sources[1..] {
+            let slc = SourceLanguageConfig::from_language(source)?;
+            hr!(auto_detect_source_lang_config_add_source_lang_config(
+                config.handle(),
+                slc.handle()
+            ))?;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_config<T>(result: Result<T>) -> String {
+        match result {
+            Err(InvalidConfig(key, msg)) => {
+                assert_eq!(key, "source_languages");
+                msg
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("invalid sources are accepted"),
+        }
+    }
+
+    #[test]
+    fn source_language_is_validated() {
+        SourceLanguage::new("en-US").validate().unwrap();
+        SourceLanguage::with_endpoint("zh-Hans-CN", "model")
+            .validate()
+            .unwrap();
+        let msg = invalid_config(SourceLanguageConfig::from_language(
+            &SourceLanguage::new("en_US"),
+        ));
+        assert_eq!(msg, "invalid language \"en_US\"");
+        let msg = invalid_config(SourceLanguageConfig::from_language(
+            &SourceLanguage::with_endpoint("de-DE", " "),
+        ));
+        assert_eq!(msg, "empty endpoint id of de-DE");
+    }
+
+    #[test]
+    fn sources_are_not_empty_or_duplicate() {
+        let msg =
+            invalid_config(AutoDetectSourceLanguageConfig::from_sources(&[]));
+        assert_eq!(msg, "at least one candidate is required");
+        let sources = vec![
+            SourceLanguage::new("en-US"),
+            SourceLanguage::with_endpoint("EN-us", "model"),
+        ];
+        let msg = invalid_config(AutoDetectSourceLanguageConfig::from_sources(
+            &sources,
+        ));
+        assert_eq!(msg, "duplicate language EN-us");
+        let sources =
+            vec![SourceLanguage::new("en-US"), SourceLanguage::new("")];
+        let msg = invalid_config(AutoDetectSourceLanguageConfig::from_sources(
+            &sources,
+        ));
+        assert_eq!(msg, "invalid language \"\"");
+    }
+
+    #[test]
+    fn language_id_mode_from_str() {
+        for mode in &[LanguageIdMode::AtStart, LanguageIdMode::Continuous] {
+            assert_eq!(
+                mode.to_string().parse::<LanguageIdMode>().unwrap(),
+                *mode
+            );
+        }
+        assert_eq!(
+            "continuous".parse::<LanguageIdMode>().unwrap(),
+            LanguageIdMode::Continuous
+        );
+        assert_eq!(LanguageIdMode::default().to_string(), "AtStart");
+        assert!("AtEnd".parse::<LanguageIdMode>().is_err());
+    }
+}
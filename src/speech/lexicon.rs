@@ -0,0 +1,403 @@
+//! Custom pronunciation lexicon of words like brand names.
+//!
+//! A lexicon maps graphemes to phonemes or aliases. It is rendered as a W3C
+//! PLS document to be hosted for the synthesizer, applied inline to SSML
+//! content, or turned into phrase hints of the recognizer.
+
+use super::ssml::{escape, Content, PhoneticAlphabet};
+use crate::{error::InvalidConfig, properties::is_language_tag, Result};
+use std::{fmt, fs, path::Path};
+
+const PLS_NAMESPACE: &str = "http://www.w3.org/2005/01/pronunciation-lexicon";
+
+/// How the graphemes are read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pronunciation {
+    /// Phonemes in the alphabet of the lexicon.
+    Phoneme(String),
+    /// Text read instead, e.g. "Contoso Limited" of "Contoso Ltd.".
+    Alias(String),
+}
+
+/// Entry of the lexicon, one or more spellings read the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+    pub graphemes: Vec<String>,
+    pub pronunciation: Pronunciation,
+}
+
+/// Pronunciation lexicon of a language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexicon {
+    language: String,
+    alphabet: PhoneticAlphabet,
+    lexemes: Vec<Lexeme>,
+}
+
+impl Lexicon {
+    /// Lexicon of the language, e.g. "en-US", with phonemes in the
+    /// alphabet.
+    pub fn new(language: &str, alphabet: PhoneticAlphabet) -> Self {
+        Lexicon {
+            language: language.to_string(),
+            alphabet,
+            lexemes: Vec::new(),
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn alphabet(&self) -> PhoneticAlphabet {
+        self.alphabet
+    }
+
+    pub fn lexemes(&self) -> &[Lexeme] {
+        &self.lexemes
+    }
+
+    /// Read the grapheme as the phonemes.
+    pub fn phoneme(&mut self, grapheme: &str, ph: &str) -> &mut Self {
+        self.lexeme(&[grapheme], Pronunciation::Phoneme(ph.to_string()))
+    }
+
+    /// Read the grapheme as the alias.
+    pub fn alias(&mut self, grapheme: &str, alias: &str) -> &mut Self {
+        self.lexeme(&[grapheme], Pronunciation::Alias(alias.to_string()))
+    }
+
+    /// Read all the graphemes the same way.
+    pub fn lexeme(
+        &mut self,
+        graphemes: &[&str],
+        pronunciation: Pronunciation,
+    ) -> &mut Self {
+        self.lexemes.push(Lexeme {
+            graphemes: graphemes.iter().map(|g| g.to_string()).collect(),
+            pronunciation,
+        });
+        self
+    }
+
+    /// Pronunciation of the grapheme, the first lexeme wins.
+    pub fn lookup(&self, grapheme: &str) -> Option<&Pronunciation> {
+        self.lexemes
+            .iter()
+            .find(|l| l.graphemes.iter().any(|g| g == grapheme))
+            .map(|l| &l.pronunciation)
+    }
+
+    /// Check the language and the lexemes.
+    pub fn validate(&self) -> Result {
+        if !is_language_tag(&self.language) {
+            return Err(invalid("xml:lang", &self.language));
+        }
+        for lexeme in &self.lexemes {
+            if lexeme.graphemes.is_empty()
+                || lexeme.graphemes.iter().any(|g| g.trim().is_empty())
+            {
+                return Err(invalid("grapheme", "grapheme is empty"));
+            }
+            let value = match &lexeme.pronunciation {
+                Pronunciation::Phoneme(value) => value,
+                Pronunciation::Alias(value) => value,
+            };
+            if value.trim().is_empty() {
+                return Err(invalid(
+                    &lexeme.graphemes[0],
+                    "pronunciation is empty",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validated PLS document.
+    pub fn to_pls(&self) -> Result<String> {
+        self.validate()?;
+        Ok(self.to_string())
+    }
+
+    /// Write the PLS document, e.g. to be hosted for `Content::lexicon`.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result {
+        fs::write(path, self.to_pls()?)?;
+        Ok(())
+    }
+
+    /// Distinct graphemes as phrase hints of the recognizer.
+    pub fn phrases(&self) -> Vec<String> {
+        let mut phrases: Vec<String> = Vec::new();
+        for grapheme in self.lexemes.iter().flat_map(|l| &l.graphemes) {
+            if !phrases.contains(grapheme) {
+                phrases.push(grapheme.clone());
+            }
+        }
+        phrases
+    }
+
+    /// Append the text to the content, the graphemes of the lexicon as
+    /// phonemes or aliases. Graphemes are matched case sensitively, as
+    /// whole words if they start or end with letters or digits.
+    pub fn apply(&self, text: &str, content: &mut Content) {
+        let mut graphemes: Vec<(&str, &Pronunciation)> = self
+            .lexemes
+            .iter()
+            .flat_map(|l| {
+                l.graphemes
+                    .iter()
+                    .map(move |g| (g.as_str(), &l.pronunciation))
+            })
+            .filter(|(g, _)| !g.is_empty())
+            .collect();
+        // The longest grapheme matches first.
+        graphemes.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        let mut plain = 0;
+        let mut pos = 0;
+        while pos < text.len() {
+            let found =
+                graphemes.iter().find(|(g, _)| is_word_at(text, pos, g));
+            match found {
+                Some((grapheme, pronunciation)) => {
+                    if plain < pos {
+                        content.text(&text[plain..pos]);
+                    }
+                    let word = &text[pos..pos + grapheme.len()];
+                    match pronunciation {
+                        Pronunciation::Phoneme(ph) => {
+                            content.phoneme(self.alphabet, ph, word)
+                        }
+                        Pronunciation::Alias(alias) => content.sub(alias, word),
+                    };
+                    pos += grapheme.len();
+                    plain = pos;
+                }
+                None => {
+                    pos += text[pos..].chars().next().map_or(1, char::len_utf8)
+                }
+            }
+        }
+        if plain < text.len() {
+            content.text(&text[plain..]);
+        }
+    }
+}
+
+/// Check the grapheme is at the position, not inside a word.
+fn is_word_at(text: &str, pos: usize, grapheme: &str) -> bool {
+    if !text[pos..].starts_with(grapheme) {
+        return false;
+    }
+    let joined = |a: Option<char>, b: Option<char>| match (a, b) {
+        (Some(a), Some(b)) => {
+            a.is_ascii_alphanumeric() && b.is_ascii_alphanumeric()
+        }
+        _ => false,
+    };
+    let end = pos + grapheme.len();
+    !joined(text[..pos].chars().next_back(), grapheme.chars().next())
+        && !joined(grapheme.chars().next_back(), text[end..].chars().next())
+}
+
+/// Name of the alphabet in PLS documents.
+fn pls_alphabet(alphabet: PhoneticAlphabet) -> &'static str {
+    match alphabet {
+        PhoneticAlphabet::Ipa => "ipa",
+        PhoneticAlphabet::Sapi => "x-microsoft-sapi",
+        PhoneticAlphabet::Ups => "x-microsoft-ups",
+    }
+}
+
+fn invalid(name: &str, msg: &str) -> crate::SpxError {
+    InvalidConfig(format!("lexicon {}", name), msg.to_string())
+}
+
+/// PLS document without validation.
+impl fmt::Display for Lexicon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            f,
+            "<lexicon version=\"1.0\" xmlns=\"{}\" alphabet=\"{}\" xml:lang=\"{}\">",
+            PLS_NAMESPACE,
+            pls_alphabet(self.alphabet),
+            escape(&self.language)
+        )?;
+        for lexeme in &self.lexemes {
+            writeln!(f, "  <lexeme>")?;
+            for grapheme in &lexeme.graphemes {
+                writeln!(f, "    <grapheme>{}</grapheme>", escape(grapheme))?;
+            }
+            match &lexeme.pronunciation {
+                Pronunciation::Phoneme(ph) => {
+                    writeln!(f, "    <phoneme>{}</phoneme>", escape(ph))?
+                }
+                Pronunciation::Alias(alias) => {
+                    writeln!(f, "    <alias>{}</alias>", escape(alias))?
+                }
+            }
+            writeln!(f, "  </lexeme>")?;
+        }
+        writeln!(f, "</lexicon>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{speech::ssml::Ssml, SpxError};
+
+    fn lexicon() -> Lexicon {
+        let mut lexicon = Lexicon::new("en-US", PhoneticAlphabet::Ipa);
+        lexicon
+            .alias("Ltd.", "Limited")
+            .phoneme("Contoso", "kənˈtoʊsoʊ")
+            .alias("Contoso Ltd.", "Contoso Limited")
+            .lexeme(
+                &["AI", "A.I."],
+                Pronunciation::Alias(String::from("artificial intelligence")),
+            );
+        lexicon
+    }
+
+    fn applied(lexicon: &Lexicon, text: &str) -> Content {
+        let mut content = Content::default();
+        content.pronounce(text, lexicon);
+        content
+    }
+
+    #[test]
+    fn renders_pls() {
+        let expected = "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<lexicon version=\"1.0\" \
+xmlns=\"http://www.w3.org/2005/01/pronunciation-lexicon\" \
+alphabet=\"ipa\" xml:lang=\"en-US\">
+  <lexeme>
+    <grapheme>Ltd.</grapheme>
+    <alias>Limited</alias>
+  </lexeme>
+  <lexeme>
+    <grapheme>Contoso</grapheme>
+    <phoneme>kənˈtoʊsoʊ</phoneme>
+  </lexeme>
+  <lexeme>
+    <grapheme>Contoso Ltd.</grapheme>
+    <alias>Contoso Limited</alias>
+  </lexeme>
+  <lexeme>
+    <grapheme>AI</grapheme>
+    <grapheme>A.I.</grapheme>
+    <alias>artificial intelligence</alias>
+  </lexeme>
+</lexicon>
+";
+        assert_eq!(lexicon().to_pls().unwrap(), expected);
+    }
+
+    #[test]
+    fn renders_pls_alphabets_and_escapes() {
+        let mut lexicon = Lexicon::new("zh-CN", PhoneticAlphabet::Sapi);
+        lexicon.alias("R&D", "<research> & \"development\"");
+        let expected = "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<lexicon version=\"1.0\" \
+xmlns=\"http://www.w3.org/2005/01/pronunciation-lexicon\" \
+alphabet=\"x-microsoft-sapi\" xml:lang=\"zh-CN\">
+  <lexeme>
+    <grapheme>R&amp;D</grapheme>
+    <alias>&lt;research&gt; &amp; &quot;development&quot;</alias>
+  </lexeme>
+</lexicon>
+";
+        assert_eq!(lexicon.to_pls().unwrap(), expected);
+
+        let lexicon = Lexicon::new("en-US", PhoneticAlphabet::Ups);
+        assert!(lexicon
+            .to_pls()
+            .unwrap()
+            .contains("alphabet=\"x-microsoft-ups\""));
+    }
+
+    #[test]
+    fn validates_lexemes() {
+        let key = |lexicon: &Lexicon| match lexicon.validate() {
+            Err(SpxError::InvalidConfig(key, _)) => key,
+            other => panic!("unexpected validation: {:?}", other),
+        };
+        assert!(lexicon().validate().is_ok());
+
+        let mut empty = Lexicon::new("en-US", PhoneticAlphabet::Ipa);
+        empty.alias(" ", "blank");
+        assert_eq!(key(&empty), "lexicon grapheme");
+        let mut none = Lexicon::new("en-US", PhoneticAlphabet::Ipa);
+        none.lexeme(&[], Pronunciation::Alias(String::from("nothing")));
+        assert_eq!(key(&none), "lexicon grapheme");
+        assert!(none.to_pls().is_err());
+
+        let mut silent = Lexicon::new("en-US", PhoneticAlphabet::Ipa);
+        silent.phoneme("Contoso", "");
+        assert_eq!(key(&silent), "lexicon Contoso");
+        let language = Lexicon::new("en_US", PhoneticAlphabet::Ipa);
+        assert_eq!(key(&language), "lexicon xml:lang");
+    }
+
+    #[test]
+    fn applies_whole_words() {
+        let lexicon = lexicon();
+        let mut expected = Content::default();
+        expected
+            .text("Ask ")
+            .phoneme(PhoneticAlphabet::Ipa, "kənˈtoʊsoʊ", "Contoso")
+            .text(", not Contosos or contoso or MyContoso.");
+        assert_eq!(
+            applied(
+                &lexicon,
+                "Ask Contoso, not Contosos or contoso or MyContoso."
+            ),
+            expected
+        );
+
+        let mut expected = Content::default();
+        expected
+            .sub("artificial intelligence", "AI")
+            .text("/")
+            .sub("artificial intelligence", "A.I.")
+            .text(" and MAIL");
+        assert_eq!(applied(&lexicon, "AI/A.I. and MAIL"), expected);
+
+        let mut expected = Content::default();
+        expected.text("No words here");
+        assert_eq!(applied(&lexicon, "No words here"), expected);
+        assert_eq!(applied(&lexicon, ""), Content::default());
+    }
+
+    #[test]
+    fn applies_longest_graphemes_first() {
+        let lexicon = lexicon();
+        let mut expected = Content::default();
+        expected
+            .sub("Contoso Limited", "Contoso Ltd.")
+            .text(" and ")
+            .phoneme(PhoneticAlphabet::Ipa, "kənˈtoʊsoʊ", "Contoso")
+            .text(", ")
+            .sub("Limited", "Ltd.");
+        assert_eq!(
+            applied(&lexicon, "Contoso Ltd. and Contoso, Ltd."),
+            expected
+        );
+    }
+
+    #[test]
+    fn renders_applied_ssml() {
+        let mut ssml = Ssml::new("en-US");
+        ssml.voice("en-US-JennyNeural", |c| {
+            c.pronounce("Hi AI, I'm Contoso.", &lexicon());
+        });
+        assert!(ssml.to_xml().unwrap().contains(
+            "Hi <sub alias=\"artificial intelligence\">AI</sub>, I&apos;m \
+             <phoneme alphabet=\"ipa\" ph=\"kənˈtoʊsoʊ\">Contoso</phoneme>."
+        ));
+    }
+}
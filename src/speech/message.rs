@@ -0,0 +1,213 @@
+//! Raw messages exchanged with the service through the connection.
+
+use super::recognizer::Connection;
+use crate::{
+    hr,
+    properties::{Properties, PropertyBag},
+    speech_api::*,
+    Handle, Result, SmartHandle, SpxError, INVALID_HANDLE,
+};
+use futures::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    Async, Poll, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    os::raw::c_void,
+    sync::{Arc, Weak},
+};
+
+SmartHandle!(
+    MessageEvent,
+    SPXEVENTHANDLE,
+    connection_message_received_event_handle_release,
+    connection_message_received_event_handle_is_valid
+);
+
+SmartHandle!(
+    MessageHandle,
+    SPXCONNECTIONMESSAGEHANDLE,
+    connection_message_handle_release,
+    connection_message_handle_is_valid
+);
+
+/// Message received from the service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionMessage {
+    /// Path of the message, e.g. "turn.start" or "speech.hypothesis".
+    pub path: String,
+    /// Text message or binary message.
+    pub is_text: bool,
+    /// Payload of the message.
+    pub data: Vec<u8>,
+}
+
+impl ConnectionMessage {
+    /// Extract the message of the received event.
+    fn extract(hevent: SPXEVENTHANDLE) -> Result<Self> {
+        let evt = MessageEvent::new(hevent);
+        let mut hmsg = INVALID_HANDLE;
+        hr!(connection_message_received_event_get_message(
+            evt.handle, &mut hmsg
+        ))?;
+        let msg = MessageHandle::new(hmsg);
+        let mut hprops = INVALID_HANDLE;
+        hr!(connection_message_get_property_bag(msg.handle, &mut hprops))?;
+        let props = Properties::new(hprops);
+        let path = props.get_by_name("connection.message.path")?;
+        let is_text = props.get_by_name("connection.message.type")? == "text";
+
+        let size = unsafe { connection_message_get_data_size(msg.handle) };
+        let mut data = vec![0u8; size as usize];
+        if size > 0 {
+            hr!(connection_message_get_data(
+                msg.handle,
+                data.as_mut_ptr(),
+                size
+            ))?;
+        }
+        Ok(ConnectionMessage::from_payload(path, is_text, data))
+    }
+
+    /// Message of the received payload.
+    fn from_payload(path: String, is_text: bool, mut data: Vec<u8>) -> Self {
+        if is_text {
+            // Text payload is terminated by NUL.
+            while data.last() == Some(&0) {
+                data.pop();
+            }
+        }
+        ConnectionMessage {
+            path,
+            is_text,
+            data,
+        }
+    }
+
+    /// Payload of text message.
+    pub fn text(&self) -> Option<&str> {
+        if self.is_text {
+            std::str::from_utf8(&self.data).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// Stream of the received messages, stop receiving when dropped.
+pub struct MessageStream {
+    pub(crate) source: UnboundedReceiver<ConnectionMessage>,
+    /// Keep the sender alive for the callback.
+    pub(crate) sink: Arc<UnboundedSender<ConnectionMessage>>,
+    pub(crate) connection: Connection,
+}
+
+impl Stream for MessageStream {
+    type Item = ConnectionMessage;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<ConnectionMessage>, SpxError> {
+        match self.source.poll() {
+            Ok(Async::Ready(msg)) => Ok(Async::Ready(msg)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => {
+                Err(SpxError::Other(String::from("streaming is interrupted")))
+            }
+        }
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        if !self.connection.is_valid() {
+            return;
+        }
+        let hr = unsafe {
+            connection_message_received_set_callback(
+                self.connection.handle(),
+                None,
+                std::ptr::null_mut(),
+            )
+        };
+        if hr != 0 {
+            log::error!("failed to stop receiving messages: {}", hr);
+        }
+    }
+}
+
+/// Callback of received messages.
+#[no_mangle]
+pub(crate) unsafe extern "C" fn on_message_received(
+    hevent: SPXEVENTHANDLE,
+    context: *mut c_void,
+) {
+    if context.is_null() {
+        log::error!("Unknown context with NULL pointer.");
+        return;
+    }
+    let ctx =
+        Box::from_raw(context as *mut Weak<UnboundedSender<ConnectionMessage>>);
+    let weak_ptr = Weak::clone(&ctx);
+    // forget the box, at least one box is leaked.
+    Box::into_raw(ctx);
+    let sender = match weak_ptr.upgrade() {
+        Some(sender) => sender,
+        None => {
+            MessageEvent::new(hevent);
+            log::debug!("Message stream is dropped.");
+            return;
+        }
+    };
+    match ConnectionMessage::extract(hevent) {
+        Ok(msg) => {
+            log::trace!("Message {} received.", msg.path);
+            if sender.unbounded_send(msg).is_err() {
+                log::debug!("Message stream is closed.");
+            }
+        }
+        Err(err) => log::error!("failed to extract message: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(is_text: bool, data: &[u8]) -> ConnectionMessage {
+        ConnectionMessage::from_payload(
+            String::from("turn.start"),
+            is_text,
+            data.to_vec(),
+        )
+    }
+
+    #[test]
+    fn binary_payload_is_kept() {
+        let msg = message(false, &[1, 2, 0, 0]);
+        assert_eq!(msg.data, vec![1, 2, 0, 0]);
+        assert_eq!(msg.text(), None);
+    }
+
+    #[test]
+    fn text_payload_is_trimmed() {
+        let msg = message(true, b"{\"context\":{}}\0\0");
+        assert_eq!(msg.data, b"{\"context\":{}}".to_vec());
+        assert_eq!(msg.text(), Some("{\"context\":{}}"));
+
+        let msg = message(true, &[0xff, 0xfe, 0]);
+        assert_eq!(msg.data, vec![0xff, 0xfe]);
+        assert_eq!(msg.text(), None);
+    }
+
+    #[test]
+    fn empty_payload() {
+        let msg = message(true, &[]);
+        assert_eq!(msg.text(), Some(""));
+        let msg = message(true, &[0]);
+        assert!(msg.data.is_empty());
+        assert_eq!(msg.text(), Some(""));
+        let msg = message(false, &[]);
+        assert!(msg.data.is_empty());
+        assert_eq!(msg.text(), None);
+    }
+}
@@ -1,13 +1,51 @@
 //! Recognition, intent analysis, translation of speech.
 
 pub mod audio;
+pub mod auth;
 pub mod builder;
+pub mod cache;
 pub mod events;
+pub mod failover;
+pub mod format;
+pub mod language;
+pub mod lexicon;
+pub mod message;
+pub mod normalize;
+pub mod pool;
+pub mod progress;
+pub mod queue;
 pub mod recognizer;
+pub mod recorder;
+pub mod replay;
+pub mod settings;
+pub mod ssml;
+pub mod streaming;
+pub mod supervisor;
 pub mod synthesizer;
+pub mod vad;
+pub mod voices;
 
 pub use audio::*;
+pub use auth::*;
 pub use builder::*;
+pub use cache::*;
 pub use events::*;
+pub use failover::*;
+pub use format::*;
+pub use language::*;
+pub use lexicon::*;
+pub use message::*;
+pub use normalize::*;
+pub use pool::*;
+pub use progress::*;
+pub use queue::*;
 pub use recognizer::*;
+pub use recorder::*;
+pub use replay::*;
+pub use settings::*;
+pub use ssml::*;
+pub use streaming::*;
+pub use supervisor::*;
 pub use synthesizer::*;
+pub use vad::*;
+pub use voices::*;
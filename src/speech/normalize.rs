@@ -0,0 +1,1155 @@
+//! Normalization of text before synthesis.
+//!
+//! Rules find numbers, dates, currencies, abbreviations and URLs in plain
+//! text and give their reading, as SSML `say-as` for the service and as
+//! expanded words. The result is rendered into SSML content, or into plain
+//! text with the words expanded.
+
+use super::ssml::{Content, Ssml};
+use std::ops::Range;
+
+/// How a piece of text is read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reading {
+    /// Type of `say-as`, e.g. "date".
+    pub interpret_as: Option<String>,
+    /// Format of `say-as`, e.g. "mdy".
+    pub format: Option<String>,
+    /// Expanded words, e.g. "twelve dollars".
+    pub spoken: Option<String>,
+}
+
+impl Reading {
+    /// Read as the type by the service.
+    pub fn say_as(interpret_as: &str, format: Option<&str>) -> Self {
+        Reading {
+            interpret_as: Some(interpret_as.to_string()),
+            format: format.map(str::to_string),
+            spoken: None,
+        }
+    }
+
+    /// Read as the words.
+    pub fn spoken<S: Into<String>>(words: S) -> Self {
+        Reading {
+            interpret_as: None,
+            format: None,
+            spoken: Some(words.into()),
+        }
+    }
+
+    /// Also read as the words where `say-as` is not used.
+    pub fn or_spoken<S: Into<String>>(mut self, words: Option<S>) -> Self {
+        self.spoken = words.map(Into::into);
+        self
+    }
+}
+
+/// Piece of the normalized text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Read { text: String, reading: Reading },
+}
+
+/// Rule finding text to normalize.
+pub trait NormalizeRule: Send + Sync {
+    /// Range of the first match in the text and its reading.
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)>;
+}
+
+/// Caller supplied rule.
+impl<F> NormalizeRule for F
+where
+    F: Fn(&str) -> Option<(Range<usize>, Reading)> + Send + Sync,
+{
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        self(text)
+    }
+}
+
+/// Ordered rules of a language, earlier rules take precedence.
+pub struct Normalizer {
+    language: String,
+    rules: Vec<Box<dyn NormalizeRule>>,
+}
+
+impl Normalizer {
+    /// Normalizer of the language without rules.
+    pub fn new(language: &str) -> Self {
+        Normalizer {
+            language: language.to_string(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Normalizer with the built-in rules of the language, e.g. "en-US".
+    /// English expands the words, other languages use `say-as` only.
+    pub fn for_language(language: &str) -> Self {
+        let mut normalizer = Normalizer::new(language);
+        let english = is_english(language);
+        if english {
+            normalizer.add_rule(UrlRule);
+        }
+        normalizer
+            .add_rule(CurrencyRule { english })
+            .add_rule(DateRule::for_language(language))
+            .add_rule(TimeRule { english })
+            .add_rule(TelephoneRule { english });
+        if english {
+            normalizer
+                .add_rule(PercentRule)
+                .add_rule(OrdinalRule)
+                .add_rule(Replacements::english_abbreviations())
+                .add_rule(NumberRule);
+        }
+        normalizer
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// Append the rule after the others.
+    pub fn add_rule<R: NormalizeRule + 'static>(
+        &mut self,
+        rule: R,
+    ) -> &mut Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Insert the rule before the others, e.g. to override built-in ones.
+    pub fn prepend_rule<R: NormalizeRule + 'static>(
+        &mut self,
+        rule: R,
+    ) -> &mut Self {
+        self.rules.insert(0, Box::new(rule));
+        self
+    }
+
+    /// Split the text into plain and read segments.
+    pub fn normalize(&self, text: &str) -> Normalized {
+        let mut segments = vec![Segment::Text(text.to_string())];
+        for rule in &self.rules {
+            let mut next = Vec::with_capacity(segments.len());
+            for segment in segments {
+                match segment {
+                    Segment::Text(text) => {
+                        split(rule.as_ref(), &text, &mut next)
+                    }
+                    read => next.push(read),
+                }
+            }
+            segments = next;
+        }
+        Normalized { segments }
+    }
+
+    /// Plain text with the words expanded.
+    pub fn to_text(&self, text: &str) -> String {
+        self.normalize(text).to_text()
+    }
+
+    /// SSML document of the text spoken by the voice.
+    pub fn to_ssml(&self, text: &str, voice: &str) -> Ssml {
+        let normalized = self.normalize(text);
+        let mut ssml = Ssml::new(&self.language);
+        ssml.voice(voice, |c| normalized.render(c));
+        ssml
+    }
+}
+
+/// Apply the rule to the plain text repeatedly.
+fn split(rule: &dyn NormalizeRule, mut text: &str, out: &mut Vec<Segment>) {
+    while let Some((range, reading)) = rule.find(text) {
+        if range.start >= range.end || range.end > text.len() {
+            break;
+        }
+        if range.start > 0 {
+            out.push(Segment::Text(text[..range.start].to_string()));
+        }
+        out.push(Segment::Read {
+            text: text[range.clone()].to_string(),
+            reading,
+        });
+        text = &text[range.end..];
+    }
+    if !text.is_empty() {
+        out.push(Segment::Text(text.to_string()));
+    }
+}
+
+/// Text split by the rules.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalized {
+    pub segments: Vec<Segment>,
+}
+
+impl Normalized {
+    /// Plain text with the expanded words, or the original text if there
+    /// is no expansion.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Read { text, reading } => {
+                    out.push_str(reading.spoken.as_ref().unwrap_or(text))
+                }
+            }
+        }
+        out
+    }
+
+    /// Append to the SSML content, as `say-as` if set, else `sub` of the
+    /// expanded words.
+    pub fn render(&self, content: &mut Content) {
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => content.text(text),
+                Segment::Read { text, reading } => {
+                    match (&reading.interpret_as, &reading.spoken) {
+                        (Some(interpret_as), _) => content.say_as(
+                            interpret_as,
+                            reading.format.as_ref().map(String::as_str),
+                            text,
+                        ),
+                        (None, Some(spoken)) => content.sub(spoken, text),
+                        (None, None) => content.text(text),
+                    }
+                }
+            };
+        }
+    }
+}
+
+fn is_english(language: &str) -> bool {
+    language.to_ascii_lowercase().split('-').next() == Some("en")
+}
+
+/// Check the range is a whole word of the text.
+fn is_word(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    before.map_or(true, |c| !c.is_alphanumeric())
+        && after.map_or(true, |c| !c.is_alphanumeric())
+}
+
+/// First whole word match of the matcher, tried at every position.
+fn scan<F>(text: &str, matcher: F) -> Option<(Range<usize>, Reading)>
+where
+    F: Fn(&str, usize) -> Option<(usize, Reading)>,
+{
+    text.char_indices()
+        .filter_map(|(start, _)| {
+            matcher(text, start)
+                .filter(|(end, _)| is_word(text, start, *end))
+                .map(|(end, reading)| (start..end, reading))
+        })
+        .next()
+}
+
+/// Number at the position, digits with optional comma groups and
+/// decimals. Returns the end, the integer digits and the decimals.
+fn number_at(text: &str, start: usize) -> Option<(usize, String, String)> {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let first = digits(start);
+    if first == 0 {
+        return None;
+    }
+    let mut integer = text[start..start + first].to_string();
+    let mut end = start + first;
+    // Comma groups of three digits after up to three leading digits.
+    if first <= 3 {
+        while end + 4 <= bytes.len()
+            && bytes[end] == b','
+            && digits(end + 1) == 3
+        {
+            integer.push_str(&text[end + 1..end + 4]);
+            end += 4;
+        }
+    }
+    let mut decimals = String::new();
+    if end + 1 < bytes.len() && bytes[end] == b'.' && digits(end + 1) > 0 {
+        let n = digits(end + 1);
+        decimals = text[end + 1..end + 1 + n].to_string();
+        end += 1 + n;
+    }
+    Some((end, integer, decimals))
+}
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty",
+    "ninety",
+];
+const SCALES: [&str; 5] = ["", "thousand", "million", "billion", "trillion"];
+
+/// English words of numbers below a thousand.
+fn below_thousand(n: u64) -> String {
+    let mut words = Vec::new();
+    if n >= 100 {
+        words.push(format!("{} hundred", ONES[(n / 100) as usize]));
+    }
+    let rest = n % 100;
+    if rest >= 20 {
+        let tens = TENS[(rest / 10) as usize];
+        match rest % 10 {
+            0 => words.push(tens.to_string()),
+            ones => words.push(format!("{}-{}", tens, ONES[ones as usize])),
+        }
+    } else if rest > 0 || words.is_empty() {
+        words.push(ONES[rest as usize].to_string());
+    }
+    words.join(" ")
+}
+
+/// English words of the number, e.g. "one thousand two hundred five".
+pub fn cardinal_words(n: u64) -> String {
+    if n < 1000 {
+        return below_thousand(n);
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 1000);
+        rest /= 1000;
+    }
+    if groups.len() > SCALES.len() {
+        return digit_words(&n.to_string());
+    }
+    let mut words = Vec::new();
+    for (scale, group) in groups.iter().enumerate().rev() {
+        if *group > 0 {
+            words.push(below_thousand(*group));
+            if scale > 0 {
+                words.push(SCALES[scale].to_string());
+            }
+        }
+    }
+    words.join(" ")
+}
+
+/// English ordinal words of the number, e.g. "twenty-first".
+pub fn ordinal_words(n: u64) -> String {
+    let cardinal = cardinal_words(n);
+    let split = cardinal
+        .rfind(|c| c == ' ' || c == '-')
+        .map_or(0, |i| i + 1);
+    let (head, last) = cardinal.split_at(split);
+    let last = match last {
+        "one" => String::from("first"),
+        "two" => String::from("second"),
+        "three" => String::from("third"),
+        "five" => String::from("fifth"),
+        "eight" => String::from("eighth"),
+        "nine" => String::from("ninth"),
+        "twelve" => String::from("twelfth"),
+        word if word.ends_with('y') => {
+            format!("{}ieth", &word[..word.len() - 1])
+        }
+        word => format!("{}th", word),
+    };
+    format!("{}{}", head, last)
+}
+
+/// English words of each digit, e.g. "zero zero seven".
+fn digit_words(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// English words of the number text, with leading zeros read as digits.
+fn number_words(integer: &str, decimals: &str) -> String {
+    let mut words = match integer.parse::<u64>() {
+        Ok(n) if integer.len() == 1 || !integer.starts_with('0') => {
+            cardinal_words(n)
+        }
+        _ => digit_words(integer),
+    };
+    if !decimals.is_empty() {
+        words.push_str(" point ");
+        words.push_str(&digit_words(decimals));
+    }
+    words
+}
+
+/// English words of the year, e.g. "nineteen eighty-four".
+fn year_words(year: u64) -> String {
+    match year {
+        y if y < 1000 || (y >= 2000 && y < 2010) || y % 1000 == 0 => {
+            cardinal_words(y)
+        }
+        y if y % 100 == 0 => format!("{} hundred", cardinal_words(y / 100)),
+        y if y % 100 < 10 => format!(
+            "{} oh {}",
+            cardinal_words(y / 100),
+            cardinal_words(y % 100)
+        ),
+        y => format!("{} {}", cardinal_words(y / 100), cardinal_words(y % 100)),
+    }
+}
+
+/// Addresses of the web and e-mail, read by the parts in English.
+pub struct UrlRule;
+
+impl NormalizeRule for UrlRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let rest = &text[start..];
+            let len = rest
+                .find(|c: char| c.is_whitespace() || "<>\"'()".contains(c))
+                .unwrap_or_else(|| rest.len());
+            // Trailing punctuation belongs to the sentence.
+            let token = rest[..len].trim_end_matches(|c| ".,;:!?".contains(c));
+            let lower = token.to_ascii_lowercase();
+            let is_web = lower.starts_with("http://")
+                || lower.starts_with("https://")
+                || lower.starts_with("www.");
+            let is_mail = token.find('@').map_or(false, |at| {
+                at > 0 && token[at + 1..].contains('.') && !token.contains('/')
+            });
+            if !is_web && !is_mail {
+                return None;
+            }
+            let address = token
+                .trim_start_matches("https://")
+                .trim_start_matches("http://");
+            let mut words = Vec::new();
+            let mut word = String::new();
+            for c in address.chars() {
+                let name = match c {
+                    '.' => "dot",
+                    '/' => "slash",
+                    '@' => "at",
+                    '-' => "dash",
+                    '_' => "underscore",
+                    ':' => "colon",
+                    '?' => "question mark",
+                    '=' => "equals",
+                    '&' => "and",
+                    '#' => "hash",
+                    _ => {
+                        word.push(c);
+                        continue;
+                    }
+                };
+                if !word.is_empty() {
+                    words.push(word.split_off(0));
+                }
+                words.push(name.to_string());
+            }
+            if !word.is_empty() {
+                words.push(word);
+            }
+            Some((start + token.len(), Reading::spoken(words.join(" "))))
+        })
+    }
+}
+
+/// Amounts of money after a currency symbol, e.g. "$12.50".
+pub struct CurrencyRule {
+    /// Expand the amount in English words.
+    pub english: bool,
+}
+
+impl NormalizeRule for CurrencyRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let symbol = text[start..].chars().next()?;
+            let (unit, units, cent, cents) = match symbol {
+                '$' => ("dollar", "dollars", "cent", "cents"),
+                '€' => ("euro", "euros", "cent", "cents"),
+                '£' => ("pound", "pounds", "penny", "pence"),
+                '¥' => ("yen", "yen", "", ""),
+                _ => return None,
+            };
+            let (end, integer, decimals) =
+                number_at(text, start + symbol.len_utf8())?;
+            let reading = Reading::say_as("currency", None);
+            if !self.english || decimals.len() > 2 {
+                return Some((end, reading));
+            }
+            let amount: u64 = integer.parse().ok()?;
+            let mut words = format!(
+                "{} {}",
+                cardinal_words(amount),
+                if amount == 1 { unit } else { units }
+            );
+            let fraction: u64 = match decimals.len() {
+                1 => decimals.parse::<u64>().ok()? * 10,
+                2 => decimals.parse().ok()?,
+                _ => 0,
+            };
+            if fraction > 0 && !cent.is_empty() {
+                words.push_str(&format!(
+                    " and {} {}",
+                    cardinal_words(fraction),
+                    if fraction == 1 { cent } else { cents }
+                ));
+            }
+            Some((end, reading.or_spoken(Some(words))))
+        })
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Order of the day and the month in dates with slashes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateOrder {
+    /// Month first, e.g. "8/7/2019" of August 7.
+    MonthDay,
+    /// Day first, e.g. "7/8/2019" of August 7.
+    DayMonth,
+}
+
+/// Dates of ISO "2019-08-07" and slashes "8/7/2019".
+pub struct DateRule {
+    pub order: DateOrder,
+    /// Expand the date in English words.
+    pub english: bool,
+}
+
+impl DateRule {
+    /// Month first in the US, day first elsewhere.
+    pub fn for_language(language: &str) -> Self {
+        let lower = language.to_ascii_lowercase();
+        DateRule {
+            order: if lower == "en-us" || lower == "en" {
+                DateOrder::MonthDay
+            } else {
+                DateOrder::DayMonth
+            },
+            english: is_english(language),
+        }
+    }
+
+    fn words(&self, year: u64, month: u64, day: u64) -> String {
+        let month = MONTHS[month as usize - 1];
+        match self.order {
+            DateOrder::MonthDay => format!(
+                "{} {}, {}",
+                month,
+                ordinal_words(day),
+                year_words(year)
+            ),
+            DateOrder::DayMonth => format!(
+                "the {} of {}, {}",
+                ordinal_words(day),
+                month,
+                year_words(year)
+            ),
+        }
+    }
+}
+
+impl NormalizeRule for DateRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let rest = &text[start..];
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '-' && c != '/')
+                .unwrap_or_else(|| rest.len());
+            let token = &rest[..len];
+            let (separator, format) = if token.contains('-') {
+                ('-', "ymd")
+            } else {
+                match self.order {
+                    DateOrder::MonthDay => ('/', "mdy"),
+                    DateOrder::DayMonth => ('/', "dmy"),
+                }
+            };
+            let parts: Vec<&str> = token.split(separator).collect();
+            if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+                return None;
+            }
+            let numbers: Vec<u64> =
+                parts.iter().filter_map(|p| p.parse().ok()).collect();
+            if numbers.len() != 3 {
+                return None;
+            }
+            let (year, month, day) = match format {
+                "ymd" if parts[0].len() == 4 => {
+                    (numbers[0], numbers[1], numbers[2])
+                }
+                "mdy" if parts[2].len() == 4 => {
+                    (numbers[2], numbers[0], numbers[1])
+                }
+                "dmy" if parts[2].len() == 4 => {
+                    (numbers[2], numbers[1], numbers[0])
+                }
+                _ => return None,
+            };
+            if month < 1 || month > 12 || day < 1 || day > 31 {
+                return None;
+            }
+            let words = if self.english {
+                Some(self.words(year, month, day))
+            } else {
+                None
+            };
+            let reading = Reading::say_as("date", Some(format));
+            Some((start + len, reading.or_spoken(words)))
+        })
+    }
+}
+
+/// Times of the day, e.g. "10:30" and "7:05 pm".
+pub struct TimeRule {
+    /// Expand the time in English words.
+    pub english: bool,
+}
+
+impl NormalizeRule for TimeRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let rest = &text[start..];
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != ':')
+                .unwrap_or_else(|| rest.len());
+            let parts: Vec<&str> = rest[..len].split(':').collect();
+            if parts.len() < 2
+                || parts.len() > 3
+                || parts[0].is_empty()
+                || parts[0].len() > 2
+                || parts[1..].iter().any(|p| p.len() != 2)
+            {
+                return None;
+            }
+            let numbers: Vec<u64> =
+                parts.iter().filter_map(|p| p.parse().ok()).collect();
+            if numbers[0] > 23 || numbers[1..].iter().any(|n| *n > 59) {
+                return None;
+            }
+            // Optional "am" or "pm" after a space.
+            let mut end = start + len;
+            let mut meridiem = None;
+            let after = text[end..].trim_start_matches(' ');
+            let lower = after.get(..2).map(str::to_ascii_lowercase);
+            if let Some(m) = lower.filter(|m| m == "am" || m == "pm") {
+                let skipped = text[end..].len() - after.len();
+                if is_word(text, end + skipped, end + skipped + 2) {
+                    end += skipped + 2;
+                    meridiem = Some(m.to_ascii_uppercase());
+                }
+            }
+            let format = if meridiem.is_some() { "hms12" } else { "hms24" };
+            let words = if self.english {
+                let minutes = match numbers[1] {
+                    0 => String::from(" o'clock"),
+                    m if m < 10 => format!(" oh {}", cardinal_words(m)),
+                    m => format!(" {}", cardinal_words(m)),
+                };
+                let mut words =
+                    format!("{}{}", cardinal_words(numbers[0]), minutes);
+                match numbers.get(2) {
+                    Some(1) => words.push_str(" and one second"),
+                    Some(s) if *s > 0 => words.push_str(&format!(
+                        " and {} seconds",
+                        cardinal_words(*s)
+                    )),
+                    _ => {}
+                }
+                if let Some(ref m) = meridiem {
+                    words.push(' ');
+                    words.push_str(m);
+                }
+                Some(words)
+            } else {
+                None
+            };
+            let reading = Reading::say_as("time", Some(format));
+            Some((end, reading.or_spoken(words)))
+        })
+    }
+}
+
+/// Telephone numbers of dashed digit groups, e.g. "555-123-4567".
+pub struct TelephoneRule {
+    /// Expand the digits in English words.
+    pub english: bool,
+}
+
+impl NormalizeRule for TelephoneRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let rest = &text[start..];
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '-')
+                .unwrap_or_else(|| rest.len());
+            let token = rest[..len].trim_end_matches('-');
+            let groups: Vec<&str> = token.split('-').collect();
+            let digits: usize = groups.iter().map(|g| g.len()).sum();
+            if groups.len() < 3
+                || groups.iter().any(|g| g.is_empty())
+                || digits < 7
+                || digits > 15
+            {
+                return None;
+            }
+            let words = if self.english {
+                let groups: Vec<_> =
+                    groups.iter().map(|g| digit_words(g)).collect();
+                Some(groups.join(", "))
+            } else {
+                None
+            };
+            let reading = Reading::say_as("telephone", None);
+            Some((start + token.len(), reading.or_spoken(words)))
+        })
+    }
+}
+
+/// Percentages in English, e.g. "12.5%".
+pub struct PercentRule;
+
+impl NormalizeRule for PercentRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let (end, integer, decimals) = number_at(text, start)?;
+            if !text[end..].starts_with('%') {
+                return None;
+            }
+            let words = number_words(&integer, &decimals) + " percent";
+            Some((end + 1, Reading::spoken(words)))
+        })
+    }
+}
+
+/// Ordinals in English, e.g. "21st".
+pub struct OrdinalRule;
+
+impl NormalizeRule for OrdinalRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let (end, integer, decimals) = number_at(text, start)?;
+            let suffix = text.get(end..end + 2)?.to_ascii_lowercase();
+            let n: u64 = integer.parse().ok()?;
+            let expected = match (n % 10, n % 100) {
+                (_, 11..=13) => "th",
+                (1, _) => "st",
+                (2, _) => "nd",
+                (3, _) => "rd",
+                _ => "th",
+            };
+            if !decimals.is_empty() || suffix != expected {
+                return None;
+            }
+            let reading = Reading::say_as("ordinal", None)
+                .or_spoken(Some(ordinal_words(n)));
+            Some((end + 2, reading))
+        })
+    }
+}
+
+/// Numbers in English, e.g. "1,234.5".
+pub struct NumberRule;
+
+impl NormalizeRule for NumberRule {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            let (end, integer, decimals) = number_at(text, start)?;
+            let interpret_as = if integer.len() > 1 && integer.starts_with('0')
+            {
+                "digits"
+            } else {
+                "cardinal"
+            };
+            let reading = Reading::say_as(interpret_as, None)
+                .or_spoken(Some(number_words(&integer, &decimals)));
+            Some((end, reading))
+        })
+    }
+}
+
+/// Whole words read as others, e.g. abbreviations and acronyms.
+#[derive(Debug, Clone, Default)]
+pub struct Replacements {
+    words: Vec<(String, String)>,
+}
+
+impl Replacements {
+    pub fn new() -> Self {
+        Replacements::default()
+    }
+
+    /// Read the word, matched case sensitively, as the replacement.
+    pub fn add(&mut self, word: &str, replacement: &str) -> &mut Self {
+        self.words.push((word.to_string(), replacement.to_string()));
+        // The longest word matches first.
+        self.words.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self
+    }
+
+    /// Common English abbreviations.
+    pub fn english_abbreviations() -> Self {
+        let mut words = Replacements::new();
+        for (word, replacement) in &[
+            ("Mr.", "Mister"),
+            ("Mrs.", "Missus"),
+            ("Dr.", "Doctor"),
+            ("Prof.", "Professor"),
+            ("Ave.", "Avenue"),
+            ("Jan.", "January"),
+            ("Feb.", "February"),
+            ("Aug.", "August"),
+            ("Sept.", "September"),
+            ("Oct.", "October"),
+            ("Nov.", "November"),
+            ("Dec.", "December"),
+            ("approx.", "approximately"),
+            ("dept.", "department"),
+            ("e.g.", "for example"),
+            ("i.e.", "that is"),
+            ("etc.", "et cetera"),
+            ("vs.", "versus"),
+            ("km", "kilometers"),
+            ("kg", "kilograms"),
+        ] {
+            words.add(word, replacement);
+        }
+        words
+    }
+}
+
+impl NormalizeRule for Replacements {
+    fn find(&self, text: &str) -> Option<(Range<usize>, Reading)> {
+        scan(text, |text, start| {
+            self.words
+                .iter()
+                .find(|(word, _)| text[start..].starts_with(word.as_str()))
+                .map(|(word, replacement)| {
+                    (start + word.len(), Reading::spoken(replacement.as_str()))
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(
+        text: &str,
+        interpret_as: Option<&str>,
+        format: Option<&str>,
+    ) -> Segment {
+        Segment::Read {
+            text: text.to_string(),
+            reading: Reading {
+                interpret_as: interpret_as.map(str::to_string),
+                format: format.map(str::to_string),
+                spoken: None,
+            },
+        }
+    }
+
+    fn plain(text: &str) -> Segment {
+        Segment::Text(text.to_string())
+    }
+
+    /// Segments without the expanded words.
+    fn unspoken(normalizer: &Normalizer, text: &str) -> Vec<Segment> {
+        normalizer
+            .normalize(text)
+            .segments
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Read { text, mut reading } => {
+                    reading.spoken = None;
+                    Segment::Read { text, reading }
+                }
+                text => text,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cardinal_numbers() {
+        let cases = &[
+            (0, "zero"),
+            (7, "seven"),
+            (13, "thirteen"),
+            (20, "twenty"),
+            (21, "twenty-one"),
+            (100, "one hundred"),
+            (105, "one hundred five"),
+            (999, "nine hundred ninety-nine"),
+            (1000, "one thousand"),
+            (1205, "one thousand two hundred five"),
+            (1_000_000, "one million"),
+            (2_000_017, "two million seventeen"),
+            (
+                3_000_400_000_090,
+                "three trillion four hundred million ninety",
+            ),
+            (
+                1_000_000_000_000_000,
+                "one zero zero zero zero zero zero zero zero zero zero zero \
+                 zero zero zero zero",
+            ),
+        ];
+        for (n, words) in cases {
+            assert_eq!(cardinal_words(*n), *words, "{}", n);
+        }
+    }
+
+    #[test]
+    fn ordinal_numbers() {
+        let cases = &[
+            (0, "zeroth"),
+            (1, "first"),
+            (2, "second"),
+            (3, "third"),
+            (4, "fourth"),
+            (5, "fifth"),
+            (8, "eighth"),
+            (9, "ninth"),
+            (11, "eleventh"),
+            (12, "twelfth"),
+            (20, "twentieth"),
+            (21, "twenty-first"),
+            (100, "one hundredth"),
+            (101, "one hundred first"),
+            (1000, "one thousandth"),
+        ];
+        for (n, words) in cases {
+            assert_eq!(ordinal_words(*n), *words, "{}", n);
+        }
+    }
+
+    #[test]
+    fn years() {
+        let cases = &[
+            (999, "nine hundred ninety-nine"),
+            (1066, "ten sixty-six"),
+            (1900, "nineteen hundred"),
+            (1905, "nineteen oh five"),
+            (1984, "nineteen eighty-four"),
+            (2000, "two thousand"),
+            (2005, "two thousand five"),
+            (2010, "twenty ten"),
+            (2019, "twenty nineteen"),
+            (3000, "three thousand"),
+        ];
+        for (year, words) in cases {
+            assert_eq!(year_words(*year), *words, "{}", year);
+        }
+    }
+
+    #[test]
+    fn expands_english_text() {
+        let normalizer = Normalizer::for_language("en-US");
+        let cases = &[
+            ("No numbers here.", "No numbers here."),
+            (
+                "On 8/7/2019 we met",
+                "On August seventh, twenty nineteen we met",
+            ),
+            ("Due 2019-08-07.", "Due August seventh, twenty nineteen."),
+            (
+                "Call 555-123-4567 now",
+                "Call five five five, one two three, four five six seven now",
+            ),
+            (
+                "Pay $12.50 today",
+                "Pay twelve dollars and fifty cents today",
+            ),
+            (
+                "Only $1 or £3.01",
+                "Only one dollar or three pounds and one penny",
+            ),
+            ("Costs ¥500", "Costs five hundred yen"),
+            ("Exactly $1.234", "Exactly $1.234"),
+            ("At 7:05 pm.", "At seven oh five PM."),
+            ("At 10:30 or 9:00 AM", "At ten thirty or nine o'clock AM"),
+            ("At 12:00:01", "At twelve o'clock and one second"),
+            ("At 7:05 pmx", "At seven oh five pmx"),
+            (
+                "Up 12.5% on the 21st",
+                "Up twelve point five percent on the twenty-first",
+            ),
+            (
+                "Sold 1,234.5 of 007",
+                "Sold one thousand two hundred thirty-four point five of zero \
+                 zero seven",
+            ),
+            ("Dr. Smith ran 5 km", "Doctor Smith ran five kilometers"),
+            (
+                "See https://example.com/docs.",
+                "See example dot com slash docs.",
+            ),
+            (
+                "Mail bob_s@example.com, thanks",
+                "Mail bob underscore s at example dot com, thanks",
+            ),
+            (
+                "Visit www.rust-lang.org!",
+                "Visit www dot rust dash lang dot org!",
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(normalizer.to_text(text), *expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn dates_follow_the_language() {
+        let normalizer = Normalizer::for_language("en-GB");
+        assert_eq!(
+            normalizer.to_text("On 7/8/2019"),
+            "On the seventh of August, twenty nineteen"
+        );
+        assert_eq!(
+            unspoken(&normalizer, "13/8/2019"),
+            vec![read("13/8/2019", Some("date"), Some("dmy"))]
+        );
+        // Month first in the US, 13 isn't a month.
+        assert_eq!(
+            Normalizer::for_language("en-US").to_text("13/8/2019"),
+            "thirteen/eight/two thousand nineteen"
+        );
+    }
+
+    #[test]
+    fn other_languages_use_say_as() {
+        let normalizer = Normalizer::for_language("de-DE");
+        let text = "Am 7/8/2019 um 10:30, 555-123-4567 oder 5€";
+        assert_eq!(normalizer.to_text(text), text);
+        assert_eq!(
+            unspoken(&normalizer, "Am 7/8/2019 um 10:30 Uhr"),
+            vec![
+                plain("Am "),
+                read("7/8/2019", Some("date"), Some("dmy")),
+                plain(" um "),
+                read("10:30", Some("time"), Some("hms24")),
+                plain(" Uhr"),
+            ]
+        );
+    }
+
+    #[test]
+    fn earlier_rules_take_precedence() {
+        let normalizer = Normalizer::for_language("en-US");
+        let cases = vec![
+            // Dashed dates aren't telephone numbers.
+            (
+                "2019-08-07",
+                vec![read("2019-08-07", Some("date"), Some("ymd"))],
+            ),
+            (
+                "555-123-4567",
+                vec![read("555-123-4567", Some("telephone"), None)],
+            ),
+            // Amounts aren't plain numbers.
+            ("$5", vec![read("$5", Some("currency"), None)]),
+            // The meridiem belongs to the time.
+            (
+                "7:05 pm.",
+                vec![read("7:05 pm", Some("time"), Some("hms12")), plain(".")],
+            ),
+            ("7:05", vec![read("7:05", Some("time"), Some("hms24"))]),
+            // Numbers of addresses aren't expanded, trailing punctuation
+            // belongs to the sentence.
+            (
+                "www.example2.com/1st.",
+                vec![read("www.example2.com/1st", None, None), plain(".")],
+            ),
+            (
+                "(https://example.com/a?b=1&c=2),",
+                vec![
+                    plain("("),
+                    read("https://example.com/a?b=1&c=2", None, None),
+                    plain("),"),
+                ],
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(unspoken(&normalizer, text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn prepended_rules_override_built_in_ones() {
+        let answer = |text: &str| {
+            text.find("42")
+                .map(|i| (i..i + 2, Reading::spoken("the answer")))
+        };
+        let mut normalizer = Normalizer::for_language("en-US");
+        normalizer.add_rule(answer);
+        assert_eq!(normalizer.to_text("It is 42."), "It is forty-two.");
+
+        let mut normalizer = Normalizer::for_language("en-US");
+        let mut streets = Replacements::new();
+        streets.add("Dr.", "Drive");
+        normalizer.prepend_rule(answer).prepend_rule(streets);
+        assert_eq!(
+            normalizer.to_text("It is 42 on Elm Dr. by Dr. Who, 43."),
+            "It is the answer on Elm Drive by Drive Who, forty-three."
+        );
+    }
+
+    #[test]
+    fn renders_ssml() {
+        let ssml = Normalizer::for_language("en-US").to_ssml(
+            "Call 555-123-4567 at 7:05 pm, see www.example.com.",
+            "en-US-JennyNeural",
+        );
+        assert_eq!(
+            ssml.to_xml().unwrap(),
+            "<speak version=\"1.0\" \
+             xmlns=\"http://www.w3.org/2001/10/synthesis\" \
+             xml:lang=\"en-US\"><voice name=\"en-US-JennyNeural\">Call \
+             <say-as interpret-as=\"telephone\">555-123-4567</say-as> at \
+             <say-as interpret-as=\"time\" format=\"hms12\">7:05 pm</say-as>, \
+             see <sub alias=\"www dot example dot com\">www.example.com</sub>.\
+             </voice></speak>"
+        );
+    }
+}
@@ -0,0 +1,625 @@
+//! Pool of warm recognizers for servers of concurrent requests.
+//!
+//! Recognizers are created ahead with their connections opened, handed out
+//! to one user at a time and reset when returned. The number of recognizers
+//! is limited, callers wait for a free one synchronously or asynchronously.
+
+use super::{
+    builder::RecognizerConfig,
+    recognizer::{Connection, Recognizer},
+};
+use crate::{
+    error::{Poisoned, WouldBlock},
+    Result, SpxError,
+};
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Size limits of the pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Recognizers created ahead and kept idle, the dropped ones are
+    /// replaced in the background.
+    pub min_idle: usize,
+    /// Upper bound of recognizers in use and idle.
+    pub max_size: usize,
+    /// Open the connections for continuous recognition.
+    pub continuous: bool,
+    /// Idle recognizers older than this are dropped when a recognizer is
+    /// taken, and replaced in the background.
+    pub max_idle_time: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_idle: 1,
+            max_size: 8,
+            continuous: true,
+            max_idle_time: None,
+        }
+    }
+}
+
+/// Statistics of the pool.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PoolState {
+    pub idle: usize,
+    pub in_use: usize,
+    pub waiting: usize,
+}
+
+type Factory = dyn Fn() -> Result<Recognizer> + Send + Sync;
+
+/// Idle recognizer with its opened connection.
+struct Warm {
+    reco: Recognizer,
+    connection: Connection,
+    since: Instant,
+}
+
+struct Slots {
+    idle: Vec<Warm>,
+    in_use: usize,
+    /// Replacements being warmed up in the background.
+    warming: usize,
+    /// Tasks of pending futures by their keys.
+    waiters: Vec<(usize, Task)>,
+    next_key: usize,
+    /// Threads blocked in `get`.
+    blocked: usize,
+}
+
+struct Shared {
+    factory: Box<Factory>,
+    config: PoolConfig,
+    slots: Mutex<Slots>,
+    ready: Condvar,
+}
+
+impl Shared {
+    /// Create a recognizer and open its connection.
+    fn warm_up(&self) -> Result<Warm> {
+        let reco = (self.factory)()?;
+        let connection = Connection::from_recognizer(&reco)?;
+        connection.open(self.config.continuous)?;
+        Ok(Warm {
+            reco,
+            connection,
+            since: Instant::now(),
+        })
+    }
+
+    /// Take an idle recognizer or reserve a slot for a new one, None if the
+    /// pool is exhausted. Expired recognizers are moved out to be released
+    /// after the slots are unlocked.
+    fn take(
+        &self,
+        slots: &mut Slots,
+        expired: &mut Vec<Warm>,
+    ) -> Option<Option<Warm>> {
+        if let Some(max) = self.config.max_idle_time {
+            let (old, fresh) = slots
+                .idle
+                .drain(..)
+                .partition(|w: &Warm| w.since.elapsed() >= max);
+            slots.idle = fresh;
+            expired.extend(old);
+        }
+        if let Some(warm) = slots.idle.pop() {
+            slots.in_use += 1;
+            return Some(Some(warm));
+        }
+        if slots.in_use + slots.idle.len() + slots.warming
+            < self.config.max_size
+        {
+            slots.in_use += 1;
+            return Some(None);
+        }
+        None
+    }
+
+    /// Finish the reservation with a warm recognizer.
+    fn fill(shared: &Arc<Shared>, taken: Option<Warm>) -> Result<Pooled> {
+        let warm = match taken {
+            Some(warm) => warm,
+            None => match shared.warm_up() {
+                Ok(warm) => warm,
+                Err(err) => {
+                    shared.vacate(None);
+                    return Err(err);
+                }
+            },
+        };
+        Ok(Pooled {
+            warm: Some(warm),
+            shared: shared.clone(),
+            reusable: true,
+        })
+    }
+
+    /// Release the expired recognizers, the slots must be unlocked, and
+    /// replace them.
+    fn expire(shared: &Arc<Shared>, expired: Vec<Warm>) {
+        if !expired.is_empty() {
+            drop(expired);
+            Shared::replenish(shared);
+        }
+    }
+
+    /// Warm up recognizers in the background until `min_idle` of them are
+    /// idle again.
+    fn replenish(shared: &Arc<Shared>) {
+        let count = match shared.slots.lock() {
+            Ok(mut slots) => {
+                let total = slots.in_use + slots.idle.len() + slots.warming;
+                let count = shared
+                    .config
+                    .min_idle
+                    .saturating_sub(slots.idle.len() + slots.warming)
+                    .min(shared.config.max_size.saturating_sub(total));
+                slots.warming += count;
+                count
+            }
+            Err(_) => return,
+        };
+        if count == 0 {
+            return;
+        }
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for _ in 0..count {
+                let warm = shared
+                    .warm_up()
+                    .map_err(|err| {
+                        log::warn!(
+                            "failed to replace pooled recognizer: {}",
+                            err
+                        )
+                    })
+                    .ok();
+                if let Ok(mut slots) = shared.slots.lock() {
+                    slots.warming -= 1;
+                    slots.idle.extend(warm);
+                    for (_, task) in &slots.waiters {
+                        task.notify();
+                    }
+                }
+                shared.ready.notify_one();
+            }
+        });
+    }
+
+    /// Free the slot, keep the recognizer if any, and wake the waiters.
+    /// Futures stay registered until they are resolved or dropped.
+    fn vacate(&self, warm: Option<Warm>) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.in_use -= 1;
+            if let Some(warm) = warm {
+                slots.idle.push(warm);
+            }
+            for (_, task) in &slots.waiters {
+                task.notify();
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    /// Reset the recognizer for the next user, None if it can't be reused.
+    /// Audio written to the push stream but not closed would be taken as
+    /// the input of the next user, so the recognizer is dropped.
+    fn reset(&self, mut warm: Warm) -> Option<Warm> {
+        if warm.reco.is_stream_closed() || warm.reco.is_stream_pending() {
+            return None;
+        }
+        if warm.reco.started() {
+            if let Err(err) = warm.reco.stop() {
+                log::warn!("failed to stop pooled recognizer: {}", err);
+                return None;
+            }
+        }
+        if let Err(err) = warm.connection.open(self.config.continuous) {
+            log::warn!("failed to reopen pooled connection: {}", err);
+            return None;
+        }
+        warm.reco.reset_audio();
+        warm.since = Instant::now();
+        Some(warm)
+    }
+}
+
+/// Pool of recognizers of the same configuration.
+#[derive(Clone)]
+pub struct RecognizerPool {
+    shared: Arc<Shared>,
+}
+
+impl RecognizerPool {
+    /// Pool of speech recognizers generated by the configuration.
+    pub fn new(config: RecognizerConfig, pool: PoolConfig) -> Result<Self> {
+        RecognizerPool::with_factory(move || config.recognizer(), pool)
+    }
+
+    /// Pool of recognizers generated by the factory, e.g. intent recognizers.
+    pub fn with_factory<F>(factory: F, config: PoolConfig) -> Result<Self>
+    where
+        F: Fn() -> Result<Recognizer> + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            factory: Box::new(factory),
+            slots: Mutex::new(Slots {
+                idle: Vec::new(),
+                in_use: 0,
+                warming: 0,
+                waiters: Vec::new(),
+                next_key: 0,
+                blocked: 0,
+            }),
+            ready: Condvar::new(),
+            config,
+        });
+        let count = shared.config.min_idle.min(shared.config.max_size);
+        let mut idle = Vec::with_capacity(count);
+        for _ in 0..count {
+            idle.push(shared.warm_up()?);
+        }
+        shared.slots.lock().map_err(|_| Poisoned)?.idle = idle;
+        Ok(RecognizerPool { shared })
+    }
+
+    /// Take a recognizer without waiting, `WouldBlock` if exhausted.
+    pub fn try_get(&self) -> Result<Pooled> {
+        let mut expired = Vec::new();
+        let taken = {
+            let mut slots = self.shared.slots.lock().map_err(|_| Poisoned)?;
+            self.shared.take(&mut slots, &mut expired)
+        };
+        Shared::expire(&self.shared, expired);
+        match taken {
+            Some(taken) => Shared::fill(&self.shared, taken),
+            None => Err(WouldBlock),
+        }
+    }
+
+    /// Take a recognizer, block until one is free.
+    pub fn get(&self) -> Result<Pooled> {
+        self.get_timeout(None)
+    }
+
+    /// Take a recognizer, `WouldBlock` if none is free in the timeout.
+    pub fn get_timeout<T: Into<Option<Duration>>>(
+        &self,
+        timeout: T,
+    ) -> Result<Pooled> {
+        let deadline = timeout.into().map(|t| Instant::now() + t);
+        // Declared ahead of the guard to be released after the unlock.
+        let mut expired = Vec::new();
+        let mut slots = self.shared.slots.lock().map_err(|_| Poisoned)?;
+        let taken = loop {
+            if let Some(taken) = self.shared.take(&mut slots, &mut expired) {
+                break taken;
+            }
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(WouldBlock);
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            slots.blocked += 1;
+            let ready = &self.shared.ready;
+            slots = match timeout {
+                Some(timeout) => ready
+                    .wait_timeout(slots, timeout)
+                    .map(|(slots, _)| slots)
+                    .map_err(|_| Poisoned)?,
+                None => ready.wait(slots).map_err(|_| Poisoned)?,
+            };
+            slots.blocked -= 1;
+        };
+        drop(slots);
+        Shared::expire(&self.shared, expired);
+        Shared::fill(&self.shared, taken)
+    }
+
+    /// Future resolved with a free recognizer.
+    pub fn acquire(&self) -> Acquire {
+        Acquire {
+            shared: self.shared.clone(),
+            key: None,
+        }
+    }
+
+    /// Current statistics.
+    pub fn state(&self) -> Result<PoolState> {
+        let slots = self.shared.slots.lock().map_err(|_| Poisoned)?;
+        Ok(PoolState {
+            idle: slots.idle.len(),
+            in_use: slots.in_use,
+            waiting: slots.waiters.len() + slots.blocked,
+        })
+    }
+}
+
+/// Future of a free recognizer in the pool.
+pub struct Acquire {
+    shared: Arc<Shared>,
+    /// Key of the task registered to be woken.
+    key: Option<usize>,
+}
+
+impl Future for Acquire {
+    type Item = Pooled;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Pooled, SpxError> {
+        let mut expired = Vec::new();
+        let taken = {
+            let mut slots = self.shared.slots.lock().map_err(|_| Poisoned)?;
+            let taken = self.shared.take(&mut slots, &mut expired);
+            match (taken.is_some(), self.key) {
+                (true, Some(key)) => {
+                    slots.waiters.retain(|(k, _)| *k != key);
+                    self.key = None;
+                }
+                (true, None) => {}
+                (false, Some(key)) => {
+                    // Woken by another task of the owner, update it in place.
+                    if let Some(waiter) =
+                        slots.waiters.iter_mut().find(|(k, _)| *k == key)
+                    {
+                        waiter.1 = task::current();
+                    }
+                }
+                (false, None) => {
+                    let key = slots.next_key;
+                    slots.next_key = slots.next_key.wrapping_add(1);
+                    slots.waiters.push((key, task::current()));
+                    self.key = Some(key);
+                }
+            }
+            taken
+        };
+        Shared::expire(&self.shared, expired);
+        match taken {
+            Some(taken) => Shared::fill(&self.shared, taken).map(Async::Ready),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Pending future is no longer woken.
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            if let Ok(mut slots) = self.shared.slots.lock() {
+                slots.waiters.retain(|(k, _)| *k != key);
+            }
+        }
+    }
+}
+
+/// Recognizer borrowed from the pool, returned when dropped.
+pub struct Pooled {
+    warm: Option<Warm>,
+    shared: Arc<Shared>,
+    reusable: bool,
+}
+
+impl Pooled {
+    /// Drop the recognizer instead of returning it, e.g. after errors.
+    pub fn discard(mut self) {
+        self.reusable = false;
+    }
+}
+
+impl Deref for Pooled {
+    type Target = Recognizer;
+
+    fn deref(&self) -> &Recognizer {
+        &self.warm.as_ref().expect("pooled recognizer").reco
+    }
+}
+
+impl DerefMut for Pooled {
+    fn deref_mut(&mut self) -> &mut Recognizer {
+        &mut self.warm.as_mut().expect("pooled recognizer").reco
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        let warm = self.warm.take().and_then(|warm| {
+            if self.reusable {
+                self.shared.reset(warm)
+            } else {
+                None
+            }
+        });
+        let dropped = warm.is_none();
+        self.shared.vacate(warm);
+        if dropped {
+            Shared::replenish(&self.shared);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        speech::{audio::Audio, events::Flags},
+        INVALID_HANDLE,
+    };
+    use futures::executor::{spawn, Notify, NotifyHandle};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Notify for Counter {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn pool_with(
+        config: PoolConfig,
+        created: Arc<AtomicUsize>,
+    ) -> RecognizerPool {
+        let factory = move || {
+            created.fetch_add(1, Ordering::SeqCst);
+            let audio = Audio::create_push_input(&Default::default())?;
+            Recognizer::new(INVALID_HANDLE, audio, Flags::empty(), 0)
+        };
+        RecognizerPool::with_factory(factory, config).unwrap()
+    }
+
+    fn pool(max_size: usize, created: Arc<AtomicUsize>) -> RecognizerPool {
+        let config = PoolConfig {
+            min_idle: 1,
+            max_size,
+            ..PoolConfig::default()
+        };
+        pool_with(config, created)
+    }
+
+    /// Wait for the replacements warmed up in the background.
+    fn wait_idle(pool: &RecognizerPool, idle: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.state().unwrap().idle != idle {
+            assert!(
+                Instant::now() < deadline,
+                "idle recognizers are not replaced"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn unused_recognizer_is_returned() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let pool = pool(2, created.clone());
+        drop(pool.get().unwrap());
+        drop(pool.get().unwrap());
+        let state = pool.state().unwrap();
+        assert_eq!((state.idle, state.in_use), (1, 0));
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn written_or_closed_recognizer_is_discarded() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let pool = pool(2, created.clone());
+        let mut reco = pool.get().unwrap();
+        reco.write_stream(&mut [0; 320]).unwrap();
+        assert!(reco.is_stream_pending());
+        drop(reco);
+        wait_idle(&pool, 1);
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+
+        let mut reco = pool.get().unwrap();
+        reco.write_stream(&mut [0; 320]).unwrap();
+        reco.close_stream().unwrap();
+        drop(reco);
+        wait_idle(&pool, 1);
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        let state = pool.state().unwrap();
+        assert_eq!((state.idle, state.in_use), (1, 0));
+    }
+
+    #[test]
+    fn expired_recognizers_are_replaced() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let config = PoolConfig {
+            min_idle: 2,
+            max_size: 4,
+            max_idle_time: Some(Duration::from_millis(10)),
+            ..PoolConfig::default()
+        };
+        let pool = pool_with(config, created.clone());
+        thread::sleep(Duration::from_millis(20));
+        let held = pool.try_get().unwrap();
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        wait_idle(&pool, 2);
+        assert_eq!(created.load(Ordering::SeqCst), 5);
+        assert_eq!(pool.state().unwrap().in_use, 1);
+        drop(held);
+    }
+
+    #[test]
+    fn acquire_registers_once_without_busy_polling() {
+        let pool = pool(1, Arc::new(AtomicUsize::new(0)));
+        let held = pool.try_get().unwrap();
+        let counter = Arc::new(Counter::default());
+        let notify = NotifyHandle::from(counter.clone());
+        let mut acquire = spawn(pool.acquire());
+        for _ in 0..3 {
+            match acquire.poll_future_notify(&notify, 0) {
+                Ok(Async::NotReady) => {}
+                _ => panic!("pool is not exhausted"),
+            }
+        }
+        assert_eq!(pool.state().unwrap().waiting, 1);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        drop(held);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        match acquire.poll_future_notify(&notify, 0) {
+            Ok(Async::Ready(_)) => {}
+            _ => panic!("recognizer is not acquired"),
+        }
+        assert_eq!(pool.state().unwrap().waiting, 0);
+    }
+
+    #[test]
+    fn dropped_acquire_is_unregistered() {
+        let pool = pool(1, Arc::new(AtomicUsize::new(0)));
+        let _held = pool.try_get().unwrap();
+        let notify = NotifyHandle::from(Arc::new(Counter::default()));
+        let mut acquire = spawn(pool.acquire());
+        assert!(acquire
+            .poll_future_notify(&notify, 0)
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(pool.state().unwrap().waiting, 1);
+        drop(acquire);
+        assert_eq!(pool.state().unwrap().waiting, 0);
+    }
+
+    #[test]
+    fn get_waits_for_returned_recognizer() {
+        let pool = pool(1, Arc::new(AtomicUsize::new(0)));
+        let held = pool.get().unwrap();
+        match pool.get_timeout(Duration::from_millis(20)) {
+            Err(WouldBlock) => {}
+            _ => panic!("pool is not exhausted"),
+        }
+        assert_eq!(pool.state().unwrap().waiting, 0);
+
+        let returner = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(held);
+        });
+        let begin = Instant::now();
+        pool.get_timeout(Duration::from_secs(5)).unwrap();
+        assert!(begin.elapsed() >= Duration::from_millis(10));
+        returner.join().unwrap();
+    }
+}
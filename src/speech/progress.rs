@@ -0,0 +1,463 @@
+//! Progress and completion of each synthesis request.
+//!
+//! The synthesizer speaks requests in order of submission, and reports
+//! their events by result id. The tracker binds the id of each started
+//! synthesis to the next submitted request, then follows its audio chunks
+//! until it is completed or canceled.
+
+use super::events::{
+    AsrResult, CancellationError, CancellationResult, Event, Flags, Session,
+    SynthEventResult, Synthesis,
+};
+use crate::{
+    error::{Other, Poisoned},
+    Result, SpxError,
+};
+use futures::{
+    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    task::{self, Task},
+    Async, Future, Poll,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Stage of a synthesis request.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SynthesisState {
+    Queued,
+    Synthesizing,
+    Completed,
+    Canceled,
+}
+
+impl Default for SynthesisState {
+    fn default() -> Self {
+        SynthesisState::Queued
+    }
+}
+
+/// Snapshot of a synthesis request.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SynthesisProgress {
+    /// Result id, known when the synthesis starts.
+    pub id: Option<String>,
+    pub state: SynthesisState,
+    /// Audio bytes synthesized so far.
+    pub bytes: usize,
+    /// Audio chunks synthesized so far.
+    pub chunks: usize,
+    /// Time from the request to the first audio chunk.
+    pub first_audio_latency: Option<Duration>,
+    /// Time since the request.
+    pub elapsed: Duration,
+}
+
+/// Final result of a synthesis request.
+#[derive(Debug)]
+pub enum SynthesisOutcome {
+    Completed(Synthesis),
+    /// Canceled by the service, or by `Synthesizer::stop_speaking` with
+    /// the code of no error.
+    Canceled(CancellationError),
+}
+
+impl SynthesisOutcome {
+    /// Synthesis if it is completed, else the cancellation as error.
+    pub fn into_result(self) -> Result<Synthesis> {
+        match self {
+            SynthesisOutcome::Completed(synthesis) => Ok(synthesis),
+            SynthesisOutcome::Canceled(err) => Err(err.into()),
+        }
+    }
+}
+
+struct Request {
+    submitted: Instant,
+    progress: SynthesisProgress,
+    outcome: Option<Result<SynthesisOutcome>>,
+    task: Option<Task>,
+    watchers: Vec<UnboundedSender<SynthesisProgress>>,
+}
+
+type RequestRef = Arc<Mutex<Request>>;
+
+impl Request {
+    fn snapshot(&self) -> SynthesisProgress {
+        let mut progress = self.progress.clone();
+        progress.elapsed = self.submitted.elapsed();
+        progress
+    }
+
+    /// Post the progress to watchers, dropped ones are removed.
+    fn notify(&mut self) {
+        let progress = self.snapshot();
+        self.watchers
+            .retain(|w| w.unbounded_send(progress.clone()).is_ok());
+    }
+
+    fn finish(&mut self, outcome: Result<SynthesisOutcome>) {
+        self.progress.state = match outcome {
+            Ok(SynthesisOutcome::Completed(_)) => SynthesisState::Completed,
+            _ => SynthesisState::Canceled,
+        };
+        self.outcome = Some(outcome);
+        self.notify();
+        self.watchers.clear();
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Requests {
+    /// Submitted requests not started yet, in order.
+    queued: VecDeque<RequestRef>,
+    /// Started requests by result id.
+    started: HashMap<String, RequestRef>,
+}
+
+/// Requests of a synthesizer, fed by its events.
+#[derive(Default)]
+pub(crate) struct Tracker {
+    requests: Mutex<Requests>,
+}
+
+impl Tracker {
+    /// Queue a request before it is passed to the synthesizer.
+    pub(crate) fn submit(&self) -> Result<SynthesisTask> {
+        let request = Arc::new(Mutex::new(Request {
+            submitted: Instant::now(),
+            progress: SynthesisProgress::default(),
+            outcome: None,
+            task: None,
+            watchers: Vec::new(),
+        }));
+        self.requests
+            .lock()
+            .map_err(|_| Poisoned)?
+            .queued
+            .push_back(request.clone());
+        Ok(SynthesisTask { request })
+    }
+
+    /// Remove the request failed to be passed to the synthesizer.
+    pub(crate) fn withdraw(&self, task: &SynthesisTask) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.queued.retain(|r| !Arc::ptr_eq(r, &task.request));
+        }
+    }
+
+    /// Follow the request of the event, in the callback thread.
+    pub(crate) fn on_event(&self, evt: &Event) {
+        let flag = evt.flag();
+        if !flag.intersects(
+            Flags::SessionStarted
+                | Flags::Synthesizing
+                | Flags::Synthesized
+                | Flags::Canceled,
+        ) {
+            return;
+        }
+        let mut requests = match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(_) => return,
+        };
+        if requests.queued.is_empty() && requests.started.is_empty() {
+            return;
+        }
+        let result = match SynthEventResult::from_event_ref(evt) {
+            Ok(result) => result,
+            Err(err) => {
+                log::error!("failed to get result of {:?}: {}", flag, err);
+                return;
+            }
+        };
+        let id = match result.id() {
+            Ok(id) => id,
+            Err(err) => {
+                log::error!("failed to get result id of {:?}: {}", flag, err);
+                return;
+            }
+        };
+        let length = if flag.intersects(Flags::Synthesizing) {
+            result.audio_data_length().unwrap_or_default() as usize
+        } else {
+            0
+        };
+        requests.update(flag, id, length, || outcome(&result, flag));
+    }
+}
+
+impl Requests {
+    /// Apply the event of the result id, `length` is the audio length of
+    /// synthesizing events and `outcome` resolves the finished request.
+    fn update<F>(&mut self, flag: Flags, id: String, length: usize, outcome: F)
+    where
+        F: FnOnce() -> Result<SynthesisOutcome>,
+    {
+        if flag.intersects(Flags::SessionStarted) {
+            if let Some(request) = self.queued.pop_front() {
+                if let Ok(mut req) = request.lock() {
+                    req.progress.id = Some(id.clone());
+                    req.progress.state = SynthesisState::Synthesizing;
+                    req.notify();
+                }
+                self.started.insert(id, request);
+            }
+        } else if flag.intersects(Flags::Synthesizing) {
+            if let Some(request) = self.started.get(&id) {
+                if let Ok(mut req) = request.lock() {
+                    if req.progress.first_audio_latency.is_none() {
+                        req.progress.first_audio_latency =
+                            Some(req.submitted.elapsed());
+                    }
+                    req.progress.bytes += length;
+                    req.progress.chunks += 1;
+                    req.notify();
+                }
+            }
+        } else if flag.intersects(Flags::Synthesized | Flags::Canceled) {
+            // Canceled before start has no started request.
+            let request = match self.started.remove(&id) {
+                Some(request) => Some(request),
+                None => self.queued.pop_front(),
+            };
+            if let Some(request) = request {
+                let outcome = outcome();
+                if let Ok(mut req) = request.lock() {
+                    if req.progress.id.is_none() {
+                        req.progress.id = Some(id);
+                    }
+                    req.finish(outcome);
+                }
+            }
+        }
+    }
+}
+
+fn outcome(result: &SynthEventResult, flag: Flags) -> Result<SynthesisOutcome> {
+    if result.reason().intersects(Flags::Canceled) {
+        return result.cancellation().map(SynthesisOutcome::Canceled);
+    }
+    result.synthesis(flag).map(SynthesisOutcome::Completed)
+}
+
+/// Requests still pending when the synthesizer is dropped are failed.
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        if let Ok(mut requests) = self.requests.lock() {
+            let started: Vec<_> =
+                requests.started.drain().map(|(_, r)| r).collect();
+            for request in requests.queued.drain(..).chain(started) {
+                if let Ok(mut req) = request.lock() {
+                    req.finish(Err(Other(String::from(
+                        "synthesizer is dropped",
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+/// Handle of a synthesis request, also the future of its outcome.
+pub struct SynthesisTask {
+    request: RequestRef,
+}
+
+impl SynthesisTask {
+    /// Result id of the request, None until the synthesis starts.
+    pub fn id(&self) -> Result<Option<String>> {
+        Ok(self
+            .request
+            .lock()
+            .map_err(|_| Poisoned)?
+            .progress
+            .id
+            .clone())
+    }
+
+    pub fn progress(&self) -> Result<SynthesisProgress> {
+        Ok(self.request.lock().map_err(|_| Poisoned)?.snapshot())
+    }
+
+    /// Stream of the progress on each update, ends when the request is
+    /// completed or canceled.
+    pub fn progress_stream(
+        &self,
+    ) -> Result<UnboundedReceiver<SynthesisProgress>> {
+        let (s, r) = unbounded();
+        let mut request = self.request.lock().map_err(|_| Poisoned)?;
+        if s.unbounded_send(request.snapshot()).is_ok()
+            && request.outcome.is_none()
+            && request.progress.state != SynthesisState::Completed
+            && request.progress.state != SynthesisState::Canceled
+        {
+            request.watchers.push(s);
+        }
+        Ok(r)
+    }
+}
+
+impl Future for SynthesisTask {
+    type Item = SynthesisOutcome;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<SynthesisOutcome, SpxError> {
+        let mut request = self.request.lock().map_err(|_| Poisoned)?;
+        match request.outcome.take() {
+            Some(outcome) => outcome.map(Async::Ready),
+            None if request.progress.state == SynthesisState::Queued
+                || request.progress.state == SynthesisState::Synthesizing =>
+            {
+                request.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            None => Err(Other(String::from("request is resolved already"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{speech_api::*, INVALID_HANDLE};
+    use futures::Stream;
+    use serde_json::json;
+
+    fn completed(id: &str, audio: &[u8]) -> Result<SynthesisOutcome> {
+        Ok(SynthesisOutcome::Completed(Synthesis {
+            flag: Flags::Synthesized,
+            id: id.to_string(),
+            reason: Flags::Synthesized,
+            audio_length: audio.len(),
+            audio_data: audio.to_vec(),
+            format: None,
+        }))
+    }
+
+    fn canceled() -> Result<SynthesisOutcome> {
+        let err = serde_json::from_value(json!({
+            "reason": Result_CancellationReason_CancellationReason_Error,
+            "code": Result_CancellationErrorCode_CancellationErrorCode_NoError,
+            "details": "",
+        }))
+        .unwrap();
+        Ok(SynthesisOutcome::Canceled(err))
+    }
+
+    fn update<F>(tracker: &Tracker, flag: Flags, id: &str, len: usize, f: F)
+    where
+        F: FnOnce() -> Result<SynthesisOutcome>,
+    {
+        tracker
+            .requests
+            .lock()
+            .unwrap()
+            .update(flag, id.to_string(), len, f);
+    }
+
+    fn state(task: &SynthesisTask) -> (Option<String>, SynthesisState) {
+        let progress = task.progress().unwrap();
+        (progress.id, progress.state)
+    }
+
+    fn no_outcome() -> Result<SynthesisOutcome> {
+        panic!("unexpected outcome")
+    }
+
+    #[test]
+    fn binds_started_and_canceled_requests_in_order() {
+        let tracker = Tracker::default();
+        let first = tracker.submit().unwrap();
+        let second = tracker.submit().unwrap();
+        let third = tracker.submit().unwrap();
+        let progress = first.progress_stream().unwrap();
+
+        update(&tracker, Flags::SessionStarted, "a", 0, no_outcome);
+        assert_eq!(
+            state(&first),
+            (Some("a".into()), SynthesisState::Synthesizing)
+        );
+        assert_eq!(state(&second), (None, SynthesisState::Queued));
+
+        update(&tracker, Flags::Synthesizing, "a", 100, no_outcome);
+        update(&tracker, Flags::Synthesizing, "a", 60, no_outcome);
+        // Audio of unknown ids isn't counted.
+        update(&tracker, Flags::Synthesizing, "b", 10, no_outcome);
+        let snapshot = first.progress().unwrap();
+        assert_eq!((snapshot.bytes, snapshot.chunks), (160, 2));
+        assert!(snapshot.first_audio_latency.is_some());
+
+        // Canceled before start resolves the next queued request.
+        update(&tracker, Flags::Canceled, "b", 0, canceled);
+        assert_eq!(
+            state(&second),
+            (Some("b".into()), SynthesisState::Canceled)
+        );
+        match second.wait() {
+            Ok(SynthesisOutcome::Canceled(_)) => {}
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        update(&tracker, Flags::SessionStarted, "c", 0, no_outcome);
+        assert_eq!(
+            state(&third),
+            (Some("c".into()), SynthesisState::Synthesizing)
+        );
+
+        update(&tracker, Flags::Synthesized, "a", 0, || {
+            completed("a", &[1, 2])
+        });
+        match first.wait().unwrap() {
+            SynthesisOutcome::Completed(synthesis) => {
+                assert_eq!(synthesis.audio_data, vec![1, 2])
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        let states: Vec<_> =
+            progress.wait().map(|p| p.unwrap().state).collect();
+        assert_eq!(
+            states,
+            vec![
+                SynthesisState::Queued,
+                SynthesisState::Synthesizing,
+                SynthesisState::Synthesizing,
+                SynthesisState::Synthesizing,
+                SynthesisState::Completed,
+            ]
+        );
+
+        update(&tracker, Flags::Canceled, "c", 0, canceled);
+        assert_eq!(state(&third).1, SynthesisState::Canceled);
+        // Events without requests are ignored.
+        update(&tracker, Flags::SessionStarted, "d", 0, no_outcome);
+        update(&tracker, Flags::Synthesized, "d", 0, no_outcome);
+    }
+
+    #[test]
+    fn ignores_other_events() {
+        let tracker = Tracker::default();
+        let task = tracker.submit().unwrap();
+        for flag in &[Flags::Recognized, Flags::BookmarkReached] {
+            tracker.on_event(&Event::new(*flag, INVALID_HANDLE));
+        }
+        assert_eq!(state(&task), (None, SynthesisState::Queued));
+        assert_eq!(tracker.requests.lock().unwrap().queued.len(), 1);
+    }
+
+    #[test]
+    fn dropped_tracker_fails_pending_requests() {
+        let tracker = Tracker::default();
+        let started = tracker.submit().unwrap();
+        let queued = tracker.submit().unwrap();
+        update(&tracker, Flags::SessionStarted, "a", 0, no_outcome);
+        drop(tracker);
+        assert!(started.wait().is_err());
+        assert!(queued.wait().is_err());
+    }
+}
@@ -0,0 +1,615 @@
+//! Queue of synthesis requests for voice assistants.
+//!
+//! Requests are spoken one at a time by a background thread, in order of
+//! priority and then of arrival. Each request has a handle to cancel it and
+//! to wait its completion. The speech in progress can be stopped at once,
+//! e.g. when the user starts speaking.
+
+use super::{
+    events::{Flags, Synthesis},
+    ssml::AsSsml,
+    synthesizer::Synthesizer,
+};
+use crate::{
+    error::{Other, Poisoned},
+    Result, SpxError,
+};
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Timeout of each wait of the synthesis result by the waiter thread.
+const WAIT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Order of the requests, `Urgent` also stops the speech in progress.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// Text or SSML to speak.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeechInput {
+    Text(String),
+    Ssml(String),
+}
+
+impl SpeechInput {
+    /// SSML input, documents are validated here.
+    pub fn ssml<S: AsSsml + ?Sized>(ssml: &S) -> Result<Self> {
+        Ok(SpeechInput::Ssml(ssml.as_ssml()?.into_owned()))
+    }
+}
+
+impl<'a> From<&'a str> for SpeechInput {
+    fn from(text: &'a str) -> Self {
+        SpeechInput::Text(text.to_string())
+    }
+}
+
+impl From<String> for SpeechInput {
+    fn from(text: String) -> Self {
+        SpeechInput::Text(text)
+    }
+}
+
+/// Progress of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RequestState {
+    Queued,
+    Speaking,
+    Completed,
+    Canceled,
+    Failed,
+}
+
+/// Shared state of a request and its handle.
+struct Request {
+    state: RequestState,
+    outcome: Option<Result<Option<Synthesis>>>,
+    task: Option<Task>,
+}
+
+type RequestRef = Arc<Mutex<Request>>;
+
+fn finish(request: &RequestRef, outcome: Result<Option<Synthesis>>) {
+    let task = match request.lock() {
+        Ok(mut req) => {
+            req.state = match outcome {
+                Ok(Some(_)) => RequestState::Completed,
+                Ok(None) => RequestState::Canceled,
+                Err(_) => RequestState::Failed,
+            };
+            req.outcome = Some(outcome);
+            req.task.take()
+        }
+        Err(_) => return,
+    };
+    if let Some(task) = task {
+        task.notify();
+    }
+}
+
+struct Entry {
+    id: u64,
+    priority: Priority,
+    input: SpeechInput,
+    request: RequestRef,
+}
+
+struct Pending {
+    entries: Vec<Entry>,
+    next_id: u64,
+    /// Request in progress.
+    current: Option<u64>,
+    /// Stop the request in progress.
+    stop: bool,
+    /// Outcome of the request in progress, set by its waiter thread.
+    done: Option<Result<Option<Synthesis>>>,
+    closed: bool,
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Shared {
+            pending: Mutex::new(Pending {
+                entries: Vec::new(),
+                next_id: 1,
+                current: None,
+                stop: false,
+                done: None,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queue the request, `Urgent` stops the one in progress.
+    fn push(
+        &self,
+        input: SpeechInput,
+        priority: Priority,
+    ) -> Result<(u64, RequestRef)> {
+        let request = Arc::new(Mutex::new(Request {
+            state: RequestState::Queued,
+            outcome: None,
+            task: None,
+        }));
+        let id = {
+            let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+            if pending.closed {
+                return Err(Other(String::from("synthesis queue is closed")));
+            }
+            let id = pending.next_id;
+            pending.next_id += 1;
+            pending.entries.push(Entry {
+                id,
+                priority,
+                input,
+                request: request.clone(),
+            });
+            if priority == Priority::Urgent {
+                pending.stop = pending.current.is_some();
+            }
+            id
+        };
+        self.ready.notify_all();
+        Ok((id, request))
+    }
+
+    /// Take the next request, wait if there is none, None if closed.
+    fn next(&self) -> Result<Option<Entry>> {
+        let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+        loop {
+            if pending.closed {
+                pending.current = None;
+                return Ok(None);
+            }
+            if !pending.entries.is_empty() {
+                // The first of the highest priority.
+                let mut index = 0;
+                for (i, e) in pending.entries.iter().enumerate() {
+                    if e.priority > pending.entries[index].priority {
+                        index = i;
+                    }
+                }
+                let entry = pending.entries.remove(index);
+                pending.current = Some(entry.id);
+                pending.stop = false;
+                pending.done = None;
+                return Ok(Some(entry));
+            }
+            pending.current = None;
+            pending = self.ready.wait(pending).map_err(|_| Poisoned)?;
+        }
+    }
+
+    /// Wait the outcome of the request in progress, None if it should be
+    /// stopped first.
+    fn wait_done(&self) -> Result<Option<Result<Option<Synthesis>>>> {
+        let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+        loop {
+            if let Some(outcome) = pending.done.take() {
+                return Ok(Some(outcome));
+            }
+            if pending.stop || pending.closed {
+                return Ok(None);
+            }
+            pending = self.ready.wait(pending).map_err(|_| Poisoned)?;
+        }
+    }
+
+    /// Wait the outcome of the stopped request.
+    fn wait_stopped(&self) -> Result {
+        let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+        while pending.done.take().is_none() {
+            pending = self.ready.wait(pending).map_err(|_| Poisoned)?;
+        }
+        Ok(())
+    }
+
+    /// Report the outcome of the request and wake the worker, it is
+    /// dropped if the request is no longer in progress.
+    fn complete(&self, id: u64, outcome: Result<Option<Synthesis>>) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if pending.current == Some(id) {
+                pending.done = Some(outcome);
+            }
+        }
+        self.ready.notify_all();
+    }
+
+    /// Remove the queued requests, all if id is not set.
+    fn remove(&self, id: Option<u64>) -> Result<Vec<RequestRef>> {
+        let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+        let (removed, kept) = pending
+            .entries
+            .drain(..)
+            .partition(|e| id.map_or(true, |id| e.id == id));
+        pending.entries = kept;
+        Ok(removed.into_iter().map(|e: Entry| e.request).collect())
+    }
+
+    /// Stop the request in progress, only if it is the id if set.
+    fn stop(&self, id: Option<u64>) -> Result<bool> {
+        let stopped = {
+            let mut pending = self.pending.lock().map_err(|_| Poisoned)?;
+            match pending.current {
+                Some(current) if id.map_or(true, |id| id == current) => {
+                    pending.stop = true;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if stopped {
+            self.ready.notify_all();
+        }
+        Ok(stopped)
+    }
+
+    fn cancel(&self, id: Option<u64>) -> Result {
+        for request in self.remove(id)? {
+            finish(&request, Ok(None));
+        }
+        Ok(())
+    }
+}
+
+/// Closes the queue when the last clone is dropped.
+struct Owner {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.shared.pending.lock() {
+            pending.closed = true;
+        }
+        self.shared.ready.notify_all();
+    }
+}
+
+/// Queue of synthesis requests spoken by one synthesizer.
+#[derive(Clone)]
+pub struct SynthesisQueue {
+    shared: Arc<Shared>,
+    _owner: Arc<Owner>,
+}
+
+impl SynthesisQueue {
+    /// Start speaking the requests by the synthesizer, the queue is closed
+    /// and the pending requests canceled when all the clones are dropped.
+    pub fn new(synth: Synthesizer) -> Self {
+        let shared = Arc::new(Shared::new());
+        let worker = shared.clone();
+        thread::spawn(move || speak_all(synth, worker));
+        SynthesisQueue {
+            _owner: Arc::new(Owner {
+                shared: shared.clone(),
+            }),
+            shared,
+        }
+    }
+
+    /// Queue the request.
+    pub fn speak<I: Into<SpeechInput>>(
+        &self,
+        input: I,
+        priority: Priority,
+    ) -> Result<SpeechHandle> {
+        let (id, request) = self.shared.push(input.into(), priority)?;
+        Ok(SpeechHandle {
+            id,
+            request,
+            shared: self.shared.clone(),
+        })
+    }
+
+    /// Stop the speech in progress, the queued requests go on.
+    pub fn stop_speaking(&self) -> Result {
+        self.shared.stop(None).map(|_| ())
+    }
+
+    /// Cancel the queued requests, the speech in progress goes on.
+    pub fn clear(&self) -> Result {
+        self.shared.cancel(None)
+    }
+
+    /// Stop the speech in progress and cancel the queued requests.
+    pub fn barge_in(&self) -> Result {
+        self.shared.cancel(None)?;
+        self.stop_speaking()
+    }
+
+    /// Barge in whenever the user starts speaking, by the flags of the
+    /// events of a parallel recognizer, e.g. `events.map(|e| e.flag())`.
+    pub fn barge_in_on<S>(
+        &self,
+        flags: S,
+    ) -> impl Future<Item = (), Error = S::Error>
+    where
+        S: Stream<Item = Flags>,
+    {
+        let queue = self.clone();
+        flags.for_each(move |flag| {
+            if flag.intersects(Flags::SpeechStartDetected) {
+                if let Err(err) = queue.barge_in() {
+                    log::error!("failed to barge in: {}", err);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Number of the queued requests.
+    pub fn len(&self) -> Result<usize> {
+        Ok(self
+            .shared
+            .pending
+            .lock()
+            .map_err(|_| Poisoned)?
+            .entries
+            .len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.len().map(|n| n == 0)
+    }
+
+    /// Check a request is in progress.
+    pub fn is_speaking(&self) -> Result<bool> {
+        let pending = self.shared.pending.lock().map_err(|_| Poisoned)?;
+        Ok(pending.current.is_some())
+    }
+}
+
+/// Handle of a queued request, also the future of its completion which is
+/// resolved with None if it is canceled.
+pub struct SpeechHandle {
+    id: u64,
+    request: RequestRef,
+    shared: Arc<Shared>,
+}
+
+impl SpeechHandle {
+    /// Sequence number of the request in the queue.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn state(&self) -> Result<RequestState> {
+        Ok(self.request.lock().map_err(|_| Poisoned)?.state)
+    }
+
+    /// Remove the request from the queue, or stop it if it is in progress.
+    pub fn cancel(&self) -> Result {
+        self.shared.cancel(Some(self.id))?;
+        self.shared.stop(Some(self.id)).map(|_| ())
+    }
+}
+
+impl Future for SpeechHandle {
+    type Item = Option<Synthesis>;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<Synthesis>, SpxError> {
+        let mut request = self.request.lock().map_err(|_| Poisoned)?;
+        match request.outcome.take() {
+            Some(outcome) => outcome.map(Async::Ready),
+            None if request.state == RequestState::Queued
+                || request.state == RequestState::Speaking =>
+            {
+                request.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            None => Err(Other(String::from("request is resolved already"))),
+        }
+    }
+}
+
+/// Speak the requests until the queue is closed.
+fn speak_all(mut synth: Synthesizer, shared: Arc<Shared>) {
+    loop {
+        let entry = match shared.next() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("synthesis queue is broken: {}", err);
+                break;
+            }
+        };
+        if let Ok(mut request) = entry.request.lock() {
+            request.state = RequestState::Speaking;
+        }
+        let outcome = speak(&mut synth, &entry, &shared);
+        if let Err(ref err) = outcome {
+            log::warn!("synthesis request {} failed: {}", entry.id, err);
+        }
+        finish(&entry.request, outcome);
+    }
+    if let Err(err) = shared.cancel(None) {
+        log::error!("failed to cancel pending requests: {}", err);
+    }
+}
+
+/// Speak one request, None if it is stopped. The result is waited by
+/// another thread, so the worker is woken at once by a stop.
+fn speak(
+    synth: &mut Synthesizer,
+    entry: &Entry,
+    shared: &Arc<Shared>,
+) -> Result<Option<Synthesis>> {
+    let task = match &entry.input {
+        SpeechInput::Text(text) => synth.speak_async(text)?,
+        SpeechInput::Ssml(ssml) => synth.speak_ssml_async(ssml.as_str())?,
+    };
+    let (id, waiter) = (entry.id, shared.clone());
+    thread::spawn(move || {
+        let outcome = loop {
+            match task.wait_for(WAIT_INTERVAL) {
+                Ok(Some(result)) => {
+                    break result.synthesis(Flags::Synthesized).map(Some)
+                }
+                Ok(None) => continue,
+                Err(err) => break Err(err),
+            }
+        };
+        waiter.complete(id, outcome);
+    });
+    if let Some(outcome) = shared.wait_done()? {
+        return outcome;
+    }
+    synth.stop_speaking()?;
+    // The stopped request ends before the next one starts.
+    shared.wait_stopped()?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::time::Instant;
+
+    /// Queue without worker, the requests are taken by the test.
+    fn queue() -> SynthesisQueue {
+        let shared = Arc::new(Shared::new());
+        SynthesisQueue {
+            _owner: Arc::new(Owner {
+                shared: shared.clone(),
+            }),
+            shared,
+        }
+    }
+
+    fn take(queue: &SynthesisQueue) -> SpeechInput {
+        queue.shared.next().unwrap().unwrap().input
+    }
+
+    fn stopping(queue: &SynthesisQueue) -> bool {
+        queue.shared.pending.lock().unwrap().stop
+    }
+
+    #[test]
+    fn priority_then_arrival() {
+        let queue = queue();
+        queue.speak("a", Priority::Low).unwrap();
+        queue.speak("b", Priority::Normal).unwrap();
+        queue.speak("c", Priority::High).unwrap();
+        queue.speak("d", Priority::Normal).unwrap();
+        assert_eq!(queue.len().unwrap(), 4);
+        for text in &["c", "b", "d", "a"] {
+            assert_eq!(take(&queue), SpeechInput::from(*text));
+        }
+        assert!(queue.is_speaking().unwrap());
+        assert!(queue.is_empty().unwrap());
+    }
+
+    #[test]
+    fn cancel_removes_or_stops() {
+        let queue = queue();
+        let first = queue.speak("first", Priority::Normal).unwrap();
+        let second = queue.speak("second", Priority::Normal).unwrap();
+        second.cancel().unwrap();
+        assert_eq!(second.state().unwrap(), RequestState::Canceled);
+        assert!(second.wait().unwrap().is_none());
+        assert_eq!(queue.len().unwrap(), 1);
+        assert!(!stopping(&queue));
+
+        assert_eq!(take(&queue), SpeechInput::from("first"));
+        first.cancel().unwrap();
+        assert!(stopping(&queue));
+        assert_eq!(first.state().unwrap(), RequestState::Queued);
+    }
+
+    #[test]
+    fn urgent_preempts_speech_in_progress() {
+        let queue = queue();
+        queue.speak("later", Priority::Urgent).unwrap();
+        assert!(!stopping(&queue));
+        take(&queue);
+
+        queue.speak("normal", Priority::Normal).unwrap();
+        assert!(!stopping(&queue));
+        queue.speak("urgent", Priority::Urgent).unwrap();
+        assert!(stopping(&queue));
+        assert_eq!(take(&queue), SpeechInput::from("urgent"));
+        assert!(!stopping(&queue));
+        assert_eq!(take(&queue), SpeechInput::from("normal"));
+    }
+
+    #[test]
+    fn barge_in_stops_and_clears() {
+        let queue = queue();
+        let handles: Vec<_> = ["one", "two", "three"]
+            .iter()
+            .map(|text| queue.speak(*text, Priority::Normal).unwrap())
+            .collect();
+        take(&queue);
+        queue
+            .barge_in_on(stream::iter_ok::<_, ()>(vec![
+                Flags::SessionStarted,
+                Flags::Recognizing,
+            ]))
+            .wait()
+            .unwrap();
+        assert_eq!(queue.len().unwrap(), 2);
+        assert!(!stopping(&queue));
+
+        queue
+            .barge_in_on(stream::iter_ok::<_, ()>(vec![
+                Flags::SpeechStartDetected,
+            ]))
+            .wait()
+            .unwrap();
+        assert!(queue.is_empty().unwrap());
+        assert!(stopping(&queue));
+        for handle in &handles[1..] {
+            assert_eq!(handle.state().unwrap(), RequestState::Canceled);
+        }
+    }
+
+    #[test]
+    fn stop_wakes_the_worker() {
+        let queue = queue();
+        queue.speak("long", Priority::Normal).unwrap();
+        let id = queue.shared.next().unwrap().unwrap().id;
+        let worker = queue.shared.clone();
+        let waiting = thread::spawn(move || worker.wait_done().unwrap());
+        thread::sleep(Duration::from_millis(20));
+        let begin = Instant::now();
+        queue.barge_in().unwrap();
+        assert!(waiting.join().unwrap().is_none());
+        assert!(begin.elapsed() < Duration::from_secs(5));
+
+        // Outcome of a request no longer in progress is dropped.
+        queue.shared.complete(id + 1, Ok(None));
+        assert!(queue.shared.pending.lock().unwrap().done.is_none());
+        queue.shared.complete(id, Ok(None));
+        queue.shared.wait_stopped().unwrap();
+    }
+}
@@ -2,13 +2,21 @@
 
 use super::{
     audio::{Audio, AudioStream},
-    events::{Event, EventResult, Flags, Recognition, Session},
+    auth::TokenTarget,
+    events::{Event, EventResult, Flags, OwnedEvent, Recognition, Session},
+    message::{on_message_received, ConnectionMessage, MessageStream},
+    recorder::Recorder,
+    vad::{Activity, VoiceDetector},
 };
 use crate::{
     error::{AlreadyExists, Other, SpxError},
     hr,
+    properties::{
+        Profanity, Properties, Property, PropertyBag, ResultFormat,
+        TypedProperties,
+    },
     speech_api::*,
-    Handle, Result, SmartHandle, INVALID_HANDLE,
+    FlattenProps, Handle, Result, SmartHandle, INVALID_HANDLE,
 };
 use futures::{
     sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
@@ -19,6 +27,7 @@ use std::{
     os::raw::c_void,
     ptr::null,
     sync::{Arc, Weak},
+    time::Duration,
 };
 
 /// The event callback definition macro.
@@ -63,6 +72,52 @@ impl Connection {
     pub fn close(&self) -> Result {
         hr!(connection_close(self.handle))
     }
+
+    /// Send custom message to the service, e.g. "speech.context" payload.
+    pub fn send_message(&self, path: &str, payload: &str) -> Result {
+        let path = CString::new(path)?;
+        let payload = CString::new(payload)?;
+        hr!(connection_send_message(
+            self.handle,
+            path.as_ptr(),
+            payload.as_ptr()
+        ))
+    }
+
+    /// Set the property of the messages sent with the path.
+    pub fn set_message_property(
+        &self,
+        path: &str,
+        name: &str,
+        value: &str,
+    ) -> Result {
+        let path = CString::new(path)?;
+        let name = CString::new(name)?;
+        let value = CString::new(value)?;
+        hr!(connection_set_message_property(
+            self.handle,
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr()
+        ))
+    }
+
+    /// Subscribe the raw messages received from the service.
+    pub fn messages(self) -> Result<MessageStream> {
+        let (s, source) = unbounded::<ConnectionMessage>();
+        let sink = Arc::new(s);
+        let context = Box::into_raw(Box::new(Arc::downgrade(&sink)));
+        hr!(connection_message_received_set_callback(
+            self.handle,
+            Some(on_message_received),
+            context as *mut c_void,
+        ))?;
+        Ok(MessageStream {
+            source,
+            sink,
+            connection: self,
+        })
+    }
 }
 
 SmartHandle!(
@@ -115,6 +170,50 @@ impl IntentTrigger {
     }
 }
 
+SmartHandle!(
+    PhraseListGrammar,
+    SPXGRAMMARHANDLE,
+    grammar_handle_release,
+    grammar_handle_is_valid
+);
+
+SmartHandle!(
+    GrammarPhrase,
+    SPXPHRASEHANDLE,
+    grammar_phrase_handle_release,
+    grammar_phrase_handle_is_valid
+);
+
+impl PhraseListGrammar {
+    /// Phrase list of the recognizer.
+    pub fn from_recognizer(reco: &Recognizer) -> Result<Self> {
+        let mut handle = INVALID_HANDLE;
+        let name = CString::new("")?;
+        hr!(phrase_list_grammar_from_recognizer_by_name(
+            &mut handle,
+            reco.handle(),
+            name.as_ptr()
+        ))?;
+        Ok(PhraseListGrammar::new(handle))
+    }
+
+    /// Add a phrase more likely to be recognized, e.g. a brand name.
+    pub fn add_phrase(&self, phrase: &str) -> Result {
+        let mut hphrase = INVALID_HANDLE;
+        let phrase = CString::new(phrase)?;
+        hr!(grammar_phrase_create_from_text(
+            &mut hphrase,
+            phrase.as_ptr()
+        ))?;
+        let phrase = GrammarPhrase::new(hphrase);
+        hr!(phrase_list_grammar_add_phrase(self.handle, phrase.handle()))
+    }
+
+    pub fn clear(&self) -> Result {
+        hr!(phrase_list_grammar_clear(self.handle))
+    }
+}
+
 SmartHandle!(
     Model,
     SPXLUMODELHANDLE,
@@ -171,9 +270,17 @@ pub struct Recognizer {
     handle: SPXRECOHANDLE,
     flags: Flags,
     audio: Audio,
-    sink: Option<Arc<UnboundedSender<Event>>>,
+    sink: Option<Arc<EventSink>>,
     timeout: u32,
     continuous: bool,
+    closed: bool,
+    /// Audio is written to the push stream.
+    written: bool,
+    vad: Option<VoiceDetector>,
+    recorder: Option<Recorder>,
+    /// Audio is recorded by the pull stream when it is read.
+    stream_recorded: bool,
+    props: Arc<Properties>,
 }
 
 impl Recognizer {
@@ -183,27 +290,232 @@ impl Recognizer {
         audio: Audio,
         flags: Flags,
         timeout: u32,
-    ) -> Self {
-        Recognizer {
+    ) -> Result<Self> {
+        let mut hprops = INVALID_HANDLE;
+        hr!(recognizer_get_property_bag(handle, &mut hprops))?;
+        Ok(Recognizer {
             handle,
+            props: Arc::new(Properties::new(hprops)),
             flags,
             audio,
             timeout,
             sink: None,
             continuous: false,
+            closed: false,
+            written: false,
+            vad: None,
+            recorder: None,
+            stream_recorded: false,
+        })
+    }
+
+    /// Replace the authorization token before it expires.
+    pub fn set_authorization_token(&self, token: &str) -> Result {
+        self.props
+            .put_by_id(Property::AuthorizationToken.id(), token)
+    }
+
+    /// Language of the speech, applied from the next session.
+    pub fn set_language(&self, lang: &str) -> Result {
+        self.props.put_property_str(Property::RecoLanguage, lang)
+    }
+
+    /// Silence which ends a phrase. Settings are sent when connecting, so it
+    /// is applied after reconnecting or restarting the recognition.
+    pub fn set_segmentation_silence_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result {
+        self.props
+            .put_duration(Property::SegmentationSilenceTimeout, timeout)
+    }
+
+    pub fn segmentation_silence_timeout(&self) -> Result<Option<Duration>> {
+        self.props
+            .get_duration(Property::SegmentationSilenceTimeout)
+    }
+
+    /// Silence before the speech which ends the recognition, applied after
+    /// reconnecting or restarting the recognition.
+    pub fn set_initial_silence_timeout(&self, timeout: Duration) -> Result {
+        self.props
+            .put_duration(Property::InitialSilenceTimeout, timeout)
+    }
+
+    pub fn initial_silence_timeout(&self) -> Result<Option<Duration>> {
+        self.props.get_duration(Property::InitialSilenceTimeout)
+    }
+
+    /// Handling of profanity in the results, applied after reconnecting or
+    /// restarting the recognition.
+    pub fn set_profanity(&self, profanity: Profanity) -> Result {
+        self.props
+            .put_property_str(Property::ProfanityOption, profanity.as_str())
+    }
+
+    pub fn profanity(&self) -> Result<Option<Profanity>> {
+        match self.props.get_text(Property::ProfanityOption)? {
+            Some(v) => v.parse().map(Some),
+            None => Ok(None),
         }
     }
 
+    /// Request the timestamps of the words in detailed results, applied
+    /// after reconnecting or restarting the recognition.
+    pub fn set_word_level_timestamps(&self, enabled: bool) -> Result {
+        self.props
+            .put_bool(Property::RequestWordLevelTimestamps, enabled)
+    }
+
+    pub fn word_level_timestamps(&self) -> Result<Option<bool>> {
+        self.props.get_bool(Property::RequestWordLevelTimestamps)
+    }
+
+    /// Simple or detailed format of the results, applied after reconnecting
+    /// or restarting the recognition.
+    pub fn set_result_format(&self, format: ResultFormat) -> Result {
+        self.props
+            .put_property_str(Property::OutputFormatOption, format.as_str())
+    }
+
+    /// Raw messages received by the connection of the recognizer.
+    pub fn messages(&self) -> Result<MessageStream> {
+        Connection::from_recognizer(self)?.messages()
+    }
+
+    /// Record the audio sent and the results of the resulting stream. Audio
+    /// of pull stream is recorded when it is read by the SDK.
+    pub fn set_recorder(&mut self, recorder: Recorder) -> &mut Self {
+        self.stream_recorded =
+            self.audio.set_recorder(recorder.clone()).is_ok();
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Enable client side voice activity detection of push stream.
+    pub fn set_voice_detector(&mut self, vad: VoiceDetector) -> &mut Self {
+        self.vad = Some(vad);
+        self
+    }
+
     /// Proxy the write function of push stream.
+    /// If voice detection is enabled, only speech with its margins is sent.
     pub fn write_stream(&mut self, buffer: &mut [u8]) -> Result {
+        self.written |= !buffer.is_empty();
+        match self.detect_voice(buffer) {
+            Some(ref mut voiced) if voiced.is_empty() => Ok(()),
+            Some(ref mut voiced) => {
+                self.record_audio(voiced);
+                self.audio.write(voiced)
+            }
+            None => {
+                self.record_audio(buffer);
+                self.audio.write(buffer)
+            }
+        }
+    }
+
+    /// Pass the audio through voice detection and the recording tap without
+    /// sending it, return the audio `write_stream` would send.
+    pub(crate) fn filter_audio(&mut self, buffer: &[u8]) -> Vec<u8> {
+        let voiced =
+            self.detect_voice(buffer).unwrap_or_else(|| buffer.to_vec());
+        self.record_audio(&voiced);
+        voiced
+    }
+
+    /// Write to the push stream as is, bypassing voice detection and the
+    /// recording tap, e.g. to send audio filtered already once more.
+    pub(crate) fn write_raw(&mut self, buffer: &mut [u8]) -> Result {
+        self.written |= !buffer.is_empty();
         self.audio.write(buffer)
     }
 
+    /// Speech of the buffer with its margins, None if voice detection is
+    /// disabled.
+    fn detect_voice(&mut self, buffer: &[u8]) -> Option<Vec<u8>> {
+        let vad = self.vad.as_mut()?;
+        let mut voiced = Vec::with_capacity(buffer.len());
+        for act in vad.process(buffer, &mut voiced) {
+            self.post_activity(act);
+        }
+        Some(voiced)
+    }
+
+    /// Write to the recording tap unless the stream records itself.
+    fn record_audio(&self, buffer: &[u8]) {
+        match self.recorder {
+            Some(ref recorder) if !self.stream_recorded => {
+                if let Err(err) = recorder.write_audio(buffer) {
+                    log::error!("failed to record audio: {}", err);
+                }
+            }
+            _ => {}
+        }
+    }
+
     /// Close the push stream gracefully.
     pub fn close_stream(&mut self) -> Result {
+        let mut rest = self.finish_audio();
+        if !rest.is_empty() {
+            self.audio.write(&mut rest)?;
+        }
+        self.end_stream()
+    }
+
+    /// Flush voice detection at the end of input, return the audio to be
+    /// sent before closing the stream.
+    pub(crate) fn finish_audio(&mut self) -> Vec<u8> {
+        let mut rest = Vec::new();
+        let act = self.vad.as_mut().and_then(|vad| vad.finish(&mut rest));
+        if let Some(act) = act {
+            self.post_activity(act);
+        }
+        if !rest.is_empty() {
+            self.record_audio(&rest);
+        }
+        rest
+    }
+
+    /// Close the push stream without flushing voice detection.
+    pub(crate) fn end_stream(&mut self) -> Result {
+        self.closed = true;
         self.audio.close()
     }
 
+    /// Check if the push stream is closed, no more audio can be written.
+    pub fn is_stream_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Check if audio is written to the push stream which is not closed.
+    pub fn is_stream_pending(&self) -> bool {
+        self.written && !self.closed
+    }
+
+    /// Forget the input of the previous user: voice detection starts over
+    /// and the recorded files of the session are finalized.
+    pub(crate) fn reset_audio(&mut self) {
+        if let Some(ref mut vad) = self.vad {
+            vad.reset();
+        }
+        if let Some(ref recorder) = self.recorder {
+            if let Err(err) = recorder.finish() {
+                log::error!("failed to finish recording: {}", err);
+            }
+        }
+    }
+
+    /// Post local voice activity into event stream.
+    fn post_activity(&self, act: Activity) {
+        log::trace!("Local voice activity {:?} detected.", act);
+        if let Some(ref sink) = self.sink {
+            if let Err(err) = sink.post(Event::from_activity(act)) {
+                log::error!("failed to post {:?} activity: {}", act, err);
+            }
+        }
+    }
+
     /// Blocked mode for once recognition.
     pub fn recognize(&self) -> Result<EventResult> {
         let mut hres = INVALID_HANDLE;
@@ -246,14 +558,37 @@ impl Recognizer {
         Ok(())
     }
 
+    /// Start the recognition session, data of events are extracted in the
+    /// callback thread and handles are released immediately.
+    pub fn start_owned(&mut self) -> Result<OwnedEventStream> {
+        self.start_flags_owned(Flags::Session | Flags::Canceled)
+    }
+
+    /// Start recognition with customized flags, yield owned events.
+    pub fn start_flags_owned(
+        &mut self,
+        flags: Flags,
+    ) -> Result<OwnedEventStream> {
+        let stream = self.start_with(flags, true)?;
+        Ok(OwnedEventStream { stream })
+    }
+
     /// Start recognition with customized flags.
     /// Notice: If Flags::Cancled is not set, error message may not be handled; If Flags::Session is not set, stream future may not be resolved.
     pub fn start_flags(&mut self, flags: Flags) -> Result<EventStream> {
+        self.start_with(flags, false)
+    }
+
+    /// Register the callbacks and create the event stream.
+    fn start_with(&mut self, flags: Flags, eager: bool) -> Result<EventStream> {
         if self.started() {
             return Err(AlreadyExists);
         }
 
-        let flags = self.flags | flags;
+        let mut flags = self.flags | flags;
+        if self.vad.is_some() {
+            flags |= Flags::VoiceDetection;
+        }
         let mut h = INVALID_HANDLE;
         hr!(recognizer_start_continuous_recognition_async(
             self.handle,
@@ -266,10 +601,11 @@ impl Recognizer {
         ))?;
         self.continuous = true;
 
-        let (s, r) = unbounded::<Event>();
-        let sink = Arc::new(s);
+        let (sender, r) = unbounded::<Event>();
+        let sink = Arc::new(EventSink { sender, eager });
         self.sink = Some(sink.clone());
-        let reception = EventStream::new(r, flags);
+        let mut reception = EventStream::new(r, flags);
+        reception.recorder = self.recorder.clone();
 
         let sk = Box::new(Arc::downgrade(&sink));
         let context = Box::into_raw(sk) as *mut c_void;
@@ -358,6 +694,19 @@ impl Recognizer {
         Ok(reception)
     }
 
+    /// Add phrase hints, applied from the next recognition.
+    pub fn add_phrase_hints<S: AsRef<str>>(&self, phrases: &[S]) -> Result {
+        let grammar = PhraseListGrammar::from_recognizer(self)?;
+        for phrase in phrases {
+            grammar.add_phrase(phrase.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_phrase_hints(&self) -> Result {
+        PhraseListGrammar::from_recognizer(self)?.clear()
+    }
+
     /// Add generated intent trigger.
     pub fn add_intent(&self, id: &str, trigger: &IntentTrigger) -> Result {
         if id.is_empty() {
@@ -403,6 +752,14 @@ impl Drop for Recognizer {
     }
 }
 
+FlattenProps!(Recognizer);
+
+impl TokenTarget for Recognizer {
+    fn token_props(&self) -> Weak<Properties> {
+        Arc::downgrade(&self.props)
+    }
+}
+
 /// Enable threading operation.
 unsafe impl Send for Recognizer {}
 
@@ -411,6 +768,7 @@ pub struct EventStream {
     filter: Flags,
     source: UnboundedReceiver<Event>,
     stopped: bool,
+    recorder: Option<Recorder>,
 }
 
 impl EventStream {
@@ -420,6 +778,7 @@ impl EventStream {
             filter,
             source,
             stopped: false,
+            recorder: None,
         }
     }
 
@@ -430,15 +789,24 @@ impl EventStream {
     }
 
     /// Result streaming of event object.
+    /// Results are written to the recorder of the recognizer if present.
     pub fn resulting(
-        self,
+        mut self,
     ) -> impl Stream<Item = Recognition, Error = SpxError> {
-        self.then(|res| {
-            if let Ok(evt) = res {
-                evt.into_result()
-            } else {
-                Err(Other(String::from("streaming is interrupted")))
+        let recorder = self.recorder.take();
+        self.then(move |res| {
+            let reco = match res {
+                Ok(evt) => evt.into_result()?,
+                Err(_) => {
+                    return Err(Other(String::from("streaming is interrupted")))
+                }
+            };
+            if let Some(ref recorder) = recorder {
+                if let Err(err) = recorder.record(&reco) {
+                    log::error!("failed to record result: {}", err);
+                }
             }
+            Ok(reco)
         })
     }
 
@@ -459,6 +827,44 @@ impl EventStream {
     }
 }
 
+/// Stream of events extracted in the callback thread.
+pub struct OwnedEventStream {
+    stream: EventStream,
+}
+
+impl OwnedEventStream {
+    /// Define the new filter to pick out special events.
+    pub fn set_filter(self, flags: Flags) -> Self {
+        OwnedEventStream {
+            stream: self.stream.set_filter(flags),
+        }
+    }
+}
+
+impl Stream for OwnedEventStream {
+    type Item = OwnedEvent;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<OwnedEvent>, SpxError> {
+        let evt = match self.stream.poll() {
+            Ok(Async::Ready(Some(evt))) => evt,
+            Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(_) => {
+                return Err(Other(String::from("streaming is interrupted")))
+            }
+        };
+        let owned = evt.into_owned()?;
+        if let Some(ref recorder) = self.stream.recorder {
+            let reco = owned.clone().into_recognition();
+            if let Err(err) = reco.and_then(|r| recorder.record(&r)) {
+                log::error!("failed to record result: {}", err);
+            }
+        }
+        Ok(Async::Ready(Some(owned)))
+    }
+}
+
 /// The streaming implementation of futures.
 impl Stream for EventStream {
     type Item = Event;
@@ -512,6 +918,29 @@ unsafe extern "C" fn on_disconnected(
     fire_on_event(Flags::Disconnected, hevent, context);
 }
 
+/// Receiver of the callbacks.
+struct EventSink {
+    sender: UnboundedSender<Event>,
+    /// Extract data of the events in the callback thread.
+    eager: bool,
+}
+
+impl EventSink {
+    fn post(&self, evt: Event) -> Result {
+        let evt = if self.eager && evt.is_valid() {
+            let flag = evt.flag();
+            match evt.into_owned() {
+                Ok(owned) => Event::from_owned(owned),
+                // The consumer gets the error, e.g. to end on cancellation.
+                Err(err) => Event::from_failure(flag, err),
+            }
+        } else {
+            evt
+        };
+        Ok(self.sender.unbounded_send(evt)?)
+    }
+}
+
 fn fire_on_event(flag: Flags, hevent: SPXEVENTHANDLE, context: *mut c_void) {
     log::trace!("Recognition event {:?} fired.", flag);
     let evt = Event::new(flag, hevent);
@@ -520,17 +949,75 @@ fn fire_on_event(flag: Flags, hevent: SPXEVENTHANDLE, context: *mut c_void) {
         return;
     }
     log::trace!("Event is fired with {:?} and address: {:?}", flag, context);
-    let ctx =
-        unsafe { Box::from_raw(context as *mut Weak<UnboundedSender<Event>>) };
+    let ctx = unsafe { Box::from_raw(context as *mut Weak<EventSink>) };
     let weak_ptr = Weak::clone(&ctx);
     // forget the box, at least one box is leaked.
     Box::into_raw(ctx);
-    if let Some(mut arc) = weak_ptr.upgrade() {
-        let sender = Arc::make_mut(&mut arc);
-        if let Err(err) = sender.unbounded_send(evt) {
+    if let Some(sink) = weak_ptr.upgrade() {
+        if let Err(err) = sink.post(evt) {
             log::error!("failed to post {:?} event: {}", flag, err);
         }
     } else {
         log::error!("Recognizer instance is dropped!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    fn session(flag: Flags) -> Event {
+        Event::from_owned(OwnedEvent::Session {
+            flag,
+            session: String::from("s1"),
+        })
+    }
+
+    #[test]
+    fn extraction_errors_end_owned_streams() {
+        let (sender, receiver) = unbounded();
+        let stream = OwnedEventStream {
+            stream: EventStream::new(receiver, Flags::all()),
+        };
+        sender
+            .unbounded_send(session(Flags::SessionStarted))
+            .unwrap();
+        let failed = Other(String::from("failed to extract"));
+        sender
+            .unbounded_send(Event::from_failure(Flags::Canceled, failed))
+            .unwrap();
+        sender
+            .unbounded_send(session(Flags::SessionStopped))
+            .unwrap();
+
+        let mut events = stream.wait();
+        match events.next() {
+            Some(Ok(OwnedEvent::Session { flag, .. })) => {
+                assert_eq!(flag, Flags::SessionStarted)
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        match events.next() {
+            Some(Err(SpxError::Other(msg))) => {
+                assert_eq!(msg, "failed to extract")
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        // Canceled ends the stream.
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn extraction_errors_reach_results() {
+        let (sender, receiver) = unbounded();
+        let failed = Other(String::from("failed to extract"));
+        sender
+            .unbounded_send(Event::from_failure(Flags::Recognized, failed))
+            .unwrap();
+        drop(sender);
+        let mut results =
+            EventStream::new(receiver, Flags::all()).resulting().wait();
+        assert!(results.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Recording tap of the audio sent to the recognizer and the results back.
+//!
+//! Each session is written to a WAV file named by its session id, with a
+//! sidecar JSON lines file of every recognition. Offsets in the sidecar are
+//! relative to the recorded audio file which contains them, and the elapsed
+//! time since the session start is kept for replay.
+
+use super::{
+    audio::AudioSpec,
+    events::{Flags, Recognition},
+    format::{wav_header, WAV_HEADER_SIZE},
+};
+use crate::{
+    error::{Other, Poisoned},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Where and how to record sessions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecorderConfig {
+    /// Directory of the recorded files.
+    pub directory: PathBuf,
+    /// Start a new file when the audio exceeds the size in bytes.
+    pub max_bytes: Option<u64>,
+    /// Start a new file when the audio exceeds the duration.
+    pub max_duration: Option<Duration>,
+}
+
+/// Shared recording tap, clones write to the same files.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    /// Constructor with the format of recorded audio.
+    pub fn new(config: RecorderConfig, spec: AudioSpec) -> Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let recording = Recording {
+            config,
+            spec,
+            session: String::new(),
+            parts: Vec::new(),
+            written: 0,
+            started: None,
+            wav: None,
+            log: None,
+        };
+        Ok(Recorder {
+            inner: Arc::new(Mutex::new(recording)),
+        })
+    }
+
+    /// Append the audio data sent to the recognizer.
+    pub fn write_audio(&self, data: &[u8]) -> Result {
+        self.inner.lock().map_err(|_| Poisoned)?.write_audio(data)
+    }
+
+    /// Append the recognition to the sidecar of the session.
+    pub fn record(&self, reco: &Recognition) -> Result {
+        self.inner.lock().map_err(|_| Poisoned)?.record(reco)
+    }
+
+    /// Finalize the files of current session.
+    pub fn finish(&self) -> Result {
+        self.inner.lock().map_err(|_| Poisoned)?.finish()
+    }
+}
+
+/// Recorded data of current session.
+struct Recording {
+    config: RecorderConfig,
+    spec: AudioSpec,
+    session: String,
+    /// Audio offsets in bytes where the parts start.
+    parts: Vec<u64>,
+    /// Bytes of audio written in the session.
+    written: u64,
+    /// Time of the first data recorded in the session.
+    started: Option<Instant>,
+    wav: Option<WavFile>,
+    log: Option<BufWriter<File>>,
+}
+
+impl Recording {
+    /// File name of the part without extension.
+    fn part_name(&self, part: usize) -> String {
+        if part == 0 {
+            self.session.clone()
+        } else {
+            format!("{}-{}", self.session, part)
+        }
+    }
+
+    fn part_path(&self, part: usize, ext: &str) -> PathBuf {
+        self.config
+            .directory
+            .join(format!("{}.{}", self.part_name(part), ext))
+    }
+
+    /// Session may be unknown before the first event arrives.
+    fn ensure_session(&mut self) {
+        if self.session.is_empty() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            self.session = format!("pending-{}", now.as_millis());
+        }
+    }
+
+    fn open_part(&mut self) -> Result {
+        self.ensure_session();
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        let part = self.parts.len();
+        let wav = WavFile::create(self.part_path(part, "wav"), &self.spec)?;
+        let log = File::create(self.part_path(part, "jsonl"))?;
+        self.parts.push(self.written);
+        self.wav = Some(wav);
+        self.log = Some(BufWriter::new(log));
+        Ok(())
+    }
+
+    fn close_part(&mut self) -> Result {
+        if let Some(wav) = self.wav.take() {
+            wav.finalize()?;
+        }
+        if let Some(mut log) = self.log.take() {
+            log.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Check the rotation policy for the current part.
+    fn should_rotate(&self) -> bool {
+        let size = match self.parts.last() {
+            Some(start) => self.written - start,
+            None => return false,
+        };
+        if size == 0 {
+            return false;
+        }
+        let by_size = self.config.max_bytes.map_or(false, |max| size >= max);
+        let by_time = self
+            .config
+            .max_duration
+            .map_or(false, |max| self.spec.to_duration(size) >= max);
+        by_size || by_time
+    }
+
+    fn write_audio(&mut self, data: &[u8]) -> Result {
+        if self.should_rotate() {
+            self.close_part()?;
+        }
+        if self.wav.is_none() {
+            self.open_part()?;
+        }
+        if let Some(ref mut wav) = self.wav {
+            wav.write(data)?;
+        }
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Rename the files recorded before the session id is known.
+    fn rename_session(&mut self, session: &str) -> Result {
+        let olds: Vec<_> = (0..self.parts.len())
+            .map(|p| (self.part_path(p, "wav"), self.part_path(p, "jsonl")))
+            .collect();
+        self.session = session.to_string();
+        for (p, (wav, log)) in olds.into_iter().enumerate() {
+            fs::rename(wav, self.part_path(p, "wav"))?;
+            fs::rename(log, self.part_path(p, "jsonl"))?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, reco: &Recognition) -> Result {
+        if !reco.session.is_empty() && reco.session != self.session {
+            if self.session.starts_with("pending-") {
+                self.rename_session(&reco.session)?;
+            } else if !self.session.is_empty() {
+                self.finish()?;
+                self.session = reco.session.clone();
+            } else {
+                self.session = reco.session.clone();
+            }
+        }
+        if self.log.is_none() {
+            self.open_part()?;
+        }
+
+        let elapsed = self.started.map(|t| t.elapsed()).unwrap_or_default();
+        let mut line = json!({ "elapsed": elapsed, "result": reco });
+        if let Some(offset) = reco.offset {
+            let bytes = self.spec.to_bytes(offset);
+            let part = self
+                .parts
+                .iter()
+                .rposition(|start| *start <= bytes)
+                .unwrap_or(0);
+            let start = self.parts.get(part).cloned().unwrap_or(0);
+            line["audio"] = json!(format!("{}.wav", self.part_name(part)));
+            line["audio_offset"] =
+                json!(self.spec.to_duration(bytes.saturating_sub(start)));
+        }
+        if let Some(ref mut log) = self.log {
+            serde_json::to_writer(&mut *log, &line)?;
+            log.write_all(b"\n")?;
+            log.flush()?;
+        }
+
+        if reco.flag.intersects(Flags::SessionStopped) {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result {
+        self.close_part()?;
+        self.session.clear();
+        self.parts.clear();
+        self.written = 0;
+        self.started = None;
+        Ok(())
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Err(err) = self.close_part() {
+            log::error!("failed to finalize recording: {}", err);
+        }
+    }
+}
+
+/// PCM WAV file with sizes patched on finalize.
+struct WavFile {
+    file: BufWriter<File>,
+    length: u32,
+}
+
+impl WavFile {
+    fn create(path: PathBuf, spec: &AudioSpec) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&wav_header(spec, 0))?;
+        Ok(WavFile { file, length: 0 })
+    }
+
+    /// Sizes of WAV are 32 bits, rotate by `max_bytes` for longer sessions.
+    fn write(&mut self, data: &[u8]) -> Result {
+        let length = u64::from(self.length) + data.len() as u64;
+        if length > u64::from(u32::max_value() - WAV_HEADER_SIZE) {
+            return Err(Other(String::from(
+                "recorded WAV file exceeds 4 GiB, set max_bytes to rotate",
+            )));
+        }
+        self.file.write_all(data)?;
+        self.length = length as u32;
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result {
+        let riff_size = self.length + WAV_HEADER_SIZE - 8;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&riff_size.to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.length.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::{env, process};
+
+    const SPEC: AudioSpec = AudioSpec {
+        rate: 16_000,
+        bits: 16,
+        channels: 1,
+    };
+
+    fn directory(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "luis-recorder-{}-{}",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn recorder(dir: &PathBuf, max_bytes: Option<u64>) -> Recorder {
+        let config = RecorderConfig {
+            directory: dir.clone(),
+            max_bytes,
+            max_duration: None,
+        };
+        Recorder::new(config, SPEC).unwrap()
+    }
+
+    fn reco(flag: Flags, session: &str, offset: Option<u64>) -> Recognition {
+        Recognition {
+            flag,
+            session: session.to_string(),
+            offset: offset.map(Duration::from_millis),
+            ..Recognition::default()
+        }
+    }
+
+    /// Samples of 16 bits PCM.
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn read_samples(path: PathBuf) -> Vec<i16> {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16_000);
+        reader.samples().map(|s| s.unwrap()).collect()
+    }
+
+    fn read_log(path: PathBuf) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn names(dir: &PathBuf) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn finalizes_wav_sizes() {
+        let dir = directory("sizes");
+        let recorder = recorder(&dir, None);
+        recorder
+            .record(&reco(Flags::SessionStarted, "s1", None))
+            .unwrap();
+        recorder.write_audio(&pcm(&[1, 2, 3])).unwrap();
+        recorder.write_audio(&pcm(&[-4, 5])).unwrap();
+        recorder
+            .record(&reco(Flags::SessionStopped, "s1", None))
+            .unwrap();
+
+        let file = fs::read(dir.join("s1.wav")).unwrap();
+        assert_eq!(file.len(), 54);
+        assert_eq!(&file[4..8], &46u32.to_le_bytes());
+        assert_eq!(&file[40..44], &10u32.to_le_bytes());
+        assert_eq!(read_samples(dir.join("s1.wav")), vec![1, 2, 3, -4, 5]);
+        assert_eq!(read_log(dir.join("s1.jsonl")).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = directory("size");
+        let recorder = recorder(&dir, Some(8));
+        recorder
+            .record(&reco(Flags::SessionStarted, "s1", None))
+            .unwrap();
+        for samples in &[[1, 2, 3], [4, 5, 6], [7, 8, 9]] {
+            recorder.write_audio(&pcm(samples)).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        assert_eq!(
+            names(&dir),
+            vec!["s1-1.jsonl", "s1-1.wav", "s1.jsonl", "s1.wav"]
+        );
+        assert_eq!(read_samples(dir.join("s1.wav")), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(read_samples(dir.join("s1-1.wav")), vec![7, 8, 9]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn maps_offsets_to_parts() {
+        let dir = directory("duration");
+        let config = RecorderConfig {
+            directory: dir.clone(),
+            max_bytes: None,
+            max_duration: Some(Duration::from_millis(100)),
+        };
+        let recorder = Recorder::new(config, SPEC).unwrap();
+        recorder
+            .record(&reco(Flags::SessionStarted, "s1", None))
+            .unwrap();
+        // 100 ms of audio in each write, each one is a part.
+        let chunk = vec![0u8; 3200];
+        for _ in 0..3 {
+            recorder.write_audio(&chunk).unwrap();
+        }
+        for offset in &[50, 150, 250] {
+            let reco = reco(Flags::Recognized, "s1", Some(*offset));
+            recorder.record(&reco).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        for part in &["s1.wav", "s1-1.wav", "s1-2.wav"] {
+            assert_eq!(read_samples(dir.join(part)).len(), 1600);
+        }
+        // Events are logged in the part open when they arrive.
+        let lines = read_log(dir.join("s1-2.jsonl"));
+        let mapped: Vec<_> = lines
+            .iter()
+            .map(|line| {
+                let offset: Duration =
+                    serde_json::from_value(line["audio_offset"].clone())
+                        .unwrap();
+                (line["audio"].as_str().unwrap().to_string(), offset)
+            })
+            .collect();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            mapped,
+            vec![
+                (String::from("s1.wav"), ms(50)),
+                (String::from("s1-1.wav"), ms(50)),
+                (String::from("s1-2.wav"), ms(50)),
+            ]
+        );
+        assert_eq!(lines[0]["result"]["flag"], "Recognized");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renames_pending_session() {
+        let dir = directory("pending");
+        let recorder = recorder(&dir, Some(4));
+        // Audio arrives before the session id is known.
+        recorder.write_audio(&pcm(&[1, 2])).unwrap();
+        recorder.write_audio(&pcm(&[3])).unwrap();
+        let pending = names(&dir);
+        assert_eq!(pending.len(), 4);
+        assert!(pending.iter().all(|name| name.starts_with("pending-")));
+
+        recorder
+            .record(&reco(Flags::SessionStarted, "s1", None))
+            .unwrap();
+        recorder.write_audio(&pcm(&[4])).unwrap();
+        recorder
+            .record(&reco(Flags::SessionStopped, "s1", None))
+            .unwrap();
+        assert_eq!(
+            names(&dir),
+            vec!["s1-1.jsonl", "s1-1.wav", "s1.jsonl", "s1.wav"]
+        );
+        assert_eq!(read_samples(dir.join("s1.wav")), vec![1, 2]);
+        assert_eq!(read_samples(dir.join("s1-1.wav")), vec![3, 4]);
+        let flags: Vec<_> = read_log(dir.join("s1-1.jsonl"))
+            .iter()
+            .map(|line| line["result"]["flag"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(flags, vec!["SessionStarted", "SessionStopped"]);
+
+        // The next session starts over.
+        recorder
+            .record(&reco(Flags::SessionStarted, "s2", None))
+            .unwrap();
+        recorder.write_audio(&pcm(&[5])).unwrap();
+        drop(recorder);
+        assert_eq!(read_samples(dir.join("s2.wav")), vec![5]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
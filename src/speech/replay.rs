@@ -0,0 +1,352 @@
+//! Offline replay of recorded sessions through the event stream.
+//!
+//! The event log written by the recorder, or plain JSON lines of
+//! recognitions, is re-emitted as events without underlying handles, so the
+//! consumers of `EventStream` can be tested without the service. The
+//! recorded audio can be fed back to a recognizer to recognize it again.
+
+use super::{
+    audio::AudioSpec,
+    events::{Event, Flags, Recognition},
+    format::strip_riff_header,
+    recognizer::{EventStream, Recognizer},
+};
+use crate::{error::InvalidConfig, Result};
+use futures::sync::mpsc::unbounded;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Duration of the audio chunks fed to the recognizer.
+const FEED_CHUNK: Duration = Duration::from_millis(100);
+
+/// Timing of the replayed events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// Keep the original intervals between events.
+    Original,
+    /// Divide the original intervals by the speed factor.
+    Accelerated(f64),
+    /// Emit all events at once.
+    Immediate,
+}
+
+impl Pace {
+    /// Pace of the speed factor, which must be positive and finite.
+    pub fn accelerated(speed: f64) -> Result<Self> {
+        let pace = Pace::Accelerated(speed);
+        pace.validate()?;
+        Ok(pace)
+    }
+
+    /// Check the speed factor of accelerated pace.
+    pub fn validate(self) -> Result {
+        match self {
+            Pace::Accelerated(speed) if !(speed.is_finite() && speed > 0.0) => {
+                Err(InvalidConfig(
+                    String::from("replay pace"),
+                    format!("speed {} is not positive", speed),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// One recorded event with its timing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    /// Time since the session start when the event arrived.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<Duration>,
+    /// Recorded audio file which contains the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<String>,
+    /// Offset of the event in the recorded audio file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_offset: Option<Duration>,
+    pub result: Recognition,
+}
+
+impl ReplayEntry {
+    /// Time point to emit the event. Fall back to audio offset of the result
+    /// if the arrival time is not recorded.
+    fn time_point(&self) -> Option<Duration> {
+        self.elapsed.or_else(|| {
+            let r = &self.result;
+            r.offset
+                .map(|offset| offset + r.duration.unwrap_or_default())
+        })
+    }
+}
+
+impl From<Recognition> for ReplayEntry {
+    fn from(result: Recognition) -> Self {
+        ReplayEntry {
+            elapsed: None,
+            audio: None,
+            audio_offset: None,
+            result,
+        }
+    }
+}
+
+/// Recorded events of a session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionLog {
+    entries: Vec<ReplayEntry>,
+}
+
+impl SessionLog {
+    /// Load the event log file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        SessionLog::from_reader(BufReader::new(file))
+    }
+
+    /// Parse JSON lines of recorded entries or bare recognitions.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: Value = serde_json::from_str(&line)?;
+            let entry = if value.get("result").is_some() {
+                serde_json::from_value(value)?
+            } else {
+                ReplayEntry::from(serde_json::from_value::<Recognition>(value)?)
+            };
+            entries.push(entry);
+        }
+        Ok(SessionLog { entries })
+    }
+
+    /// Build from extracted entries.
+    pub fn from_entries(entries: Vec<ReplayEntry>) -> Self {
+        SessionLog { entries }
+    }
+
+    /// Recorded entries in order.
+    pub fn entries(&self) -> &[ReplayEntry] {
+        &self.entries
+    }
+
+    /// Recorded audio files of the entries in order, without duplicates.
+    pub fn audio_files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = Vec::new();
+        for file in self.entries.iter().filter_map(|e| e.audio.as_ref()) {
+            if !files.contains(&file.as_str()) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// Write the recorded audio files in the directory to the stream of the
+    /// recognizer with the pace, then close the stream. Events of the
+    /// recognizer are the results of the audio recognized again.
+    pub fn feed_audio<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        reco: &mut Recognizer,
+        pace: Pace,
+    ) -> Result {
+        pace.validate()?;
+        for file in self.audio_files() {
+            let (spec, samples) = read_wav(&directory.as_ref().join(file))?;
+            let size = (spec.to_bytes(FEED_CHUNK) as usize).max(1);
+            for chunk in samples.chunks(size) {
+                reco.write_stream(&mut chunk.to_vec())?;
+                if let Some(delay) =
+                    scale(spec.to_duration(chunk.len() as u64), pace)
+                {
+                    thread::sleep(delay);
+                }
+            }
+        }
+        reco.close_stream()
+    }
+
+    /// Replay the events in a background thread with the given pace.
+    /// The stream is ended after the last event.
+    pub fn replay(self, pace: Pace) -> Result<EventStream> {
+        pace.validate()?;
+        let (s, r) = unbounded::<Event>();
+        let stream = EventStream::new(r, Flags::all());
+        thread::spawn(move || {
+            let begin = Instant::now();
+            for entry in self.entries {
+                if let Some(delay) = entry.time_point().and_then(|t| {
+                    let due = scale(t, pace)?;
+                    due.checked_sub(begin.elapsed())
+                }) {
+                    thread::sleep(delay);
+                }
+                let evt = Event::from_recognition(entry.result);
+                if s.unbounded_send(evt).is_err() {
+                    log::debug!("Replay stream is dropped.");
+                    return;
+                }
+            }
+        });
+        Ok(stream)
+    }
+}
+
+/// Format and samples of the WAV file written by the recorder.
+fn read_wav(path: &Path) -> Result<(AudioSpec, Vec<u8>)> {
+    let data = fs::read(path)?;
+    if data.len() < 36 || &data[..4] != b"RIFF" || &data[12..16] != b"fmt " {
+        return Err(InvalidConfig(
+            path.display().to_string(),
+            String::from("not a recorded WAV file"),
+        ));
+    }
+    let le16 = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+    let rate = u32::from(le16(24)) | u32::from(le16(26)) << 16;
+    let spec = AudioSpec {
+        rate,
+        bits: le16(34) as u8,
+        channels: le16(22) as u8,
+    };
+    let samples = strip_riff_header(&data).to_vec();
+    Ok((spec, samples))
+}
+
+/// Time point of the event with the pace.
+fn scale(time: Duration, pace: Pace) -> Option<Duration> {
+    match pace {
+        Pace::Original => Some(time),
+        Pace::Accelerated(speed) if speed > 0.0 => {
+            let nanos = (time.as_secs() as f64 * 1e9
+                + f64::from(time.subsec_nanos()))
+                / speed;
+            Some(Duration::from_nanos(nanos as u64))
+        }
+        Pace::Accelerated(_) | Pace::Immediate => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech::format::wav_header;
+    use futures::{Future, Stream};
+    use std::env;
+
+    const LOG: &str = r#"
+{"elapsed":{"secs":0,"nanos":0},"result":{"flag":"SessionStarted","session":"s1"}}
+{"elapsed":{"secs":0,"nanos":30000000},"audio":"s1.wav","audio_offset":{"secs":0,"nanos":0},"result":{"flag":"Recognized","session":"s1","offset":{"secs":0,"nanos":0},"duration":{"secs":1,"nanos":0},"text":"hello"}}
+
+{"flag":"Recognized","session":"s1","offset":{"secs":1,"nanos":0},"duration":{"secs":1,"nanos":0},"text":"world"}
+{"elapsed":{"secs":0,"nanos":60000000},"result":{"flag":"SessionStopped","session":"s1"}}
+"#;
+
+    fn log() -> SessionLog {
+        SessionLog::from_reader(LOG.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_entries_and_bare_recognitions() {
+        let log = log();
+        let entries = log.entries();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].audio.as_ref().unwrap(), "s1.wav");
+        assert_eq!(entries[1].result.text.as_ref().unwrap(), "hello");
+        assert_eq!(entries[2].elapsed, None);
+        // Bare recognitions are timed by the end of their audio.
+        assert_eq!(entries[2].time_point(), Some(Duration::from_secs(2)));
+        assert_eq!(log.audio_files(), vec!["s1.wav"]);
+    }
+
+    #[test]
+    fn rejects_speed_not_positive() {
+        assert!(Pace::accelerated(0.0).is_err());
+        assert!(Pace::accelerated(-2.0).is_err());
+        assert!(Pace::accelerated(std::f64::NAN).is_err());
+        assert!(Pace::accelerated(std::f64::INFINITY).is_err());
+        assert_eq!(Pace::accelerated(4.0).unwrap(), Pace::Accelerated(4.0));
+        assert!(log().replay(Pace::Accelerated(0.0)).is_err());
+    }
+
+    #[test]
+    fn scales_time_points() {
+        let t = Duration::from_millis(400);
+        assert_eq!(scale(t, Pace::Original), Some(t));
+        assert_eq!(
+            scale(t, Pace::Accelerated(4.0)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(scale(t, Pace::Immediate), None);
+    }
+
+    #[test]
+    fn replays_events_in_order() {
+        let texts = log()
+            .replay(Pace::Immediate)
+            .unwrap()
+            .resulting()
+            .map(|r| (r.flag, r.text))
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(
+            texts,
+            vec![
+                (Flags::SessionStarted, None),
+                (Flags::Recognized, Some(String::from("hello"))),
+                (Flags::Recognized, Some(String::from("world"))),
+                (Flags::SessionStopped, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn replays_with_accelerated_timing() {
+        let start = Instant::now();
+        let count = log()
+            .replay(Pace::Accelerated(1000.0))
+            .unwrap()
+            .collect()
+            .wait()
+            .unwrap()
+            .len();
+        assert_eq!(count, 4);
+        // Bare recognition at 2 seconds is due after 2 ms.
+        assert!(start.elapsed() >= Duration::from_millis(2));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reads_recorded_wav() {
+        let spec = AudioSpec {
+            rate: 8000,
+            bits: 16,
+            channels: 1,
+        };
+        let samples: Vec<u8> = (0..160u8).collect();
+        let mut data = wav_header(&spec, samples.len() as u32);
+        data.extend_from_slice(&samples);
+        let path =
+            env::temp_dir().join(format!("replay-{}.wav", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let (read, body) = read_wav(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((read.rate, read.bits, read.channels), (8000, 16, 1));
+        assert_eq!(body, samples);
+
+        let bad =
+            env::temp_dir().join(format!("replay-{}.bin", std::process::id()));
+        fs::write(&bad, b"not a wav file at all, not a wav file").unwrap();
+        assert!(read_wav(&bad).is_err());
+        fs::remove_file(&bad).unwrap();
+    }
+}
@@ -0,0 +1,672 @@
+//! Declarative description of recognizer configuration.
+//!
+//! Settings are loaded from TOML, JSON or YAML files, overridden by the
+//! environment variables with `LUIS_` prefix and built into
+//! `RecognizerConfig`.
+
+use super::{
+    audio::AudioSpec, builder::ProxyConfig, builder::RecognizerConfig,
+    events::Flags, format::OutputFormat, recorder::RecorderConfig,
+    vad::VadConfig,
+};
+use crate::{
+    error::InvalidConfig,
+    properties::{is_language_tag, PropertyBag},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, env, fs, path::Path};
+
+/// Format of the settings text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingsFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl SettingsFormat {
+    /// Guess the format by file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match ext.as_str() {
+            "toml" => Ok(SettingsFormat::Toml),
+            "json" => Ok(SettingsFormat::Json),
+            "yaml" | "yml" => Ok(SettingsFormat::Yaml),
+            _ => Err(InvalidConfig(
+                path.as_ref().display().to_string(),
+                String::from("unknown format of the settings file"),
+            )),
+        }
+    }
+}
+
+/// Audio input or output.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    pub file_path: Option<String>,
+    pub spec: Option<AudioSpec>,
+    pub pull_mode: bool,
+}
+
+/// Intent recognition.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntentSettings {
+    pub model_id: Option<String>,
+    pub intents: Vec<String>,
+}
+
+/// Speech translation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranslationSettings {
+    pub target_languages: Vec<String>,
+    pub voice: Option<String>,
+    pub features: Option<String>,
+}
+
+/// Speech synthesis.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceSettings {
+    pub name: Option<String>,
+    pub language: Option<String>,
+    pub output_format: Option<OutputFormat>,
+}
+
+/// Serializable description of `RecognizerConfig`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub subscription_key: Option<String>,
+    pub authorization_token: Option<String>,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub endpoint_id: Option<String>,
+    pub language: Option<String>,
+    pub detailed_result: Option<bool>,
+    pub flags: Option<Flags>,
+    pub timeout: Option<u32>,
+    pub audio: AudioSettings,
+    pub intent: IntentSettings,
+    pub translation: TranslationSettings,
+    pub voice: VoiceSettings,
+    pub proxy: Option<ProxyConfig>,
+    pub vad: Option<VadConfig>,
+    pub recorder: Option<RecorderConfig>,
+    /// Arbitrary named properties of the configuration.
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// Load settings file and apply the environment overrides.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut settings = Settings::from_file(path)?;
+        settings.apply_env();
+        Ok(settings)
+    }
+
+    /// Load settings file, the format is guessed by extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let format = SettingsFormat::from_path(&path)?;
+        let text = fs::read_to_string(&path)?;
+        Settings::parse(&text, format).map_err(|err| match err {
+            InvalidConfig(key, msg) => InvalidConfig(
+                key,
+                format!("{} in {}", msg, path.as_ref().display()),
+            ),
+            err => err,
+        })
+    }
+
+    /// Parse the settings text. Syntax errors tell the line, and invalid
+    /// values tell the offending key, e.g. "audio.spec.rate".
+    pub fn parse(text: &str, format: SettingsFormat) -> Result<Self> {
+        let parsed = match format {
+            SettingsFormat::Toml => toml::from_str::<toml::Value>(text)
+                .map_err(|e| {
+                    let line = e.line_col().map(|(l, _)| l + 1).unwrap_or(0);
+                    (line, e.to_string())
+                })
+                .and_then(|v| {
+                    serde_json::to_value(v).map_err(|e| (0, e.to_string()))
+                }),
+            SettingsFormat::Json => serde_json::from_str(text)
+                .map_err(|e| (e.line(), e.to_string())),
+            SettingsFormat::Yaml => {
+                serde_yaml::from_str::<serde_yaml::Value>(text)
+                    .map_err(|e| {
+                        let line = e.location().map(|l| l.line()).unwrap_or(0);
+                        (line, e.to_string())
+                    })
+                    .and_then(|v| {
+                        serde_json::to_value(v).map_err(|e| (0, e.to_string()))
+                    })
+            }
+        };
+        let value = parsed.map_err(|(line, msg)| {
+            InvalidConfig(format!("line {}", line), msg)
+        })?;
+        serde_json::from_value(value.clone()).map_err(|e| {
+            let message = e.to_string();
+            let key = offending_key(&[], &value, &message)
+                .unwrap_or_else(|| String::from("settings"));
+            InvalidConfig(key, message)
+        })
+    }
+
+    /// Override the settings by environment variables.
+    pub fn apply_env(&mut self) {
+        self.apply_vars(env::vars());
+    }
+
+    /// Override the settings by variables with `LUIS_` prefix, e.g.
+    /// `LUIS_SUBSCRIPTION_KEY`. `LUIS_PROPERTY_<NAME>` sets the named
+    /// property.
+    pub fn apply_vars<I>(&mut self, vars: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let (mut region, mut endpoint) = (false, false);
+        let (mut key, mut token) = (false, false);
+        for (name, value) in vars {
+            if !name.starts_with("LUIS_") {
+                continue;
+            }
+            let var = &name["LUIS_".len()..];
+            if var.starts_with("PROPERTY_") {
+                let prop = &var["PROPERTY_".len()..];
+                self.properties.insert(prop.to_string(), value);
+                continue;
+            }
+            let v = Some(value.clone());
+            match var {
+                "SUBSCRIPTION_KEY" => {
+                    self.subscription_key = v;
+                    key = true;
+                }
+                "AUTHORIZATION_TOKEN" => {
+                    self.authorization_token = v;
+                    token = true;
+                }
+                "REGION" => {
+                    self.region = v;
+                    region = true;
+                }
+                "ENDPOINT" => {
+                    self.endpoint = v;
+                    endpoint = true;
+                }
+                "ENDPOINT_ID" => self.endpoint_id = v,
+                "LANGUAGE" => self.language = v,
+                "MODEL_ID" => self.intent.model_id = v,
+                "VOICE_NAME" => self.voice.name = v,
+                "VOICE_LANGUAGE" => self.voice.language = v,
+                "VOICE_OUTPUT_FORMAT" => match value.parse() {
+                    Ok(format) => self.voice.output_format = Some(format),
+                    Err(_) => log::warn!("invalid {}: {}", name, value),
+                },
+                "AUDIO_FILE_PATH" => self.audio.file_path = v,
+                "PROXY_HOST_NAME" | "PROXY_PORT" | "PROXY_USER_NAME"
+                | "PROXY_PASSWORD" => {
+                    let proxy = self.proxy.get_or_insert_with(Default::default);
+                    match var {
+                        "PROXY_HOST_NAME" => proxy.host_name = value,
+                        "PROXY_PORT" => {
+                            proxy.port = value.parse().unwrap_or_else(|_| {
+                                log::warn!("invalid {}: {}", name, value);
+                                0
+                            })
+                        }
+                        "PROXY_USER_NAME" => proxy.user_name = value,
+                        _ => proxy.password = value,
+                    }
+                }
+                _ => log::debug!("unknown environment variable {}", name),
+            }
+        }
+        // One of the alternatives in the environment replaces the other in
+        // the file, both of them in the environment are conflicts.
+        if region != endpoint {
+            if region {
+                self.endpoint = None;
+            } else {
+                self.region = None;
+            }
+        }
+        if key != token {
+            if key {
+                self.authorization_token = None;
+            } else {
+                self.subscription_key = None;
+            }
+        }
+    }
+
+    /// Check the settings, the error tells the offending key.
+    pub fn validate(&self) -> Result {
+        let has_key = self.subscription_key.is_some();
+        let has_token = self.authorization_token.is_some();
+        if has_key && has_token {
+            return invalid(
+                "authorization_token",
+                "conflicts with subscription_key",
+            );
+        }
+        if !has_key && !has_token {
+            return invalid(
+                "subscription_key",
+                "either subscription_key or authorization_token is required",
+            );
+        }
+        match (&self.region, &self.endpoint) {
+            (Some(_), Some(_)) => {
+                return invalid("endpoint", "conflicts with region")
+            }
+            (None, None) => {
+                return invalid(
+                    "region",
+                    "either region or endpoint is required",
+                )
+            }
+            (Some(region), None) if region.trim().is_empty() => {
+                return invalid("region", "must not be empty")
+            }
+            (None, Some(endpoint)) if !endpoint.contains("://") => {
+                return invalid("endpoint", "must be an absolute URL")
+            }
+            _ => {}
+        }
+
+        if let Some(ref lang) = self.language {
+            check_language("language", lang)?;
+        }
+        if let Some(ref lang) = self.voice.language {
+            check_language("voice.language", lang)?;
+        }
+        for (i, lang) in self.translation.target_languages.iter().enumerate() {
+            check_language(
+                &format!("translation.target_languages[{}]", i),
+                lang,
+            )?;
+        }
+        if self.translation.voice.is_some()
+            && self.translation.target_languages.is_empty()
+        {
+            return invalid(
+                "translation.voice",
+                "needs translation.target_languages",
+            );
+        }
+        if self.timeout == Some(0) {
+            return invalid("timeout", "must be greater than 0");
+        }
+
+        if let Some(spec) = self.audio.spec {
+            if spec.rate == 0 {
+                return invalid("audio.spec.rate", "must be greater than 0");
+            }
+            if ![8, 16, 32].contains(&spec.bits) {
+                return invalid("audio.spec.bits", "must be 8, 16 or 32");
+            }
+            if spec.channels == 0 {
+                return invalid(
+                    "audio.spec.channels",
+                    "must be greater than 0",
+                );
+            }
+        } else if self.audio.pull_mode {
+            return invalid("audio.pull_mode", "needs audio.spec");
+        }
+        if self.audio.file_path.is_some() && self.audio.spec.is_some() {
+            return invalid("audio.file_path", "conflicts with audio.spec");
+        }
+        if self.vad.is_some() && self.audio.spec.is_none() {
+            return invalid("vad", "needs audio.spec of push stream");
+        }
+
+        if let Some(ref proxy) = self.proxy {
+            if proxy.host_name.is_empty() {
+                return invalid("proxy.host_name", "must not be empty");
+            }
+            if proxy.port == 0 || proxy.port > 65535 {
+                return invalid("proxy.port", "must be in 1..65535");
+            }
+        }
+        for name in self.properties.keys() {
+            if name.trim().is_empty() {
+                return invalid(
+                    "properties",
+                    "property name must not be empty",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate and build the configuration.
+    pub fn build(&self) -> Result<RecognizerConfig> {
+        self.validate()?;
+        let key = self.subscription_key.as_ref().map(String::as_str);
+        let mut config = match (&self.endpoint, &self.region) {
+            (Some(endpoint), _) => {
+                RecognizerConfig::from_endpoint(endpoint, key.unwrap_or(""))?
+            }
+            (None, Some(region)) => match key {
+                Some(key) => RecognizerConfig::from_subscription(key, region)?,
+                None => RecognizerConfig::from_authorization_token(
+                    self.authorization_token.as_ref().unwrap(),
+                    region,
+                )?,
+            },
+            (None, None) => {
+                return Err(InvalidConfig(
+                    String::from("region"),
+                    String::from("either region or endpoint is required"),
+                ))
+            }
+        };
+        if let (Some(_), Some(token)) =
+            (&self.endpoint, &self.authorization_token)
+        {
+            config.put_authorization_token(token)?;
+        }
+
+        if let Some(flags) = self.flags {
+            config.set_flags(flags);
+        }
+        if let Some(timeout) = self.timeout {
+            config.set_timeout(timeout);
+        }
+        if let Some(ref v) = self.endpoint_id {
+            config.put_endpoint(v)?;
+        }
+        if let Some(ref v) = self.language {
+            config.put_language(v)?;
+        }
+        if let Some(v) = self.detailed_result {
+            config.put_detailed_result(v)?;
+        }
+
+        if let Some(ref path) = self.audio.file_path {
+            config.set_audio_file_path(path.as_str());
+        }
+        if let Some(spec) = self.audio.spec {
+            config.set_audio_spec(spec);
+        }
+        config.set_pull_mode(self.audio.pull_mode);
+        config.set_vad(self.vad);
+        config.set_recorder(self.recorder.clone());
+
+        if let Some(ref v) = self.intent.model_id {
+            config.set_model_id(v.as_str());
+        }
+        config.set_intents(self.intent.intents.clone());
+
+        config.set_target_languages(self.translation.target_languages.clone());
+        if let Some(ref v) = self.translation.voice {
+            config.put_trans_voice_name(v)?;
+        }
+        if let Some(ref v) = self.translation.features {
+            config.put_translation_features(v)?;
+        }
+
+        if let Some(ref v) = self.voice.name {
+            config.put_synth_voice_name(v)?;
+        }
+        if let Some(ref v) = self.voice.language {
+            config.put_synth_language(v)?;
+        }
+        if let Some(v) = self.voice.output_format {
+            config.put_synth_audio_format(v)?;
+        }
+
+        if let Some(ref proxy) = self.proxy {
+            config.put_proxy(proxy)?;
+        }
+        for (name, value) in &self.properties {
+            config.put_by_name(name, value)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Dotted path of the value failed with the error message, found by
+/// deserializing the values one by one in their sections. A missing field
+/// is named in the deepest section which reproduces the error.
+fn offending_key(
+    path: &[&str],
+    value: &Value,
+    message: &str,
+) -> Option<String> {
+    let missing = missing_field(message);
+    if let Some(map) = value.as_object() {
+        for (key, v) in map {
+            // Any value of the section lacking the field reproduces it.
+            if missing.is_some() && !v.is_object() {
+                continue;
+            }
+            let mut sub = path.to_vec();
+            sub.push(key);
+            let wrapped =
+                sub.iter().rev().fold(v.clone(), |v, k| json!({ *k: v }));
+            match serde_json::from_value::<Settings>(wrapped) {
+                Err(ref e) if e.to_string() == message => {
+                    return offending_key(&sub, v, message);
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut key = path.to_vec();
+    if let (Some(field), Some(map)) = (missing, value.as_object()) {
+        if !map.contains_key(field) {
+            key.push(field);
+        }
+    }
+    if key.is_empty() {
+        None
+    } else {
+        Some(key.join("."))
+    }
+}
+
+/// Name of the field in the message of serde, e.g. "missing field `bits`".
+fn missing_field(message: &str) -> Option<&str> {
+    if !message.starts_with("missing field `") {
+        return None;
+    }
+    message.split('`').nth(1)
+}
+
+fn invalid(key: &str, message: &str) -> Result {
+    Err(InvalidConfig(key.to_string(), message.to_string()))
+}
+
+fn check_language(key: &str, lang: &str) -> Result {
+    if is_language_tag(lang) {
+        Ok(())
+    } else {
+        invalid(key, &format!("invalid language tag {:?}", lang))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_of(text: &str, format: SettingsFormat) -> String {
+        match Settings::parse(text, format) {
+            Err(InvalidConfig(key, _)) => key,
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_all_formats() {
+        let toml = "region = \"westus\"\nsubscription_key = \"k\"\n[audio.spec]\nrate = 16000\nbits = 16\nchannels = 1\n";
+        let json = r#"{"region": "westus", "subscription_key": "k", "audio": {"spec": {"rate": 16000, "bits": 16, "channels": 1}}}"#;
+        let yaml = "region: westus\nsubscription_key: k\naudio:\n  spec:\n    rate: 16000\n    bits: 16\n    channels: 1\n";
+        for (text, format) in &[
+            (toml, SettingsFormat::Toml),
+            (json, SettingsFormat::Json),
+            (yaml, SettingsFormat::Yaml),
+        ] {
+            let settings = Settings::parse(text, *format).unwrap();
+            assert_eq!(settings.region.as_ref().unwrap(), "westus");
+            assert_eq!(settings.audio.spec.unwrap().rate, 16000);
+            settings.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_errors_name_the_key() {
+        use SettingsFormat::*;
+        assert_eq!(key_of("regin = \"westus\"", Toml), "regin");
+        assert_eq!(key_of("timeout = \"soon\"", Toml), "timeout");
+        assert_eq!(
+            key_of(
+                "[audio.spec]\nrate = \"fast\"\nbits = 16\nchannels = 1",
+                Toml
+            ),
+            "audio.spec.rate"
+        );
+        assert_eq!(key_of(r#"{"voice": {"nme": "x"}}"#, Json), "voice.nme");
+        assert_eq!(
+            key_of(r#"{"voice": {"output_format": "wav"}}"#, Json),
+            "voice.output_format"
+        );
+        assert_eq!(key_of("vad:\n  frame_ms: -1\n", Yaml), "vad.frame_ms");
+        assert_eq!(key_of("region = ", Toml), "line 1");
+    }
+
+    #[test]
+    fn missing_fields_name_the_key() {
+        use SettingsFormat::*;
+        assert_eq!(
+            key_of("[audio.spec]\nrate = 16000\nchannels = 1", Toml),
+            "audio.spec.bits"
+        );
+        assert_eq!(
+            key_of(
+                "region = \"westus\"\n[voice]\nname = \"v\"\n\
+                 [audio.spec]\nrate = 16000\nbits = 16\n",
+                Toml
+            ),
+            "audio.spec.channels"
+        );
+        assert_eq!(
+            key_of(
+                r#"{"proxy": {"host_name": "h", "user_name": "u", "password": "p"}}"#,
+                Json
+            ),
+            "proxy.port"
+        );
+        assert_eq!(key_of("audio:\n  spec: {}\n", Yaml), "audio.spec.rate");
+    }
+
+    #[test]
+    fn env_region_replaces_file_endpoint() {
+        let mut settings = Settings {
+            subscription_key: Some(String::from("k")),
+            endpoint: Some(String::from("wss://example.com/speech")),
+            ..Settings::default()
+        };
+        settings.apply_vars(vars(&[("LUIS_REGION", "westus")]));
+        assert_eq!(settings.endpoint, None);
+        assert_eq!(settings.region.as_ref().unwrap(), "westus");
+        settings.validate().unwrap();
+
+        settings.apply_vars(vars(&[("LUIS_ENDPOINT", "wss://example.com/s")]));
+        assert_eq!(settings.region, None);
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn env_token_replaces_file_key() {
+        let mut settings = Settings {
+            subscription_key: Some(String::from("k")),
+            region: Some(String::from("westus")),
+            ..Settings::default()
+        };
+        settings.apply_vars(vars(&[("LUIS_AUTHORIZATION_TOKEN", "t")]));
+        assert_eq!(settings.subscription_key, None);
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn both_alternatives_in_env_conflict() {
+        let mut settings = Settings {
+            subscription_key: Some(String::from("k")),
+            ..Settings::default()
+        };
+        settings.apply_vars(vars(&[
+            ("LUIS_REGION", "westus"),
+            ("LUIS_ENDPOINT", "wss://example.com/speech"),
+        ]));
+        match settings.validate() {
+            Err(InvalidConfig(key, _)) => assert_eq!(key, "endpoint"),
+            other => panic!("unexpected validation: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn env_sets_properties_and_proxy() {
+        let mut settings = Settings::default();
+        settings.apply_vars(vars(&[
+            ("LUIS_PROPERTY_SpeechServiceConnection_Key", "v"),
+            ("LUIS_PROXY_HOST_NAME", "proxy"),
+            ("LUIS_PROXY_PORT", "8080"),
+            ("OTHER", "ignored"),
+        ]));
+        assert_eq!(settings.properties["SpeechServiceConnection_Key"], "v");
+        let proxy = settings.proxy.unwrap();
+        assert_eq!((proxy.host_name.as_str(), proxy.port), ("proxy", 8080));
+    }
+
+    #[test]
+    fn validate_names_the_key() {
+        let base = Settings {
+            subscription_key: Some(String::from("k")),
+            region: Some(String::from("westus")),
+            ..Settings::default()
+        };
+        let key = |settings: Settings| match settings.validate() {
+            Err(InvalidConfig(key, _)) => key,
+            other => panic!("unexpected validation: {:?}", other),
+        };
+        assert_eq!(
+            key(Settings {
+                language: Some(String::from("en_US")),
+                ..base.clone()
+            }),
+            "language"
+        );
+        assert_eq!(
+            key(Settings {
+                timeout: Some(0),
+                ..base.clone()
+            }),
+            "timeout"
+        );
+        let mut translation = base.clone();
+        translation.translation.target_languages =
+            vec![String::from("de"), String::from("??")];
+        assert_eq!(key(translation), "translation.target_languages[1]");
+    }
+}
@@ -0,0 +1,925 @@
+//! Typed builder of SSML documents for the synthesizer.
+//!
+//! Text and attributes are escaped while rendering, and the document is
+//! checked against the subset of SSML supported by the service.
+
+use super::{lexicon::Lexicon, normalize::Normalizer};
+use crate::{error::InvalidSsml, properties::is_language_tag, Result};
+use std::{borrow::Cow, fmt, time::Duration};
+
+const SSML_NAMESPACE: &str = "http://www.w3.org/2001/10/synthesis";
+
+/// Longest break supported by the service.
+pub const MAX_BREAK: Duration = Duration::from_millis(5000);
+
+const RATES: &[&str] =
+    &["x-slow", "slow", "medium", "fast", "x-fast", "default"];
+const PITCHES: &[&str] =
+    &["x-low", "low", "medium", "high", "x-high", "default"];
+const VOLUMES: &[&str] = &[
+    "silent", "x-soft", "soft", "medium", "loud", "x-loud", "default",
+];
+const INTERPRET_AS: &[&str] = &[
+    "address",
+    "cardinal",
+    "characters",
+    "currency",
+    "date",
+    "digits",
+    "duration",
+    "fraction",
+    "name",
+    "number",
+    "ordinal",
+    "spell-out",
+    "telephone",
+    "time",
+];
+
+/// Escape the text for XML content and attribute values.
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(|c| "&<>\"'".contains(c)) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Strength of a break.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakStrength {
+    None,
+    ExtraWeak,
+    Weak,
+    Medium,
+    Strong,
+    ExtraStrong,
+}
+
+impl BreakStrength {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakStrength::None => "none",
+            BreakStrength::ExtraWeak => "x-weak",
+            BreakStrength::Weak => "weak",
+            BreakStrength::Medium => "medium",
+            BreakStrength::Strong => "strong",
+            BreakStrength::ExtraStrong => "x-strong",
+        }
+    }
+}
+
+/// Level of emphasis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmphasisLevel {
+    Strong,
+    Moderate,
+    None,
+    Reduced,
+}
+
+impl EmphasisLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmphasisLevel::Strong => "strong",
+            EmphasisLevel::Moderate => "moderate",
+            EmphasisLevel::None => "none",
+            EmphasisLevel::Reduced => "reduced",
+        }
+    }
+}
+
+/// Phonetic alphabet of pronunciations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhoneticAlphabet {
+    Ipa,
+    Sapi,
+    Ups,
+}
+
+impl PhoneticAlphabet {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PhoneticAlphabet::Ipa => "ipa",
+            PhoneticAlphabet::Sapi => "sapi",
+            PhoneticAlphabet::Ups => "ups",
+        }
+    }
+}
+
+/// Rate, pitch and volume of the speech, unset ones keep the defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prosody {
+    pub rate: Option<String>,
+    pub pitch: Option<String>,
+    pub volume: Option<String>,
+}
+
+impl Prosody {
+    pub fn new() -> Self {
+        Prosody::default()
+    }
+
+    /// Named rate, e.g. "fast", relative percentage like "+10%" or
+    /// multiplier like "1.5".
+    pub fn rate(mut self, rate: &str) -> Self {
+        self.rate = Some(rate.to_string());
+        self
+    }
+
+    /// Named pitch, e.g. "high", or value in "Hz", "st" and "%".
+    pub fn pitch(mut self, pitch: &str) -> Self {
+        self.pitch = Some(pitch.to_string());
+        self
+    }
+
+    /// Named volume, e.g. "loud", number in 0 to 100 or relative change.
+    pub fn volume(mut self, volume: &str) -> Self {
+        self.volume = Some(volume.to_string());
+        self
+    }
+
+    fn validate(&self) -> Result {
+        if self.rate.is_none() && self.pitch.is_none() && self.volume.is_none()
+        {
+            return Err(invalid("prosody", "no attribute is set"));
+        }
+        if let Some(ref rate) = self.rate {
+            if !RATES.contains(&rate.as_str()) && !is_measure(rate, &["%", ""])
+            {
+                return Err(invalid("prosody rate", rate));
+            }
+        }
+        if let Some(ref pitch) = self.pitch {
+            if !PITCHES.contains(&pitch.as_str())
+                && !is_measure(pitch, &["Hz", "st", "%"])
+            {
+                return Err(invalid("prosody pitch", pitch));
+            }
+        }
+        if let Some(ref volume) = self.volume {
+            let absolute = volume
+                .parse::<f64>()
+                .map_or(false, |v| v >= 0.0 && v <= 100.0);
+            let relative = (volume.starts_with('+') || volume.starts_with('-'))
+                && is_measure(volume, &["%", ""]);
+            if !VOLUMES.contains(&volume.as_str()) && !absolute && !relative {
+                return Err(invalid("prosody volume", volume));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number with optional sign and one of the units.
+fn is_measure(value: &str, units: &[&str]) -> bool {
+    let value = if value.starts_with('+') || value.starts_with('-') {
+        &value[1..]
+    } else {
+        value
+    };
+    units.iter().any(|unit| {
+        value.ends_with(unit)
+            && value[..value.len() - unit.len()]
+                .parse::<f64>()
+                .map_or(false, |v| v.is_finite() && v >= 0.0)
+    })
+}
+
+fn invalid(element: &str, msg: &str) -> crate::SpxError {
+    InvalidSsml(element.to_string(), msg.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    BreakTime(Duration),
+    BreakStrength(BreakStrength),
+    Bookmark(String),
+    SayAs {
+        interpret_as: String,
+        format: Option<String>,
+        text: String,
+    },
+    Phoneme {
+        alphabet: PhoneticAlphabet,
+        ph: String,
+        text: String,
+    },
+    Sub {
+        alias: String,
+        text: String,
+    },
+    Lexicon(String),
+    Audio {
+        src: String,
+        fallback: Content,
+    },
+    Prosody(Prosody, Content),
+    Emphasis(EmphasisLevel, Content),
+    Paragraph(Content),
+    Sentence(Content),
+}
+
+/// Content of voices and container elements.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Content {
+    nodes: Vec<Node>,
+}
+
+impl Content {
+    fn build<F: FnOnce(&mut Content)>(f: F) -> Content {
+        let mut content = Content::default();
+        f(&mut content);
+        content
+    }
+
+    /// Plain text, escaped while rendering.
+    pub fn text(&mut self, text: &str) -> &mut Self {
+        self.nodes.push(Node::Text(text.to_string()));
+        self
+    }
+
+    /// Pause of the time.
+    pub fn pause(&mut self, time: Duration) -> &mut Self {
+        self.nodes.push(Node::BreakTime(time));
+        self
+    }
+
+    /// Pause of the strength.
+    pub fn pause_strength(&mut self, strength: BreakStrength) -> &mut Self {
+        self.nodes.push(Node::BreakStrength(strength));
+        self
+    }
+
+    /// Bookmark reported when the synthesis reaches it.
+    pub fn bookmark(&mut self, mark: &str) -> &mut Self {
+        self.nodes.push(Node::Bookmark(mark.to_string()));
+        self
+    }
+
+    /// Text read as the type, e.g. "date" with format "mdy".
+    pub fn say_as(
+        &mut self,
+        interpret_as: &str,
+        format: Option<&str>,
+        text: &str,
+    ) -> &mut Self {
+        self.nodes.push(Node::SayAs {
+            interpret_as: interpret_as.to_string(),
+            format: format.map(str::to_string),
+            text: text.to_string(),
+        });
+        self
+    }
+
+    /// Text read with the pronunciation.
+    pub fn phoneme(
+        &mut self,
+        alphabet: PhoneticAlphabet,
+        ph: &str,
+        text: &str,
+    ) -> &mut Self {
+        self.nodes.push(Node::Phoneme {
+            alphabet,
+            ph: ph.to_string(),
+            text: text.to_string(),
+        });
+        self
+    }
+
+    /// Text read as the alias, e.g. "World Wide Web Consortium" of "W3C".
+    pub fn sub(&mut self, alias: &str, text: &str) -> &mut Self {
+        self.nodes.push(Node::Sub {
+            alias: alias.to_string(),
+            text: text.to_string(),
+        });
+        self
+    }
+
+    /// Reference to a PLS lexicon hosted at the URL, it applies to the
+    /// rest of the voice. The service only accepts it directly in the
+    /// voice, not in other elements.
+    pub fn lexicon(&mut self, uri: &str) -> &mut Self {
+        self.nodes.push(Node::Lexicon(uri.to_string()));
+        self
+    }
+
+    /// Plain text with the words of the lexicon read as its phonemes or
+    /// aliases, without hosting the lexicon.
+    pub fn pronounce(&mut self, text: &str, lexicon: &Lexicon) -> &mut Self {
+        lexicon.apply(text, self);
+        self
+    }
+
+    /// Plain text with numbers, dates, abbreviations and the like read by
+    /// the rules of the normalizer.
+    pub fn normalize(
+        &mut self,
+        text: &str,
+        normalizer: &Normalizer,
+    ) -> &mut Self {
+        normalizer.normalize(text).render(self);
+        self
+    }
+
+    /// Prerecorded audio of the URL, with the fallback content read if it
+    /// is unavailable.
+    pub fn audio<F>(&mut self, src: &str, fallback: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.nodes.push(Node::Audio {
+            src: src.to_string(),
+            fallback: Content::build(fallback),
+        });
+        self
+    }
+
+    pub fn prosody<F>(&mut self, prosody: Prosody, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.nodes.push(Node::Prosody(prosody, Content::build(f)));
+        self
+    }
+
+    pub fn emphasis<F>(&mut self, level: EmphasisLevel, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.nodes.push(Node::Emphasis(level, Content::build(f)));
+        self
+    }
+
+    pub fn paragraph<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.nodes.push(Node::Paragraph(Content::build(f)));
+        self
+    }
+
+    pub fn sentence<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.nodes.push(Node::Sentence(Content::build(f)));
+        self
+    }
+
+    /// Check the elements, `nested` forbids lexicons, `in_para` forbids
+    /// nested paragraphs and `in_sentence` forbids paragraphs and
+    /// sentences.
+    fn validate(
+        &self,
+        nested: bool,
+        in_para: bool,
+        in_sentence: bool,
+    ) -> Result {
+        for node in &self.nodes {
+            match node {
+                Node::Text(_) | Node::BreakStrength(_) => {}
+                Node::BreakTime(time) => {
+                    if *time > MAX_BREAK {
+                        return Err(invalid(
+                            "break",
+                            &format!(
+                                "{:?} is longer than {:?}",
+                                time, MAX_BREAK
+                            ),
+                        ));
+                    }
+                }
+                Node::Bookmark(mark) => {
+                    if mark.trim().is_empty() {
+                        return Err(invalid("bookmark", "mark is empty"));
+                    }
+                }
+                Node::SayAs { interpret_as, .. } => {
+                    if !INTERPRET_AS.contains(&interpret_as.as_str()) {
+                        return Err(invalid(
+                            "say-as interpret-as",
+                            interpret_as,
+                        ));
+                    }
+                }
+                Node::Phoneme { ph, .. } => {
+                    if ph.trim().is_empty() {
+                        return Err(invalid("phoneme", "ph is empty"));
+                    }
+                }
+                Node::Sub { alias, .. } => {
+                    if alias.trim().is_empty() {
+                        return Err(invalid("sub", "alias is empty"));
+                    }
+                }
+                Node::Lexicon(uri) => {
+                    if nested {
+                        return Err(invalid(
+                            "lexicon",
+                            "lexicon must be a child of voice",
+                        ));
+                    }
+                    if !uri.starts_with("https://") {
+                        return Err(invalid(
+                            "lexicon uri",
+                            "HTTPS URL is required",
+                        ));
+                    }
+                }
+                Node::Audio { src, fallback } => {
+                    if !src.starts_with("https://") {
+                        return Err(invalid(
+                            "audio src",
+                            "HTTPS URL is required",
+                        ));
+                    }
+                    fallback.validate(true, in_para, in_sentence)?;
+                }
+                Node::Prosody(prosody, content) => {
+                    prosody.validate()?;
+                    content.validate(true, in_para, in_sentence)?;
+                }
+                Node::Emphasis(_, content) => {
+                    content.validate(true, in_para, in_sentence)?;
+                }
+                Node::Paragraph(content) => {
+                    if in_para || in_sentence {
+                        return Err(invalid("p", "paragraph can't be nested"));
+                    }
+                    content.validate(true, true, false)?;
+                }
+                Node::Sentence(content) => {
+                    if in_sentence {
+                        return Err(invalid("s", "sentence can't be nested"));
+                    }
+                    content.validate(true, in_para, true)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&self, out: &mut String) {
+        for node in &self.nodes {
+            match node {
+                Node::Text(text) => out.push_str(&escape(text)),
+                Node::BreakTime(time) => {
+                    out.push_str(&format!(
+                        "<break time=\"{}ms\"/>",
+                        time.as_millis()
+                    ));
+                }
+                Node::BreakStrength(strength) => {
+                    out.push_str(&format!(
+                        "<break strength=\"{}\"/>",
+                        strength.as_str()
+                    ));
+                }
+                Node::Bookmark(mark) => {
+                    out.push_str(&format!(
+                        "<bookmark mark=\"{}\"/>",
+                        escape(mark)
+                    ));
+                }
+                Node::SayAs {
+                    interpret_as,
+                    format,
+                    text,
+                } => {
+                    out.push_str(&format!(
+                        "<say-as interpret-as=\"{}\"",
+                        escape(interpret_as)
+                    ));
+                    if let Some(format) = format {
+                        out.push_str(&format!(
+                            " format=\"{}\"",
+                            escape(format)
+                        ));
+                    }
+                    out.push_str(&format!(">{}</say-as>", escape(text)));
+                }
+                Node::Phoneme { alphabet, ph, text } => {
+                    out.push_str(&format!(
+                        "<phoneme alphabet=\"{}\" ph=\"{}\">{}</phoneme>",
+                        alphabet.as_str(),
+                        escape(ph),
+                        escape(text)
+                    ));
+                }
+                Node::Sub { alias, text } => {
+                    out.push_str(&format!(
+                        "<sub alias=\"{}\">{}</sub>",
+                        escape(alias),
+                        escape(text)
+                    ));
+                }
+                Node::Lexicon(uri) => {
+                    out.push_str(&format!(
+                        "<lexicon uri=\"{}\"/>",
+                        escape(uri)
+                    ));
+                }
+                Node::Audio { src, fallback } => {
+                    out.push_str(&format!("<audio src=\"{}\">", escape(src)));
+                    fallback.render(out);
+                    out.push_str("</audio>");
+                }
+                Node::Prosody(prosody, content) => {
+                    out.push_str("<prosody");
+                    let attrs = [
+                        ("rate", &prosody.rate),
+                        ("pitch", &prosody.pitch),
+                        ("volume", &prosody.volume),
+                    ];
+                    for (name, value) in attrs.iter() {
+                        if let Some(value) = value {
+                            out.push_str(&format!(
+                                " {}=\"{}\"",
+                                name,
+                                escape(value)
+                            ));
+                        }
+                    }
+                    out.push('>');
+                    content.render(out);
+                    out.push_str("</prosody>");
+                }
+                Node::Emphasis(level, content) => {
+                    out.push_str(&format!(
+                        "<emphasis level=\"{}\">",
+                        level.as_str()
+                    ));
+                    content.render(out);
+                    out.push_str("</emphasis>");
+                }
+                Node::Paragraph(content) => {
+                    out.push_str("<p>");
+                    content.render(out);
+                    out.push_str("</p>");
+                }
+                Node::Sentence(content) => {
+                    out.push_str("<s>");
+                    content.render(out);
+                    out.push_str("</s>");
+                }
+            }
+        }
+    }
+}
+
+/// Voice speaking its content.
+#[derive(Debug, Clone, PartialEq)]
+struct Voice {
+    name: String,
+    content: Content,
+}
+
+/// SSML document of one or more voices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ssml {
+    language: String,
+    voices: Vec<Voice>,
+}
+
+impl Ssml {
+    /// Document of the language, e.g. "en-US".
+    pub fn new(language: &str) -> Self {
+        Ssml {
+            language: language.to_string(),
+            voices: Vec::new(),
+        }
+    }
+
+    /// Document of plain text spoken by the voice.
+    pub fn from_text(language: &str, voice: &str, text: &str) -> Self {
+        let mut ssml = Ssml::new(language);
+        ssml.voice(voice, |c| {
+            c.text(text);
+        });
+        ssml
+    }
+
+    /// Append content spoken by the voice, e.g. "en-US-JennyNeural".
+    pub fn voice<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Content),
+    {
+        self.voices.push(Voice {
+            name: name.to_string(),
+            content: Content::build(f),
+        });
+        self
+    }
+
+    /// Check the document against the supported subset of SSML.
+    pub fn validate(&self) -> Result {
+        if !is_language_tag(&self.language) {
+            return Err(invalid("speak xml:lang", &self.language));
+        }
+        if self.voices.is_empty() {
+            return Err(invalid("speak", "at least one voice is required"));
+        }
+        for voice in &self.voices {
+            if voice.name.trim().is_empty() {
+                return Err(invalid("voice", "name is empty"));
+            }
+            voice.content.validate(false, false, false)?;
+        }
+        Ok(())
+    }
+
+    /// Validated XML of the document.
+    pub fn to_xml(&self) -> Result<String> {
+        self.validate()?;
+        Ok(self.to_string())
+    }
+}
+
+/// XML of the document without validation.
+impl fmt::Display for Ssml {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = format!(
+            "<speak version=\"1.0\" xmlns=\"{}\" xml:lang=\"{}\">",
+            SSML_NAMESPACE,
+            escape(&self.language)
+        );
+        for voice in &self.voices {
+            out.push_str(&format!("<voice name=\"{}\">", escape(&voice.name)));
+            voice.content.render(&mut out);
+            out.push_str("</voice>");
+        }
+        out.push_str("</speak>");
+        f.write_str(&out)
+    }
+}
+
+/// SSML input of the synthesizer, raw strings are passed through.
+pub trait AsSsml {
+    fn as_ssml(&self) -> Result<Cow<'_, str>>;
+}
+
+impl AsSsml for str {
+    fn as_ssml(&self) -> Result<Cow<'_, str>> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+impl AsSsml for String {
+    fn as_ssml(&self) -> Result<Cow<'_, str>> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+impl AsSsml for Ssml {
+    fn as_ssml(&self) -> Result<Cow<'_, str>> {
+        self.to_xml().map(Cow::Owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEAK: &str = "<speak version=\"1.0\" \
+                         xmlns=\"http://www.w3.org/2001/10/synthesis\" \
+                         xml:lang=\"en-US\">";
+
+    fn rejected(ssml: &Ssml) -> String {
+        match ssml.validate() {
+            Err(InvalidSsml(element, _)) => element,
+            other => panic!("unexpected validation: {:?}", other),
+        }
+    }
+
+    fn voice<F: FnOnce(&mut Content)>(f: F) -> Ssml {
+        let mut ssml = Ssml::new("en-US");
+        ssml.voice("en-US-JennyNeural", f);
+        ssml
+    }
+
+    #[test]
+    fn renders_plain_text() {
+        let ssml = Ssml::from_text("en-US", "en-US-JennyNeural", "Hello");
+        assert_eq!(
+            ssml.to_xml().unwrap(),
+            format!(
+                "{}<voice name=\"en-US-JennyNeural\">Hello</voice></speak>",
+                SPEAK
+            )
+        );
+    }
+
+    #[test]
+    fn renders_all_elements() {
+        let mut ssml = voice(|c| {
+            c.paragraph(|c| {
+                c.sentence(|c| {
+                    c.text("Wait").pause(Duration::from_millis(250));
+                })
+                .sentence(|c| {
+                    c.emphasis(EmphasisLevel::Strong, |c| {
+                        c.text("now");
+                    })
+                    .pause_strength(BreakStrength::ExtraWeak);
+                });
+            })
+            .prosody(Prosody::new().rate("+10%").volume("loud"), |c| {
+                c.say_as("date", Some("mdy"), "10/18/2026").bookmark("date");
+            })
+            .phoneme(PhoneticAlphabet::Ipa, "təˈmeɪtoʊ", "tomato")
+            .sub("World Wide Web Consortium", "W3C")
+            .audio("https://example.com/chime.wav", |c| {
+                c.text("chime");
+            });
+        });
+        ssml.voice("en-US-GuyNeural", |c| {
+            c.text("Bye");
+        });
+        let expected = format!(
+            "{}<voice name=\"en-US-JennyNeural\"><p><s>Wait\
+             <break time=\"250ms\"/></s><s><emphasis level=\"strong\">now\
+             </emphasis><break strength=\"x-weak\"/></s></p>\
+             <prosody rate=\"+10%\" volume=\"loud\"><say-as \
+             interpret-as=\"date\" format=\"mdy\">10/18/2026</say-as>\
+             <bookmark mark=\"date\"/></prosody><phoneme alphabet=\"ipa\" \
+             ph=\"təˈmeɪtoʊ\">tomato</phoneme><sub alias=\"World Wide Web \
+             Consortium\">W3C</sub><audio src=\"https://example.com/chime.wav\">\
+             chime</audio></voice><voice name=\"en-US-GuyNeural\">Bye</voice>\
+             </speak>",
+            SPEAK
+        );
+        assert_eq!(ssml.to_xml().unwrap(), expected);
+    }
+
+    #[test]
+    fn escapes_text_and_attributes() {
+        assert_eq!(escape("plain"), Cow::Borrowed("plain"));
+        assert_eq!(
+            escape("<a & \"b\" 'c'>"),
+            "&lt;a &amp; &quot;b&quot; &apos;c&apos;&gt;"
+        );
+        let ssml = voice(|c| {
+            c.text("Tom & Jerry <3")
+                .sub("\"quoted\" & <tagged>", "Q&A")
+                .bookmark("a<b");
+        });
+        assert_eq!(
+            ssml.to_xml().unwrap(),
+            format!(
+                "{}<voice name=\"en-US-JennyNeural\">Tom &amp; Jerry &lt;3\
+                 <sub alias=\"&quot;quoted&quot; &amp; &lt;tagged&gt;\">\
+                 Q&amp;A</sub><bookmark mark=\"a&lt;b\"/></voice></speak>",
+                SPEAK
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        assert_eq!(
+            rejected(&Ssml::from_text("en_US", "en-US-JennyNeural", "Hi")),
+            "speak xml:lang"
+        );
+        assert_eq!(
+            rejected(&Ssml::from_text("", "voice", "Hi")),
+            "speak xml:lang"
+        );
+        assert_eq!(rejected(&Ssml::new("en-US")), "speak");
+        assert_eq!(rejected(&Ssml::from_text("en-US", " ", "Hi")), "voice");
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.phoneme(PhoneticAlphabet::Sapi, " ", "tomato");
+            })),
+            "phoneme"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.audio("http://example.com/chime.wav", |_| {});
+            })),
+            "audio src"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.audio("https://example.com/chime.wav", |c| {
+                    c.pause(Duration::from_secs(6));
+                });
+            })),
+            "break"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.sub("", "W3C");
+            })),
+            "sub"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.say_as("colour", None, "red");
+            })),
+            "say-as interpret-as"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.sentence(|c| {
+                    c.paragraph(|_| {});
+                });
+            })),
+            "p"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.prosody(Prosody::new().pitch("loud"), |_| {});
+            })),
+            "prosody pitch"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.prosody(Prosody::new(), |_| {});
+            })),
+            "prosody"
+        );
+        assert_eq!(
+            rejected(&voice(|c| {
+                c.lexicon("http://example.com/lexicon.xml");
+            })),
+            "lexicon uri"
+        );
+        assert!(Ssml::from_text("en-US", "v", "Hi").to_xml().is_ok());
+    }
+
+    #[test]
+    fn lexicons_are_children_of_voices() {
+        const URI: &str = "https://example.com/lexicon.xml";
+        let ssml = voice(|c| {
+            c.lexicon(URI).text("Contoso");
+        });
+        assert_eq!(
+            ssml.to_xml().unwrap(),
+            format!(
+                "{}<voice name=\"en-US-JennyNeural\"><lexicon uri=\"{}\"/>\
+                 Contoso</voice></speak>",
+                SPEAK, URI
+            )
+        );
+        let nested: Vec<Box<dyn Fn(&mut Content)>> = vec![
+            Box::new(|c| {
+                c.prosody(Prosody::new().rate("slow"), |c| {
+                    c.lexicon(URI);
+                });
+            }),
+            Box::new(|c| {
+                c.emphasis(EmphasisLevel::Moderate, |c| {
+                    c.lexicon(URI);
+                });
+            }),
+            Box::new(|c| {
+                c.paragraph(|c| {
+                    c.lexicon(URI);
+                });
+            }),
+            Box::new(|c| {
+                c.sentence(|c| {
+                    c.lexicon(URI);
+                });
+            }),
+            Box::new(|c| {
+                c.audio("https://example.com/chime.wav", |c| {
+                    c.lexicon(URI);
+                });
+            }),
+        ];
+        for f in nested {
+            assert_eq!(rejected(&voice(|c| f(c))), "lexicon");
+        }
+    }
+
+    #[test]
+    fn prosody_values() {
+        let valid = |p: Prosody| p.validate().is_ok();
+        assert!(valid(Prosody::new().rate("x-fast")));
+        assert!(valid(Prosody::new().rate("1.5")));
+        assert!(valid(Prosody::new().rate("-20%")));
+        assert!(!valid(Prosody::new().rate("quick")));
+        assert!(valid(Prosody::new().pitch("+2st")));
+        assert!(valid(Prosody::new().pitch("-50Hz")));
+        assert!(!valid(Prosody::new().pitch("50")));
+        assert!(valid(Prosody::new().volume("80")));
+        assert!(valid(Prosody::new().volume("+10")));
+        assert!(valid(Prosody::new().volume("-5.5%")));
+        assert!(!valid(Prosody::new().volume("150")));
+        assert!(!valid(Prosody::new().volume("50%")));
+        assert!(!valid(Prosody::new().volume("NaN%")));
+    }
+}
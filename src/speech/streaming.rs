@@ -0,0 +1,745 @@
+//! Streaming text to speech of text produced incrementally, e.g. by a
+//! language model.
+//!
+//! Text is split into sentences, or clauses of long sentences, which are
+//! submitted in order by a background thread as soon as they complete, so
+//! the synthesis of a chunk overlaps the playback of the previous ones.
+//! Audio of the chunks is joined into one stream: the RIFF headers of the
+//! chunks are replaced by one streaming header, and PCM audio is faded in
+//! at the start and out at the end to avoid clicks, the seams are kept.
+
+use super::{
+    events::Flags,
+    format::{strip_riff_header, Encoding, OutputFormat},
+    synthesizer::{Synthesizer, SynthesizerAsync},
+};
+use crate::{error::Other, Result, SpxError};
+use futures::{
+    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Async, Poll, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    mem, str,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+/// Rules of splitting the text into chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkRules {
+    /// Language of the text, e.g. "en-US" or "zh-CN".
+    pub language: String,
+    /// Sentences shorter than this are merged with the next one, in
+    /// characters.
+    pub min_chars: usize,
+    /// Sentences longer than this are split at clauses.
+    pub clause_chars: usize,
+    /// Chunks without any boundary are split at spaces beyond this.
+    pub max_chars: usize,
+    /// Fade at the start and the end of PCM audio.
+    pub fade: Duration,
+}
+
+impl Default for ChunkRules {
+    fn default() -> Self {
+        ChunkRules {
+            language: String::from("en-US"),
+            min_chars: 16,
+            clause_chars: 80,
+            max_chars: 240,
+            fade: Duration::from_millis(5),
+        }
+    }
+}
+
+impl ChunkRules {
+    /// Default rules of the language.
+    pub fn for_language(language: &str) -> Self {
+        ChunkRules {
+            language: language.to_string(),
+            ..ChunkRules::default()
+        }
+    }
+}
+
+/// Punctuation of a language family.
+struct Punctuation {
+    sentence: &'static [char],
+    clause: &'static [char],
+    /// Words are separated by spaces, the terminators must be followed by
+    /// one.
+    spaced: bool,
+    /// Words ended by period which don't end the sentence.
+    abbreviations: &'static [&'static str],
+    /// Abbreviations only when a number follows, e.g. "No. 5".
+    numbered: &'static [&'static str],
+}
+
+const CLOSING: &[char] = &['"', '\'', ')', ']', '”', '’', '」', '』', '）'];
+
+const EN_ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "etc", "e.g",
+    "i.e", "inc", "ltd",
+];
+
+const EN_NUMBERED: &[&str] = &["no", "nos", "vol", "p", "pp", "fig"];
+
+fn punctuation(language: &str) -> Punctuation {
+    let primary = language.split('-').next().unwrap_or_default();
+    match primary.to_ascii_lowercase().as_str() {
+        "zh" | "ja" => Punctuation {
+            sentence: &['。', '！', '？', '!', '?'],
+            clause: &['，', '、', '；', '：', ',', ';'],
+            spaced: false,
+            abbreviations: &[],
+            numbered: &[],
+        },
+        "hi" | "bn" | "mr" | "ne" => Punctuation {
+            sentence: &['।', '॥', '.', '!', '?'],
+            clause: &[',', ';', ':'],
+            spaced: true,
+            abbreviations: &[],
+            numbered: &[],
+        },
+        "ar" | "fa" | "ur" => Punctuation {
+            sentence: &['.', '!', '؟', '?'],
+            clause: &['،', '؛', ',', ';', ':'],
+            spaced: true,
+            abbreviations: &[],
+            numbered: &[],
+        },
+        "en" => Punctuation {
+            sentence: &['.', '!', '?', '…'],
+            clause: &[',', ';', ':', '—'],
+            spaced: true,
+            abbreviations: EN_ABBREVIATIONS,
+            numbered: EN_NUMBERED,
+        },
+        _ => Punctuation {
+            sentence: &['.', '!', '?', '…'],
+            clause: &[',', ';', ':'],
+            spaced: true,
+            abbreviations: &[],
+            numbered: &[],
+        },
+    }
+}
+
+/// Incremental splitter of text into sentences and clauses.
+pub struct SentenceChunker {
+    rules: ChunkRules,
+    punct: Punctuation,
+    buffer: String,
+}
+
+impl SentenceChunker {
+    pub fn new(rules: ChunkRules) -> Self {
+        SentenceChunker {
+            punct: punctuation(&rules.language),
+            rules,
+            buffer: String::new(),
+        }
+    }
+
+    /// Append the text, return the chunks completed by it.
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+        let mut chunks = Vec::new();
+        while let Some(end) = self.boundary() {
+            let chunk: String = self.buffer.drain(..end).collect();
+            let chunk = chunk.trim();
+            if !chunk.is_empty() {
+                chunks.push(chunk.to_string());
+            }
+        }
+        chunks
+    }
+
+    /// The rest of the text at the end of input.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest: String = self.buffer.drain(..).collect();
+        let rest = rest.trim();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest.to_string())
+        }
+    }
+
+    /// End of the first complete chunk in the buffer.
+    fn boundary(&self) -> Option<usize> {
+        let text = self.buffer.as_str();
+        let mut count = 0;
+        let mut last_space = None;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            count += 1;
+            if c.is_whitespace() {
+                last_space = Some(i);
+                continue;
+            }
+            let sentence = self.punct.sentence.contains(&c);
+            let clause = self.punct.clause.contains(&c);
+            if sentence || clause {
+                // Take the repeated terminators and closing quotes.
+                let mut end = i + c.len_utf8();
+                while let Some(&(j, next)) = chars.peek() {
+                    if self.punct.sentence.contains(&next)
+                        || CLOSING.contains(&next)
+                    {
+                        end = j + next.len_utf8();
+                        count += 1;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if self.punct.spaced {
+                    match text[end..].chars().next() {
+                        Some(next) if next.is_whitespace() => {}
+                        // Wait for the next character.
+                        None => return None,
+                        // E.g. "3.14" or "a,b".
+                        Some(_) => continue,
+                    }
+                }
+                if c == '.' {
+                    let word = last_word(&text[..i]);
+                    if self.is_abbreviation(word) {
+                        continue;
+                    }
+                    if self.is_numbered(word) {
+                        match text[end..].trim_start().chars().next() {
+                            // Wait for the next word.
+                            None => return None,
+                            Some(next) if next.is_ascii_digit() => continue,
+                            Some(_) => {}
+                        }
+                    }
+                }
+                let min = if sentence {
+                    self.rules.min_chars
+                } else {
+                    self.rules.clause_chars
+                };
+                if count >= min {
+                    return Some(end);
+                }
+                continue;
+            }
+            if count > self.rules.max_chars {
+                if self.punct.spaced {
+                    if let Some(space) = last_space {
+                        return Some(space);
+                    }
+                } else {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+
+    /// The word before the period is an abbreviation or an initial.
+    fn is_abbreviation(&self, word: &str) -> bool {
+        if word.chars().count() == 1 {
+            return word.chars().all(char::is_uppercase);
+        }
+        let word = word.to_lowercase();
+        self.punct.abbreviations.iter().any(|a| *a == word)
+    }
+
+    /// The word before the period abbreviates "number" and the like.
+    fn is_numbered(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.punct.numbered.iter().any(|a| *a == word)
+    }
+}
+
+/// Last word of the text, without leading quotes or brackets.
+fn last_word(text: &str) -> &str {
+    text.rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+}
+
+/// Writer of the text to synthesize, the input ends when it is dropped.
+pub struct TextWriter {
+    sender: Sender<String>,
+    /// Incomplete UTF-8 sequence of the last write.
+    pending: Vec<u8>,
+}
+
+impl TextWriter {
+    /// Send the text without buffering.
+    pub fn send(&self, text: &str) -> Result {
+        self.sender
+            .send(text.to_string())
+            .map_err(|_| Other(String::from("synthesis is stopped")))
+    }
+}
+
+impl Write for TextWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => {
+                self.pending.clear();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        };
+        if valid > 0 {
+            let text: Vec<u8> = self.pending.drain(..valid).collect();
+            let text = String::from_utf8(text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.sender.send(text).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "synthesis is stopped",
+                )
+            })?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Continuous audio of the streamed text.
+pub struct SynthesizedAudio {
+    source: UnboundedReceiver<Result<Vec<u8>>>,
+    format: OutputFormat,
+}
+
+impl SynthesizedAudio {
+    /// Format of the audio stream, RIFF formats start with one header.
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+}
+
+impl Stream for SynthesizedAudio {
+    type Item = Vec<u8>;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, SpxError> {
+        match self.source.poll() {
+            Ok(Async::Ready(Some(Ok(data)))) => Ok(Async::Ready(Some(data))),
+            Ok(Async::Ready(Some(Err(err)))) => Err(err),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(Other(String::from("streaming is interrupted"))),
+        }
+    }
+}
+
+/// Start synthesis of the text written to the writer.
+pub fn stream_text(
+    synth: Synthesizer,
+    rules: ChunkRules,
+) -> Result<(TextWriter, SynthesizedAudio)> {
+    let (sender, receiver) = channel();
+    let audio = stream_channel(synth, rules, receiver)?;
+    let writer = TextWriter {
+        sender,
+        pending: Vec::new(),
+    };
+    Ok((writer, audio))
+}
+
+/// Start synthesis of the text received from the channel, until all the
+/// senders are dropped.
+pub fn stream_channel(
+    synth: Synthesizer,
+    rules: ChunkRules,
+    text: Receiver<String>,
+) -> Result<SynthesizedAudio> {
+    let format = synth.output_format()?;
+    let (sender, receiver) = unbounded();
+    thread::spawn(move || {
+        let fader = match format.encoding() {
+            Encoding::Pcm => {
+                let rate = u64::from(format.sample_rate());
+                let samples = rules.fade.as_millis() as u64 * rate / 1000;
+                Some(Fader::new(samples as usize))
+            }
+            _ => None,
+        };
+        let mut joiner = Joiner {
+            synth,
+            format,
+            fader,
+            sender,
+            started: false,
+            pending: VecDeque::new(),
+        };
+        let mut chunker = SentenceChunker::new(rules);
+        loop {
+            // Send the audio completed while waiting for the text.
+            let piece = if joiner.pending.is_empty() {
+                text.recv().ok()
+            } else {
+                match text.recv_timeout(POLL_INTERVAL) {
+                    Ok(piece) => Some(piece),
+                    Err(RecvTimeoutError::Timeout) => {
+                        if !joiner.emit(false) {
+                            return;
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => None,
+                }
+            };
+            let piece = match piece {
+                Some(piece) => piece,
+                None => break,
+            };
+            for chunk in chunker.push(&piece) {
+                if !joiner.speak(&chunk) {
+                    return;
+                }
+            }
+        }
+        if let Some(rest) = chunker.finish() {
+            if !joiner.speak(&rest) {
+                return;
+            }
+        }
+        if joiner.emit(true) {
+            joiner.finish();
+        }
+    });
+    Ok(SynthesizedAudio {
+        source: receiver,
+        format,
+    })
+}
+
+/// Chunks submitted to the synthesizer and not sent yet, beyond this the
+/// oldest one is waited.
+const MAX_PENDING: usize = 4;
+
+/// Interval of checking the submitted chunks.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Synthesize the chunks and join the audio.
+struct Joiner {
+    synth: Synthesizer,
+    format: OutputFormat,
+    /// Fade of PCM audio.
+    fader: Option<Fader>,
+    sender: UnboundedSender<Result<Vec<u8>>>,
+    started: bool,
+    /// Submitted chunks, in order.
+    pending: VecDeque<SynthesizerAsync>,
+}
+
+impl Joiner {
+    /// Submit the chunk and send the audio completed so far, false if the
+    /// stream should stop.
+    fn speak(&mut self, text: &str) -> bool {
+        log::debug!("synthesizing chunk of {} bytes", text.len());
+        match self.synth.speak_async(text) {
+            Ok(handle) => self.pending.push_back(handle),
+            Err(err) => {
+                self.fail(err);
+                return false;
+            }
+        }
+        self.emit(false)
+    }
+
+    /// Send the audio of the completed chunks in order, waiting for all of
+    /// them if `all`, or for the oldest ones beyond `MAX_PENDING`.
+    fn emit(&mut self, all: bool) -> bool {
+        while let Some(handle) = self.pending.front() {
+            let wait = all || self.pending.len() > MAX_PENDING;
+            let timeout = if wait {
+                POLL_INTERVAL
+            } else {
+                Duration::from_millis(0)
+            };
+            let synthesis = match handle.wait_for(timeout) {
+                Ok(Some(result)) => result.synthesis(Flags::Synthesized),
+                Ok(None) if wait => continue,
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            let data = match synthesis {
+                Ok(synthesis) => synthesis.audio_data,
+                Err(err) => {
+                    self.fail(err);
+                    return false;
+                }
+            };
+            self.pending.pop_front();
+            if !self.send(&data) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Send the audio of one chunk, false if the stream is dropped.
+    fn send(&mut self, data: &[u8]) -> bool {
+        let mut audio = Vec::with_capacity(data.len() + 44);
+        if !self.started {
+            if let Some(header) = self.format.stream_header() {
+                audio.extend(header);
+            }
+            self.started = true;
+        }
+        let data = strip_riff_header(data);
+        match self.fader {
+            Some(ref mut fader) => audio.extend(fader.push(data)),
+            None => audio.extend_from_slice(data),
+        }
+        self.forward(audio)
+    }
+
+    /// Send the faded end of the audio, false if the stream is dropped.
+    fn finish(&mut self) -> bool {
+        match self.fader {
+            Some(ref mut fader) => {
+                let tail = fader.finish();
+                self.forward(tail)
+            }
+            None => true,
+        }
+    }
+
+    /// End the audio before the error.
+    fn fail(&mut self, err: SpxError) {
+        if self.finish() {
+            let _ = self.sender.unbounded_send(Err(err));
+        }
+    }
+
+    fn forward(&mut self, audio: Vec<u8>) -> bool {
+        if audio.is_empty() {
+            return true;
+        }
+        if self.sender.unbounded_send(Ok(audio)).is_err() {
+            log::debug!("synthesized audio is dropped");
+            return false;
+        }
+        true
+    }
+}
+
+/// Fade in the start and fade out the end of the joined PCM audio. The end
+/// of each chunk is held back until the next one comes, so the seams are
+/// not faded.
+struct Fader {
+    samples: usize,
+    started: bool,
+    tail: Vec<u8>,
+}
+
+impl Fader {
+    fn new(samples: usize) -> Self {
+        Fader {
+            samples,
+            started: false,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Audio of the chunk to send, with the end of the previous one.
+    fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut audio = mem::replace(&mut self.tail, Vec::new());
+        audio.extend_from_slice(data);
+        if !self.started {
+            fade_in_pcm16(&mut audio, self.samples);
+            self.started = true;
+        }
+        let keep = self.samples.min(audio.len() / 2) * 2;
+        self.tail = audio.split_off(audio.len() - keep);
+        audio
+    }
+
+    /// Faded end of the audio.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut tail = mem::replace(&mut self.tail, Vec::new());
+        fade_out_pcm16(&mut tail, self.samples);
+        tail
+    }
+}
+
+/// Fade in the first samples of 16 bits little endian.
+fn fade_in_pcm16(data: &mut [u8], samples: usize) {
+    let samples = samples.min(data.len() / 2);
+    for i in 0..samples {
+        scale_pcm16(data, i, i as f32 / samples as f32);
+    }
+}
+
+/// Fade out the last samples of 16 bits little endian, a trailing odd byte
+/// is left as is.
+fn fade_out_pcm16(data: &mut [u8], samples: usize) {
+    let total = data.len() / 2;
+    let samples = samples.min(total);
+    for i in 0..samples {
+        scale_pcm16(data, total - 1 - i, i as f32 / samples as f32);
+    }
+}
+
+fn scale_pcm16(data: &mut [u8], index: usize, gain: f32) {
+    let pos = index * 2;
+    let sample = i16::from_le_bytes([data[pos], data[pos + 1]]);
+    let faded = (f32::from(sample) * gain) as i16;
+    data[pos..pos + 2].copy_from_slice(&faded.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(
+        language: &str,
+        min: usize,
+        clause: usize,
+        max: usize,
+    ) -> ChunkRules {
+        ChunkRules {
+            min_chars: min,
+            clause_chars: clause,
+            max_chars: max,
+            ..ChunkRules::for_language(language)
+        }
+    }
+
+    fn split(rules: ChunkRules, text: &str) -> Vec<String> {
+        let mut chunker = SentenceChunker::new(rules);
+        let mut chunks = chunker.push(text);
+        chunks.extend(chunker.finish());
+        chunks
+    }
+
+    #[test]
+    fn chunker_splits_sentences() {
+        let cases: &[(&str, &[&str])] = &[
+            ("One. Two! Three? Four", &["One.", "Two!", "Three?", "Four"]),
+            ("Wait... what?! Ok", &["Wait...", "what?!", "Ok"]),
+            (
+                "He said \"hi.\" Then left.",
+                &["He said \"hi.\"", "Then left."],
+            ),
+            ("Pi is 3.14 today. Yes", &["Pi is 3.14 today.", "Yes"]),
+            ("Ask Dr. Smith now. Ok", &["Ask Dr. Smith now.", "Ok"]),
+            ("See e.g. this. Ok", &["See e.g. this.", "Ok"]),
+            ("By J. R. Tolkien. Ok", &["By J. R. Tolkien.", "Ok"]),
+            ("Take No. 5 now. Ok", &["Take No. 5 now.", "Ok"]),
+            ("I said no. Then left", &["I said no.", "Then left"]),
+            ("Say no. 5 more", &["Say no. 5 more"]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(split(rules("en-US", 0, 1000, 1000), text), *expected);
+        }
+    }
+
+    #[test]
+    fn chunker_merges_short_sentences() {
+        let chunks =
+            split(rules("en-US", 8, 1000, 1000), "Hi. Ok. Longer one. End");
+        assert_eq!(chunks, vec!["Hi. Ok. Longer one.", "End"]);
+    }
+
+    #[test]
+    fn chunker_splits_long_sentences() {
+        let chunks = split(
+            rules("en-US", 0, 10, 1000),
+            "Short, then a longer clause, end",
+        );
+        assert_eq!(chunks, vec!["Short, then a longer clause,", "end"]);
+
+        let chunks =
+            split(rules("en-US", 0, 1000, 10), "one two three four five");
+        assert_eq!(chunks, vec!["one two", "three", "four five"]);
+
+        let chunks = split(rules("zh-CN", 0, 1000, 4), "一二三四五六");
+        assert_eq!(chunks, vec!["一二三四", "五六"]);
+    }
+
+    #[test]
+    fn chunker_splits_without_spaces() {
+        let chunks =
+            split(rules("zh-CN", 0, 3, 1000), "你好。今天天气很好，我们走吧！");
+        assert_eq!(chunks, vec!["你好。", "今天天气很好，", "我们走吧！"]);
+    }
+
+    #[test]
+    fn chunker_waits_for_the_next_character() {
+        let mut chunker = SentenceChunker::new(rules("en-US", 0, 1000, 1000));
+        assert!(chunker.push("Pi is 3.").is_empty());
+        assert!(chunker.push("14.").is_empty());
+        assert_eq!(chunker.push(" See No."), vec!["Pi is 3.14."]);
+        assert!(chunker.push(" ").is_empty());
+        assert_eq!(chunker.push("Go"), vec!["See No."]);
+        assert_eq!(chunker.finish(), Some(String::from("Go")));
+        assert_eq!(chunker.finish(), None);
+    }
+
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn fade_ramps_the_ends() {
+        let mut data = pcm(&[1000; 6]);
+        fade_in_pcm16(&mut data, 2);
+        assert_eq!(data, pcm(&[0, 500, 1000, 1000, 1000, 1000]));
+        fade_out_pcm16(&mut data, 4);
+        assert_eq!(data, pcm(&[0, 500, 750, 500, 250, 0]));
+
+        let mut data = pcm(&[-1000; 4]);
+        fade_in_pcm16(&mut data, 4);
+        assert_eq!(data, pcm(&[0, -250, -500, -750]));
+    }
+
+    #[test]
+    fn fade_is_limited_to_short_data() {
+        let mut data = pcm(&[1000; 2]);
+        fade_in_pcm16(&mut data, 10);
+        assert_eq!(data, pcm(&[0, 500]));
+
+        let mut data = pcm(&[1000; 4]);
+        fade_out_pcm16(&mut data, 0);
+        assert_eq!(data, pcm(&[1000; 4]));
+
+        // A trailing odd byte is left as is.
+        let mut data = vec![0xe8, 0x03, 0xe8, 0x03, 0x7f];
+        fade_out_pcm16(&mut data, 1);
+        assert_eq!(data, vec![0xe8, 0x03, 0, 0, 0x7f]);
+
+        let mut data = Vec::new();
+        fade_in_pcm16(&mut data, 4);
+        fade_out_pcm16(&mut data, 4);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn fader_keeps_the_seams() {
+        let mut fader = Fader::new(2);
+        let mut audio = fader.push(&pcm(&[1000; 4]));
+        assert_eq!(audio, pcm(&[0, 500]));
+        audio.extend(fader.push(&pcm(&[1000; 3])));
+        audio.extend(fader.push(&pcm(&[1000; 1])));
+        audio.extend(fader.finish());
+        assert_eq!(audio, pcm(&[0, 500, 1000, 1000, 1000, 1000, 500, 0]));
+        assert!(fader.finish().is_empty());
+    }
+}
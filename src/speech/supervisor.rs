@@ -0,0 +1,762 @@
+//! Supervisor of continuous recognition which survives transient failures.
+//!
+//! Audio sent through the supervisor is buffered since the last final
+//! result, as it is forwarded after voice detection. When the session is
+//! canceled by a retryable error, the connection is reopened with exponential
+//! backoff, the buffered audio is sent again as is and the offsets of later
+//! events are shifted to keep them monotonic.
+
+use super::{
+    audio::AudioSpec,
+    events::{Flags, OwnedEvent},
+    recognizer::{Connection, Recognizer},
+};
+use crate::{
+    error::{Other, Poisoned},
+    Result, SpxError,
+};
+use futures::{
+    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Async, Poll, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Backoff and buffering of reconnection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Upper bound of the delay.
+    pub max_delay: Duration,
+    /// Growth of the delay after each failed attempt.
+    pub multiplier: f64,
+    /// Give up after the attempts in a row, retry forever if not set.
+    pub max_attempts: Option<u32>,
+    /// Longest audio kept for re-sending.
+    pub max_buffer: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+            max_buffer: Duration::from_secs(300),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the attempt, starting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt as i32 - 1);
+        let millis = self.initial_delay.as_millis() as f64 * factor;
+        let max = self.max_delay.as_millis() as f64;
+        Duration::from_millis(millis.min(max) as u64)
+    }
+}
+
+/// Events of one session.
+type Events = Box<dyn Stream<Item = OwnedEvent, Error = SpxError> + Send>;
+
+/// Operations of the supervised recognizer.
+trait Session: Send {
+    fn start(&mut self, flags: Flags) -> Result<Events>;
+
+    /// Drop the broken session and start again on a new connection.
+    fn restart(&mut self, flags: Flags) -> Result<Events>;
+
+    fn stop(&mut self) -> Result;
+
+    /// Pass the written audio through voice detection and the recorder,
+    /// return the audio to be sent.
+    fn filter(&mut self, data: &[u8]) -> Vec<u8>;
+
+    /// Audio left in voice detection at the end of input.
+    fn finish(&mut self) -> Vec<u8>;
+
+    /// Send the audio as is.
+    fn send(&mut self, data: &mut [u8]) -> Result;
+
+    fn close(&mut self) -> Result;
+}
+
+impl Session for Recognizer {
+    fn start(&mut self, flags: Flags) -> Result<Events> {
+        Ok(Box::new(self.start_flags_owned(flags)?))
+    }
+
+    fn restart(&mut self, flags: Flags) -> Result<Events> {
+        if let Err(err) = Recognizer::stop(self) {
+            log::debug!("failed to stop broken session: {}", err);
+        }
+        Connection::from_recognizer(self)?.open(true)?;
+        Session::start(self, flags)
+    }
+
+    fn stop(&mut self) -> Result {
+        Recognizer::stop(self)
+    }
+
+    fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        self.filter_audio(data)
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.finish_audio()
+    }
+
+    fn send(&mut self, data: &mut [u8]) -> Result {
+        self.write_raw(data)
+    }
+
+    fn close(&mut self) -> Result {
+        self.end_stream()
+    }
+}
+
+type SessionRef = Arc<Mutex<Box<dyn Session>>>;
+
+/// State of reconnection. It is locked before the session when both are
+/// needed, and never held while the session is stopped or started.
+struct Link {
+    spec: AudioSpec,
+    policy: ReconnectPolicy,
+    /// Audio sent since the last final result, after voice detection.
+    buffer: VecDeque<u8>,
+    /// Offset of the first buffered byte in the audio sent.
+    buffer_start: Duration,
+    /// Offset of the audio sent first in current connection.
+    base: Duration,
+    /// Latest offset yielded, to keep them monotonic.
+    last_offset: Duration,
+    /// Audio written while disconnected, not passed to the session yet.
+    held: Vec<u8>,
+    connected: bool,
+    closed: bool,
+    stopped: bool,
+    reconnects: u32,
+}
+
+impl Link {
+    fn max_buffer(&self) -> usize {
+        align(self.spec, self.spec.to_bytes(self.policy.max_buffer)) as usize
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+        let max = self.max_buffer();
+        if self.buffer.len() > max {
+            let excess = self.buffer.len() - max;
+            self.drop_front(excess);
+            log::warn!("reconnection buffer is full, {} bytes dropped", excess);
+        }
+    }
+
+    fn hold(&mut self, data: &[u8]) {
+        self.held.extend_from_slice(data);
+        let max = self.max_buffer();
+        if self.held.len() > max {
+            let excess = self.held.len() - max;
+            self.held.drain(..excess);
+            log::warn!("reconnection buffer is full, {} bytes dropped", excess);
+        }
+    }
+
+    fn drop_front(&mut self, size: usize) {
+        let size = size.min(self.buffer.len());
+        self.buffer.drain(..size);
+        self.buffer_start += self.spec.to_duration(size as u64);
+    }
+
+    /// Release the audio before the end of the final result.
+    fn commit(&mut self, end: Duration) {
+        if end > self.buffer_start {
+            let size =
+                align(self.spec, self.spec.to_bytes(end - self.buffer_start));
+            self.drop_front(size as usize);
+        }
+    }
+
+    /// Shift the offset of current connection to the whole audio sent.
+    fn stitch(&mut self, offset: Duration) -> Duration {
+        let offset = (self.base + offset).max(self.last_offset);
+        self.last_offset = offset;
+        offset
+    }
+
+    /// Send the buffered and held audio on a new session, return the size
+    /// sent.
+    fn resume(&mut self, session: &mut dyn Session) -> Result<usize> {
+        self.base = self.buffer_start;
+        if !self.held.is_empty() {
+            let held = std::mem::replace(&mut self.held, Vec::new());
+            let data = session.filter(&held);
+            self.push(&data);
+        }
+        let mut data: Vec<u8> = self.buffer.iter().cloned().collect();
+        if !data.is_empty() {
+            session.send(&mut data)?;
+        }
+        if self.closed {
+            let mut rest = session.finish();
+            if !rest.is_empty() {
+                self.push(&rest);
+                session.send(&mut rest)?;
+            }
+            session.close()?;
+        }
+        self.connected = true;
+        Ok(data.len())
+    }
+}
+
+/// Round the size down to whole sample frames.
+fn align(spec: AudioSpec, size: u64) -> u64 {
+    let frame = u64::from(spec.bits / 8) * u64::from(spec.channels);
+    size - size % frame.max(1)
+}
+
+/// Start a new session after the cancellation. The link is not locked
+/// while the session is restarted, so writes are only held meanwhile.
+fn reconnect(
+    link: &Mutex<Link>,
+    session: &SessionRef,
+    flags: Flags,
+) -> Result<Events> {
+    if link.lock().map_err(|_| Poisoned)?.stopped {
+        return Err(Other(String::from("supervisor is stopped")));
+    }
+    let stream = session.lock().map_err(|_| Poisoned)?.restart(flags)?;
+    let mut link = link.lock().map_err(|_| Poisoned)?;
+    let mut session = session.lock().map_err(|_| Poisoned)?;
+    if link.stopped {
+        if let Err(err) = session.stop() {
+            log::debug!("failed to stop session: {}", err);
+        }
+        return Err(Other(String::from("supervisor is stopped")));
+    }
+    let size = link.resume(&mut **session)?;
+    link.reconnects += 1;
+    log::info!("reconnected, {} bytes of audio are sent again", size);
+    Ok(stream)
+}
+
+/// Owner of the recognizer of push stream which reconnects on transient
+/// failures.
+#[derive(Clone)]
+pub struct Supervisor {
+    link: Arc<Mutex<Link>>,
+    session: SessionRef,
+}
+
+impl Supervisor {
+    /// Supervise the recognizer of push stream in the audio format.
+    pub fn new(
+        reco: Recognizer,
+        spec: AudioSpec,
+        policy: ReconnectPolicy,
+    ) -> Self {
+        Supervisor::with_session(Box::new(reco), spec, policy)
+    }
+
+    fn with_session(
+        session: Box<dyn Session>,
+        spec: AudioSpec,
+        policy: ReconnectPolicy,
+    ) -> Self {
+        let link = Link {
+            spec,
+            policy,
+            buffer: VecDeque::new(),
+            buffer_start: Duration::default(),
+            base: Duration::default(),
+            last_offset: Duration::default(),
+            held: Vec::new(),
+            connected: false,
+            closed: false,
+            stopped: false,
+            reconnects: 0,
+        };
+        Supervisor {
+            link: Arc::new(Mutex::new(link)),
+            session: Arc::new(Mutex::new(session)),
+        }
+    }
+
+    /// Start continuous recognition, events are forwarded by a background
+    /// thread until the session ends or reconnection gives up. Audio
+    /// written before is sent once the session is started.
+    pub fn start(&self, flags: Flags) -> Result<SupervisedStream> {
+        let flags = flags | Flags::Session | Flags::Canceled;
+        let stream = {
+            let mut link = self.link.lock().map_err(|_| Poisoned)?;
+            let mut session = self.session.lock().map_err(|_| Poisoned)?;
+            let stream = session.start(flags)?;
+            let size = link.buffer.len();
+            link.drop_front(size);
+            link.stopped = false;
+            link.resume(&mut **session)?;
+            stream
+        };
+        let (sender, receiver) = unbounded();
+        let link = self.link.clone();
+        let session = self.session.clone();
+        thread::spawn(move || pump(link, session, flags, stream, sender));
+        Ok(SupervisedStream { source: receiver })
+    }
+
+    /// Write audio data, it is only held while reconnecting.
+    pub fn write(&self, data: &[u8]) -> Result {
+        let mut link = self.link.lock().map_err(|_| Poisoned)?;
+        if !link.connected {
+            link.hold(data);
+            return Ok(());
+        }
+        let mut session = self.session.lock().map_err(|_| Poisoned)?;
+        let mut data = session.filter(data);
+        if !data.is_empty() {
+            link.push(&data);
+            session.send(&mut data)?;
+        }
+        Ok(())
+    }
+
+    /// Close the push stream gracefully, after the audio held if
+    /// reconnecting.
+    pub fn close(&self) -> Result {
+        let mut link = self.link.lock().map_err(|_| Poisoned)?;
+        link.closed = true;
+        if !link.connected {
+            return Ok(());
+        }
+        let mut session = self.session.lock().map_err(|_| Poisoned)?;
+        let mut rest = session.finish();
+        if !rest.is_empty() {
+            link.push(&rest);
+            session.send(&mut rest)?;
+        }
+        session.close()
+    }
+
+    /// Stop the session and the reconnection.
+    pub fn stop(&self) -> Result {
+        {
+            let mut link = self.link.lock().map_err(|_| Poisoned)?;
+            link.stopped = true;
+            link.connected = false;
+        }
+        self.session.lock().map_err(|_| Poisoned)?.stop()
+    }
+
+    /// Times of successful reconnection.
+    pub fn reconnects(&self) -> Result<u32> {
+        Ok(self.link.lock().map_err(|_| Poisoned)?.reconnects)
+    }
+}
+
+/// Stitched events of the supervised sessions.
+pub struct SupervisedStream {
+    source: UnboundedReceiver<Result<OwnedEvent>>,
+}
+
+impl Stream for SupervisedStream {
+    type Item = OwnedEvent;
+    type Error = SpxError;
+
+    fn poll(&mut self) -> Poll<Option<OwnedEvent>, SpxError> {
+        match self.source.poll() {
+            Ok(Async::Ready(Some(Ok(evt)))) => Ok(Async::Ready(Some(evt))),
+            Ok(Async::Ready(Some(Err(err)))) => Err(err),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(Other(String::from("streaming is interrupted"))),
+        }
+    }
+}
+
+type Sender = UnboundedSender<Result<OwnedEvent>>;
+
+/// Forward the events and reconnect until the end.
+fn pump(
+    link: Arc<Mutex<Link>>,
+    session: SessionRef,
+    flags: Flags,
+    mut stream: Events,
+    sender: Sender,
+) {
+    let mut attempts = 0;
+    loop {
+        let canceled = match forward(&link, stream, &sender, &mut attempts) {
+            Some(evt) => evt,
+            None => return,
+        };
+        stream = loop {
+            attempts += 1;
+            let policy = match link.lock() {
+                Ok(link) => link.policy.clone(),
+                Err(_) => return,
+            };
+            if policy.max_attempts.map_or(false, |max| attempts > max) {
+                log::error!(
+                    "reconnection gives up after {} attempts",
+                    attempts - 1
+                );
+                let _ = sender.unbounded_send(Ok(canceled));
+                return;
+            }
+            thread::sleep(policy.delay(attempts));
+            match reconnect(&link, &session, flags) {
+                Ok(stream) => break stream,
+                Err(err) => {
+                    log::warn!(
+                        "reconnection attempt {} failed: {}",
+                        attempts,
+                        err
+                    );
+                    if link.lock().map(|link| link.stopped).unwrap_or(true) {
+                        return;
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Forward the events of one connection. Return the cancellation if it is
+/// retryable.
+fn forward(
+    link: &Arc<Mutex<Link>>,
+    stream: Events,
+    sender: &Sender,
+    attempts: &mut u32,
+) -> Option<OwnedEvent> {
+    for item in stream.wait() {
+        let mut evt = match item {
+            Ok(evt) => evt,
+            Err(err) => {
+                let _ = sender.unbounded_send(Err(err));
+                return None;
+            }
+        };
+        let mut link = match link.lock() {
+            Ok(link) => link,
+            Err(_) => return None,
+        };
+        if let OwnedEvent::Canceled {
+            error: Some(ref error),
+            ..
+        } = evt
+        {
+            if error.is_retryable() && !link.stopped {
+                log::warn!("session is canceled: {}", error.details());
+                link.connected = false;
+                return Some(evt);
+            }
+        }
+        match evt {
+            OwnedEvent::SpeechDetection { ref mut offset, .. } => {
+                *offset = link.stitch(*offset);
+            }
+            OwnedEvent::Recognition {
+                flag,
+                offset: Some(ref mut offset),
+                duration,
+                ..
+            } => {
+                *offset = link.stitch(*offset);
+                if flag.intersects(Flags::Recognized) {
+                    link.commit(*offset + duration.unwrap_or_default());
+                    *attempts = 0;
+                }
+            }
+            _ => {}
+        }
+        drop(link);
+        if sender.unbounded_send(Ok(evt)).is_err() {
+            log::debug!("supervised stream is dropped");
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{speech::events::CancellationError, speech_api::*};
+    use serde_json::json;
+    use std::time::Instant;
+
+    /// Session of which voice detection drops silent samples.
+    #[derive(Clone, Default)]
+    struct Fake {
+        sessions: Arc<Mutex<Vec<UnboundedSender<OwnedEvent>>>>,
+        /// Audio sent to each session.
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+        /// Audio passed through voice detection.
+        filtered: Arc<Mutex<Vec<u8>>>,
+        /// Restarts to be failed.
+        failures: Arc<Mutex<u32>>,
+        closed: Arc<Mutex<bool>>,
+    }
+
+    impl Session for Fake {
+        fn start(&mut self, _flags: Flags) -> Result<Events> {
+            let (s, r) = unbounded();
+            self.sessions.lock().unwrap().push(s);
+            self.sent.lock().unwrap().push(Vec::new());
+            let events = r.map_err(|_| Other(String::from("unreachable")));
+            Ok(Box::new(events))
+        }
+
+        fn restart(&mut self, flags: Flags) -> Result<Events> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Other(String::from("connection refused")));
+            }
+            drop(failures);
+            self.start(flags)
+        }
+
+        fn stop(&mut self) -> Result {
+            Ok(())
+        }
+
+        fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+            let voiced: Vec<u8> = data
+                .chunks(2)
+                .filter(|sample| sample.iter().any(|b| *b != 0))
+                .flatten()
+                .cloned()
+                .collect();
+            self.filtered.lock().unwrap().extend(&voiced);
+            voiced
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn send(&mut self, data: &mut [u8]) -> Result {
+            let mut sent = self.sent.lock().unwrap();
+            sent.last_mut().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result {
+            *self.closed.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    impl Fake {
+        fn post(&self, evt: OwnedEvent) {
+            let sessions = self.sessions.lock().unwrap();
+            sessions.last().unwrap().unbounded_send(evt).unwrap();
+        }
+    }
+
+    fn wait_reconnects(sup: &Supervisor, count: u32) {
+        let begin = Instant::now();
+        while sup.reconnects().unwrap() < count {
+            assert!(begin.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn supervise(fake: &Fake, policy: ReconnectPolicy) -> Supervisor {
+        let spec = AudioSpec::default();
+        Supervisor::with_session(Box::new(fake.clone()), spec, policy)
+    }
+
+    fn policy(delay_ms: u64) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(delay_ms),
+            max_delay: Duration::from_millis(delay_ms),
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    /// Voice of the duration, 32 bytes per ms in the default format.
+    fn voice(ms: usize) -> Vec<u8> {
+        [1u8, 0].iter().cycle().take(ms * 32).cloned().collect()
+    }
+
+    fn recognized(offset_ms: u64, duration_ms: u64) -> OwnedEvent {
+        OwnedEvent::Recognition {
+            flag: Flags::Recognized,
+            session: String::from("session"),
+            id: String::from("result"),
+            reason: Flags::Recognized,
+            offset: Some(Duration::from_millis(offset_ms)),
+            duration: Some(Duration::from_millis(duration_ms)),
+            text: Some(String::from("text")),
+            intent: None,
+            details: None,
+            translations: None,
+            language: None,
+        }
+    }
+
+    fn error(code: Result_CancellationErrorCode) -> CancellationError {
+        serde_json::from_value(json!({
+            "reason": Result_CancellationReason_CancellationReason_Error,
+            "code": code,
+            "details": "broken",
+        }))
+        .unwrap()
+    }
+
+    fn canceled(code: Result_CancellationErrorCode) -> OwnedEvent {
+        OwnedEvent::Canceled {
+            flag: Flags::Canceled,
+            session: String::from("session"),
+            id: String::from("result"),
+            reason: Flags::Canceled,
+            error: Some(error(code)),
+        }
+    }
+
+    fn offset(evt: &OwnedEvent) -> Duration {
+        match *evt {
+            OwnedEvent::Recognition {
+                offset: Some(offset),
+                ..
+            } => offset,
+            ref evt => panic!("unexpected event: {:?}", evt),
+        }
+    }
+
+    #[test]
+    fn reconnect_resends_forwarded_audio() {
+        let fake = Fake::default();
+        let sup = supervise(&fake, policy(1));
+        let mut events = sup.start(Flags::Recognized).unwrap().wait();
+        sup.write(&voice(100)).unwrap();
+        sup.write(&[0; 3200]).unwrap();
+        sup.write(&voice(100)).unwrap();
+        assert_eq!(fake.sent.lock().unwrap()[0].len(), 6400);
+
+        fake.post(recognized(0, 100));
+        let evt = events.next().unwrap().unwrap();
+        assert_eq!(offset(&evt), Duration::from_millis(0));
+
+        fake.post(canceled(
+            Result_CancellationErrorCode_CancellationErrorCode_ConnectionFailure,
+        ));
+        wait_reconnects(&sup, 1);
+        // Only the audio forwarded after the final result is sent again,
+        // and it is neither detected nor recorded once more.
+        assert_eq!(fake.sent.lock().unwrap()[1], voice(100));
+        assert_eq!(fake.filtered.lock().unwrap().len(), 6400);
+        assert_eq!(sup.reconnects().unwrap(), 1);
+
+        fake.post(recognized(50, 20));
+        let evt = events.next().unwrap().unwrap();
+        assert_eq!(offset(&evt), Duration::from_millis(150));
+    }
+
+    #[test]
+    fn writes_are_held_while_reconnecting() {
+        let fake = Fake::default();
+        *fake.failures.lock().unwrap() = 2;
+        let sup = supervise(&fake, policy(50));
+        let _events = sup.start(Flags::Recognized).unwrap();
+        sup.write(&voice(10)).unwrap();
+
+        fake.post(canceled(
+            Result_CancellationErrorCode_CancellationErrorCode_ServiceTimeout,
+        ));
+        let begin = Instant::now();
+        while sup.link.lock().unwrap().connected {
+            assert!(begin.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        sup.write(&[0; 320]).unwrap();
+        sup.write(&voice(5)).unwrap();
+        sup.close().unwrap();
+        assert!(!*fake.closed.lock().unwrap());
+
+        wait_reconnects(&sup, 1);
+        let mut expected = voice(10);
+        expected.extend(voice(5));
+        assert_eq!(fake.sent.lock().unwrap()[1], expected);
+        assert!(*fake.closed.lock().unwrap());
+        assert_eq!(sup.reconnects().unwrap(), 1);
+    }
+
+    #[test]
+    fn reconnection_gives_up() {
+        let fake = Fake::default();
+        *fake.failures.lock().unwrap() = u32::max_value();
+        let sup = supervise(
+            &fake,
+            ReconnectPolicy {
+                max_attempts: Some(2),
+                ..policy(1)
+            },
+        );
+        let events = sup.start(Flags::Recognized).unwrap();
+        fake.post(canceled(
+            Result_CancellationErrorCode_CancellationErrorCode_ServiceUnavailable,
+        ));
+        let events: Vec<_> = events.wait().collect();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Ok(OwnedEvent::Canceled { .. }) => {}
+            ref evt => panic!("unexpected event: {:?}", evt),
+        }
+        assert_eq!(*fake.failures.lock().unwrap(), u32::max_value() - 2);
+        assert_eq!(sup.reconnects().unwrap(), 0);
+    }
+
+    #[test]
+    fn fatal_cancellation_is_forwarded() {
+        let fake = Fake::default();
+        let sup = supervise(&fake, policy(1));
+        let events = sup.start(Flags::Recognized).unwrap();
+        fake.post(canceled(
+            Result_CancellationErrorCode_CancellationErrorCode_AuthenticationFailure,
+        ));
+        fake.sessions.lock().unwrap().clear();
+        let events: Vec<_> = events.wait().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(fake.sent.lock().unwrap().len(), 1);
+        assert_eq!(sup.reconnects().unwrap(), 0);
+    }
+
+    #[test]
+    fn commit_keeps_whole_samples() {
+        let fake = Fake::default();
+        let sup = supervise(&fake, policy(1));
+        let mut link = sup.link.lock().unwrap();
+        link.push(&voice(10));
+        link.commit(Duration::from_micros(1_030));
+        assert_eq!(link.buffer.len(), 320 - 32);
+        assert_eq!(link.buffer_start, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn delay_grows_to_max() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            ..ReconnectPolicy::default()
+        };
+        let delays: Vec<_> =
+            (1..6).map(|i| policy.delay(i).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+    }
+}
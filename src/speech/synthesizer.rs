@@ -1,25 +1,36 @@
 //! Synthesizer for text to speech with streaming support.
 
 use super::{
-    audio::{Audio, AudioStream},
-    events::{Event, Flags, Recognition, Session, SynthEventResult},
+    audio::{Audio, AudioStream, OutputReader},
+    auth::TokenTarget,
+    events::{
+        Event, Flags, Recognition, Session, SynthEventResult, Synthesis,
+        SynthesisMark,
+    },
+    format::OutputFormat,
+    normalize::Normalizer,
+    progress::{SynthesisTask, Tracker},
+    ssml::AsSsml,
 };
 
 use crate::{
-    error::{AlreadyExists, Other, SpxError},
+    error::{AlreadyExists, IsNothing, Other, Poisoned, SpxError},
     hr,
-    properties::Properties,
+    properties::{Properties, Property, PropertyBag},
     speech_api::{
         synthesizer_async_handle_is_valid, synthesizer_async_handle_release,
+        synthesizer_bookmark_reached_set_callback,
         synthesizer_canceled_set_callback, synthesizer_completed_set_callback,
         synthesizer_disable, synthesizer_enable, synthesizer_get_property_bag,
         synthesizer_handle_is_valid, synthesizer_handle_release,
-        synthesizer_speak_ssml, synthesizer_speak_ssml_async,
-        synthesizer_speak_text, synthesizer_speak_text_async,
-        synthesizer_start_speaking_ssml_async,
+        synthesizer_speak_async_wait_for, synthesizer_speak_ssml,
+        synthesizer_speak_ssml_async, synthesizer_speak_text,
+        synthesizer_speak_text_async, synthesizer_start_speaking_ssml_async,
         synthesizer_start_speaking_text_async,
-        synthesizer_started_set_callback,
-        synthesizer_synthesizing_set_callback, SPXASYNCHANDLE, SPXEVENTHANDLE,
+        synthesizer_started_set_callback, synthesizer_stop_speaking,
+        synthesizer_synthesizing_set_callback,
+        synthesizer_viseme_received_set_callback,
+        synthesizer_word_boundary_set_callback, SPXASYNCHANDLE, SPXEVENTHANDLE,
         SPXSYNTHHANDLE,
     },
     DeriveHandle, FlattenProps, Result, SmartHandle, INVALID_HANDLE,
@@ -33,7 +44,9 @@ use futures::{
 use std::{
     ffi::CString,
     os::raw::c_void,
-    sync::{Arc, Weak},
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
 };
 
 /// The event callback definition macro.
@@ -57,6 +70,26 @@ SmartHandle!(
     synthesizer_async_handle_is_valid
 );
 
+/// Error code of the SDK when the wait times out.
+const SPXERR_TIMEOUT: usize = 0x006;
+
+impl SynthesizerAsync {
+    /// Wait the result at most timeout, None if it is not ready.
+    pub fn wait_for(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<SynthEventResult>> {
+        let mut hres = INVALID_HANDLE;
+        let ms = timeout.as_millis() as u32;
+        match hr!(synthesizer_speak_async_wait_for(self.handle, ms, &mut hres))
+        {
+            Ok(()) => SynthEventResult::new(Flags::empty(), hres).map(Some),
+            Err(SpxError::ApiError(SPXERR_TIMEOUT)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
 DeriveHandle!(
     Synthesizer,
     SPXSYNTHHANDLE,
@@ -69,12 +102,18 @@ pub struct Synthesizer {
     handle: SPXSYNTHHANDLE,
     flags: Flags,
     audio: Audio,
-    sink: Option<Arc<UnboundedSender<Event>>>,
+    relay: Option<Arc<Relay>>,
     /// Internal properties bag.
-    props: Properties,
+    props: Arc<Properties>,
 }
 FlattenProps!(Synthesizer);
 
+impl TokenTarget for Synthesizer {
+    fn token_props(&self) -> Weak<Properties> {
+        Arc::downgrade(&self.props)
+    }
+}
+
 impl Synthesizer {
     /// Constructor.
     pub fn new(
@@ -88,69 +127,133 @@ impl Synthesizer {
             handle,
             flags,
             audio,
-            sink: None,
-            props: Properties::new(hprops),
+            relay: None,
+            props: Arc::new(Properties::new(hprops)),
         })
     }
 
-    /// Execute the speech synthesis on plain text, asynchronously.
-    pub fn synthesize(&mut self, text: &str) -> Result {
+    /// Replace the authorization token before it expires.
+    pub fn set_authorization_token(&self, token: &str) -> Result {
+        self.props
+            .put_by_id(Property::AuthorizationToken.id(), token)
+    }
+
+    /// Execute the speech synthesis on plain text, asynchronously. The task
+    /// reports the progress and resolves the outcome of this request.
+    pub fn synthesize(&mut self, text: &str) -> Result<SynthesisTask> {
         let mut hasync = INVALID_HANDLE;
         let txt_len = text.len() as u32;
         let txt = CString::new(text)?;
-        hr!(synthesizer_speak_text_async(
-            self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hasync
-        ))?;
+        let task = self.track(|handle| {
+            hr!(synthesizer_speak_text_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
         SynthesizerAsync::new(hasync);
-        Ok(())
+        Ok(task)
     }
 
-    /// Execute the speech synthesis on SSML, synchronously.
-    pub fn ssml_synthesis(&mut self, text: &str) -> Result {
+    /// Execute the speech synthesis on SSML, asynchronously. The task
+    /// reports the progress and resolves the outcome of this request.
+    pub fn ssml_synthesis<S: AsSsml + ?Sized>(
+        &mut self,
+        ssml: &S,
+    ) -> Result<SynthesisTask> {
+        let text = ssml.as_ssml()?;
         let mut hasync = INVALID_HANDLE;
         let txt_len = text.len() as u32;
-        let txt = CString::new(text)?;
-        hr!(synthesizer_speak_ssml_async(
-            self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hasync
-        ))?;
+        let txt = CString::new(text.as_bytes())?;
+        let task = self.track(|handle| {
+            hr!(synthesizer_speak_ssml_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
         SynthesizerAsync::new(hasync);
-        Ok(())
+        Ok(task)
     }
 
-    /// Execute the speech synthesis on plain text, asynchronously.
-    pub fn start_synthesize(&mut self, text: &str) -> Result {
+    /// Execute the speech synthesis on plain text, the returned handle waits
+    /// the result.
+    pub fn speak_async(&mut self, text: &str) -> Result<SynthesizerAsync> {
         let mut hasync = INVALID_HANDLE;
         let txt_len = text.len() as u32;
         let txt = CString::new(text)?;
-        hr!(synthesizer_start_speaking_text_async(
-            self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hasync
-        ))?;
-        SynthesizerAsync::new(hasync);
-        Ok(())
+        self.follow(|handle| {
+            hr!(synthesizer_speak_text_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
+        Ok(SynthesizerAsync::new(hasync))
     }
 
-    /// Execute the speech synthesis on SSML, asynchronously.
-    pub fn start_ssml_synthesis(&mut self, text: &str) -> Result {
+    /// Execute the speech synthesis on SSML, the returned handle waits the
+    /// result.
+    pub fn speak_ssml_async<S: AsSsml + ?Sized>(
+        &mut self,
+        ssml: &S,
+    ) -> Result<SynthesizerAsync> {
+        let text = ssml.as_ssml()?;
+        let mut hasync = INVALID_HANDLE;
+        let txt_len = text.len() as u32;
+        let txt = CString::new(text.as_bytes())?;
+        self.follow(|handle| {
+            hr!(synthesizer_speak_ssml_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
+        Ok(SynthesizerAsync::new(hasync))
+    }
+
+    /// Start the speech synthesis on plain text, asynchronously. The task
+    /// reports the progress and resolves the outcome of this request.
+    pub fn start_synthesize(&mut self, text: &str) -> Result<SynthesisTask> {
         let mut hasync = INVALID_HANDLE;
         let txt_len = text.len() as u32;
         let txt = CString::new(text)?;
-        hr!(synthesizer_start_speaking_ssml_async(
-            self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hasync
-        ))?;
+        let task = self.track(|handle| {
+            hr!(synthesizer_start_speaking_text_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
         SynthesizerAsync::new(hasync);
-        Ok(())
+        Ok(task)
+    }
+
+    /// Start the speech synthesis on SSML, asynchronously. The task reports
+    /// the progress and resolves the outcome of this request.
+    pub fn start_ssml_synthesis<S: AsSsml + ?Sized>(
+        &mut self,
+        ssml: &S,
+    ) -> Result<SynthesisTask> {
+        let text = ssml.as_ssml()?;
+        let mut hasync = INVALID_HANDLE;
+        let txt_len = text.len() as u32;
+        let txt = CString::new(text.as_bytes())?;
+        let task = self.track(|handle| {
+            hr!(synthesizer_start_speaking_ssml_async(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hasync
+            ))
+        })?;
+        SynthesizerAsync::new(hasync);
+        Ok(task)
     }
 
     /// Execute the speech synthesis on plain text, synchronously.
@@ -158,30 +261,156 @@ impl Synthesizer {
         let mut hres = INVALID_HANDLE;
         let txt_len = text.len() as u32;
         let txt = CString::new(text)?;
-        hr!(synthesizer_speak_text(
-            self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hres
-        ))?;
+        self.follow(|handle| {
+            hr!(synthesizer_speak_text(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hres
+            ))
+        })?;
         SynthEventResult::new(Flags::empty(), hres)
     }
 
     /// Execute the speech synthesis on SSML, synchronously.
-    pub fn ssml_synthesis_once(
+    pub fn ssml_synthesis_once<S: AsSsml + ?Sized>(
         &mut self,
-        text: &str,
+        ssml: &S,
     ) -> Result<SynthEventResult> {
+        let text = ssml.as_ssml()?;
         let mut hres = INVALID_HANDLE;
         let txt_len = text.len() as u32;
-        let txt = CString::new(text)?;
-        hr!(synthesizer_speak_ssml(
+        let txt = CString::new(text.as_bytes())?;
+        self.follow(|handle| {
+            hr!(synthesizer_speak_ssml(
+                handle,
+                txt.as_ptr(),
+                txt_len,
+                &mut hres
+            ))
+        })?;
+        SynthEventResult::new(Flags::empty(), hres)
+    }
+
+    /// Execute the speech synthesis on text normalized by the rules,
+    /// synchronously. It is SSML if the voice name is set, else plain text
+    /// with the words expanded.
+    pub fn normalized_synthesis_once(
+        &mut self,
+        text: &str,
+        normalizer: &Normalizer,
+    ) -> Result<SynthEventResult> {
+        let voice = self.props.get_by_id(Property::SynthVoice.id())?;
+        if voice.is_empty() {
+            self.synthesis_once(&normalizer.to_text(text))
+        } else {
+            self.ssml_synthesis_once(&normalizer.to_ssml(text, &voice))
+        }
+    }
+
+    /// Queue the request to the tracker, then pass it to the synthesizer.
+    fn track<F>(&mut self, speak: F) -> Result<SynthesisTask>
+    where
+        F: FnOnce(SPXSYNTHHANDLE) -> Result,
+    {
+        let relay = self.relay()?;
+        let task = relay.tracker.submit()?;
+        if let Err(err) = speak(self.handle) {
+            relay.tracker.withdraw(&task);
+            return Err(err);
+        }
+        Ok(task)
+    }
+
+    /// Requests without task are queued too if others are tracked, their
+    /// events must not be taken by the next ones.
+    fn follow<F>(&mut self, speak: F) -> Result
+    where
+        F: FnOnce(SPXSYNTHHANDLE) -> Result,
+    {
+        if self.relay.is_some() {
+            self.track(speak).map(|_| ())
+        } else {
+            speak(self.handle)
+        }
+    }
+
+    /// Context of the callbacks, they are set at the first use.
+    fn relay(&mut self) -> Result<Arc<Relay>> {
+        if let Some(relay) = &self.relay {
+            return Ok(relay.clone());
+        }
+        let relay = Arc::new(Relay::default());
+        let context = Relay::context(&relay);
+        hr!(synthesizer_started_set_callback(
             self.handle,
-            txt.as_ptr(),
-            txt_len,
-            &mut hres
+            Some(on_synthesis_started),
+            context,
         ))?;
-        SynthEventResult::new(Flags::empty(), hres)
+        hr!(synthesizer_synthesizing_set_callback(
+            self.handle,
+            Some(on_synthesizing),
+            context,
+        ))?;
+        hr!(synthesizer_completed_set_callback(
+            self.handle,
+            Some(on_synthesized),
+            context,
+        ))?;
+        hr!(synthesizer_canceled_set_callback(
+            self.handle,
+            Some(on_synth_canceled),
+            context,
+        ))?;
+        self.relay = Some(relay.clone());
+        Ok(relay)
+    }
+
+    /// Output format of the synthesized audio.
+    pub fn output_format(&self) -> Result<OutputFormat> {
+        let name = self.props.get_by_id(Property::SynthOutputFormat.id())?;
+        if name.is_empty() {
+            Ok(OutputFormat::default())
+        } else {
+            name.parse()
+        }
+    }
+
+    /// Synthesize plain text to the file framed by the output format, e.g.
+    /// WAV, OGG or MP3.
+    pub fn synthesize_to_file<P: AsRef<Path>>(
+        &mut self,
+        text: &str,
+        path: P,
+    ) -> Result<Synthesis> {
+        let result = self.synthesis_once(text)?;
+        self.write_file(&result, path)
+    }
+
+    /// Synthesize SSML to the file framed by the output format.
+    pub fn ssml_synthesize_to_file<S, P>(
+        &mut self,
+        ssml: &S,
+        path: P,
+    ) -> Result<Synthesis>
+    where
+        S: AsSsml + ?Sized,
+        P: AsRef<Path>,
+    {
+        let result = self.ssml_synthesis_once(ssml)?;
+        self.write_file(&result, path)
+    }
+
+    fn write_file<P: AsRef<Path>>(
+        &self,
+        result: &SynthEventResult,
+        path: P,
+    ) -> Result<Synthesis> {
+        let mut synthesis = result.synthesis(Flags::Synthesized)?;
+        let format = self.output_format()?;
+        format.write_file(path, &synthesis.audio_data)?;
+        synthesis.format = Some(format);
+        Ok(synthesis)
     }
 
     /// Input audio data via created stream.
@@ -194,6 +423,20 @@ impl Synthesizer {
         self.audio.read(buffer)
     }
 
+    /// Output audio data via created stream, wait at most timeout.
+    pub fn read_stream_timeout(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        self.audio.read_timeout(buffer, timeout)
+    }
+
+    /// Shared reader of the output stream for blocking or asynchronous read.
+    pub fn stream_reader(&self) -> Result<OutputReader> {
+        self.audio.reader().ok_or(IsNothing)
+    }
+
     /// Close the audio stream gracefully.
     pub fn close_stream(&mut self) -> Result {
         self.audio.close()
@@ -209,9 +452,16 @@ impl Synthesizer {
         hr!(synthesizer_enable(self.handle))
     }
 
+    /// Stop the synthesis in progress and its audio output.
+    pub fn stop_speaking(&self) -> Result {
+        hr!(synthesizer_stop_speaking(self.handle))
+    }
+
     /// Check started by event sink handle.
     pub fn started(&self) -> bool {
-        self.sink.is_some()
+        self.relay
+            .as_ref()
+            .map_or(false, |relay| relay.is_posting())
     }
 
     /// Start the synthesis session with configuration present.
@@ -223,7 +473,9 @@ impl Synthesizer {
     /// Stop the sesstion.
     pub fn stop(&mut self) -> Result {
         self.close_stream()?;
-        self.sink = None;
+        if let Some(relay) = &self.relay {
+            *relay.sink.lock().map_err(|_| Poisoned)? = None;
+        }
         Ok(())
     }
 
@@ -237,41 +489,36 @@ impl Synthesizer {
         let flags = self.flags | flags;
 
         let (s, r) = unbounded::<Event>();
-        let sink = Arc::new(s);
-        self.sink = Some(sink.clone());
-        let reception = EventStream::new(r, flags);
-
-        let sk = Box::new(Arc::downgrade(&sink));
-        let context = Box::into_raw(sk) as *mut c_void;
-
-        if flags.contains(Flags::Synthesizing) {
-            hr!(synthesizer_synthesizing_set_callback(
-                self.handle,
-                Some(on_synthesizing),
-                context,
-            ))?;
+        let mut reception = EventStream::new(r, flags);
+        match self.output_format() {
+            Ok(format) => reception = reception.set_format(format),
+            Err(err) => log::warn!("unknown output format: {}", err),
         }
 
-        if flags.contains(Flags::Synthesized) {
-            hr!(synthesizer_completed_set_callback(
+        let relay = self.relay()?;
+        *relay.sink.lock().map_err(|_| Poisoned)? = Some((s, flags));
+        let context = Relay::context(&relay);
+
+        if flags.contains(Flags::WordBoundary) {
+            hr!(synthesizer_word_boundary_set_callback(
                 self.handle,
-                Some(on_synthesized),
+                Some(on_word_boundary),
                 context,
             ))?;
         }
 
-        if flags.contains(Flags::SessionStarted) {
-            hr!(synthesizer_started_set_callback(
+        if flags.contains(Flags::Viseme) {
+            hr!(synthesizer_viseme_received_set_callback(
                 self.handle,
-                Some(on_synthesis_started),
+                Some(on_viseme),
                 context,
             ))?;
         }
 
-        if flags.contains(Flags::Canceled) {
-            hr!(synthesizer_canceled_set_callback(
+        if flags.contains(Flags::BookmarkReached) {
+            hr!(synthesizer_bookmark_reached_set_callback(
                 self.handle,
-                Some(on_synth_canceled),
+                Some(on_bookmark_reached),
                 context,
             ))?;
         }
@@ -285,6 +532,7 @@ pub struct EventStream {
     filter: Flags,
     source: UnboundedReceiver<Event>,
     stopped: bool,
+    format: Option<OutputFormat>,
 }
 
 impl EventStream {
@@ -294,9 +542,16 @@ impl EventStream {
             filter,
             source,
             stopped: false,
+            format: None,
         }
     }
 
+    /// Format of the synthesized audio reported by `synthesis`.
+    pub fn set_format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
     /// Define the new filter to pick out special events.
     pub fn set_filter(mut self, flags: Flags) -> Self {
         self.filter = flags;
@@ -315,6 +570,34 @@ impl EventStream {
             }
         })
     }
+
+    /// Synthesis streaming of event object, with the audio format.
+    pub fn synthesis(self) -> impl Stream<Item = Synthesis, Error = SpxError> {
+        let format = self.format;
+        self.filter(|evt| !evt.flag().intersects(Flags::SynthesisMark))
+            .then(move |res| {
+                if let Ok(evt) = res {
+                    let mut synthesis = evt.into_synth_result()?;
+                    synthesis.format = format;
+                    Ok(synthesis)
+                } else {
+                    Err(Other(String::from("streaming is interrupted")))
+                }
+            })
+    }
+
+    /// Streaming of word boundaries, visemes and bookmarks, other events
+    /// are skipped.
+    pub fn marks(self) -> impl Stream<Item = SynthesisMark, Error = SpxError> {
+        self.filter(|evt| evt.flag().intersects(Flags::SynthesisMark))
+            .then(|res| {
+                if let Ok(evt) = res {
+                    evt.into_synthesis_mark()
+                } else {
+                    Err(Other(String::from("streaming is interrupted")))
+                }
+            })
+    }
 }
 
 /// The streaming implementation of futures.
@@ -347,6 +630,9 @@ DefCallback!(on_synthesizing, Flags::Synthesizing);
 DefCallback!(on_synthesized, Flags::Synthesized);
 DefCallback!(on_synthesis_started, Flags::SessionStarted);
 DefCallback!(on_synth_canceled, Flags::Canceled);
+DefCallback!(on_word_boundary, Flags::WordBoundary);
+DefCallback!(on_viseme, Flags::Viseme);
+DefCallback!(on_bookmark_reached, Flags::BookmarkReached);
 
 fn fire_on_event(flag: Flags, hevent: SPXEVENTHANDLE, context: *mut c_void) {
     log::trace!("Recognition event {:?} fired.", flag);
@@ -356,17 +642,100 @@ fn fire_on_event(flag: Flags, hevent: SPXEVENTHANDLE, context: *mut c_void) {
         return;
     }
     log::trace!("Event is fired with {:?} and address: {:?}", flag, context);
-    let ctx =
-        unsafe { Box::from_raw(context as *mut Weak<UnboundedSender<Event>>) };
+    let ctx = unsafe { Box::from_raw(context as *mut Weak<Relay>) };
     let weak_ptr = Weak::clone(&ctx);
     // forget the box, at least one box is leaked.
     Box::into_raw(ctx);
-    if let Some(mut arc) = weak_ptr.upgrade() {
-        let sender = Arc::make_mut(&mut arc);
-        if let Err(err) = sender.unbounded_send(evt) {
-            log::error!("failed to post {:?} event: {}", flag, err);
-        }
+    if let Some(relay) = weak_ptr.upgrade() {
+        relay.tracker.on_event(&evt);
+        relay.post(evt);
     } else {
-        log::error!("Recognizer instance is dropped!");
+        log::error!("Synthesizer instance is dropped!");
+    }
+}
+
+/// Context of the callbacks, events are followed by the tracker and posted
+/// to the stream if it is started.
+#[derive(Default)]
+struct Relay {
+    tracker: Tracker,
+    sink: Mutex<Option<(UnboundedSender<Event>, Flags)>>,
+}
+
+impl Relay {
+    /// Context pointer of the callbacks.
+    fn context(relay: &Arc<Relay>) -> *mut c_void {
+        Box::into_raw(Box::new(Arc::downgrade(relay))) as *mut c_void
+    }
+
+    fn is_posting(&self) -> bool {
+        self.sink.lock().map_or(false, |sink| sink.is_some())
+    }
+
+    /// Post the event to the stream if its flag is subscribed.
+    fn post(&self, evt: Event) {
+        let flag = evt.flag();
+        if let Ok(sink) = self.sink.lock() {
+            if let Some((sender, flags)) = sink.as_ref() {
+                if flag.intersects(*flags) {
+                    if let Err(err) = sender.unbounded_send(evt) {
+                        log::error!("failed to post {:?} event: {}", flag, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech::events::OwnedEvent;
+    use futures::Future;
+
+    fn synthesizing() -> Event {
+        Event::from_owned(OwnedEvent::Synthesis {
+            flag: Flags::Synthesizing,
+            session: String::from("session"),
+            id: String::from("id"),
+            reason: Flags::Synthesizing,
+            audio: vec![0; 32],
+        })
+    }
+
+    #[test]
+    fn marks_skip_other_events() {
+        let (sender, receiver) = unbounded();
+        let failed =
+            |flag, msg: &str| Event::from_failure(flag, Other(msg.to_string()));
+        let events = vec![
+            Event::from_owned(OwnedEvent::Session {
+                flag: Flags::SessionStarted,
+                session: String::from("session"),
+            }),
+            failed(Flags::WordBoundary, "word"),
+            synthesizing(),
+            failed(Flags::Viseme, "viseme"),
+            failed(Flags::BookmarkReached, "bookmark"),
+            synthesizing(),
+        ];
+        for evt in events {
+            sender.unbounded_send(evt).unwrap();
+        }
+        drop(sender);
+        let marks = EventStream::new(receiver, Flags::all())
+            .marks()
+            .then(|res| Ok::<_, ()>(res))
+            .collect()
+            .wait()
+            .unwrap();
+        let messages: Vec<String> = marks
+            .into_iter()
+            .map(|res| match res {
+                Err(Other(msg)) => msg,
+                other => panic!("unexpected mark: {:?}", other),
+            })
+            .collect();
+        assert_eq!(messages, vec!["word", "viseme", "bookmark"]);
     }
 }
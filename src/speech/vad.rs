@@ -0,0 +1,439 @@
+//! Client side voice activity detection and endpointing of push audio input.
+//!
+//! Audio is cut into frames of fixed duration and each frame is classified as
+//! voiced or silent by its energy, optionally refined by its zero crossing
+//! rate. Silence before the speech and after the hangover is not forwarded to
+//! the service.
+
+use super::audio::AudioSpec;
+use crate::{error::Other, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::Duration};
+
+/// Tunable parameters of voice activity detection.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    /// Duration of analysis frame in milliseconds.
+    pub frame_ms: u32,
+    /// Energy threshold of voiced frame in dBFS.
+    pub threshold: f32,
+    /// Frames with higher zero crossing rate (0.0 ~ 1.0) are taken as noise.
+    pub max_zero_crossing_rate: Option<f32>,
+    /// Duration of continuous voiced frames to confirm the start of speech.
+    pub start_ms: u32,
+    /// Duration of continuous silence to confirm the end of speech.
+    pub hangover_ms: u32,
+    /// Duration of audio kept and sent before the start of speech.
+    pub pre_roll_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        VadConfig {
+            frame_ms: 20,
+            threshold: -40.0,
+            max_zero_crossing_rate: None,
+            start_ms: 60,
+            hangover_ms: 600,
+            pre_roll_ms: 200,
+        }
+    }
+}
+
+/// Local voice activity with offset of the input audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    Start(Duration),
+    End(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Silence,
+    Speech,
+    Hangover,
+}
+
+/// Stateful detector for 16 bits little-endian PCM audio.
+pub struct VoiceDetector {
+    config: VadConfig,
+    frame_size: usize,
+    frame_duration: Duration,
+    channels: usize,
+    start_frames: usize,
+    hangover_frames: usize,
+    pre_roll_frames: usize,
+    state: State,
+    pending: Vec<u8>,
+    pre_roll: VecDeque<Vec<u8>>,
+    voiced_run: usize,
+    silent_run: usize,
+    frames: u64,
+}
+
+impl VoiceDetector {
+    /// Constructor with the format of input audio.
+    pub fn new(config: VadConfig, spec: &AudioSpec) -> Result<Self> {
+        if spec.bits != 16 {
+            return Err(Other(format!(
+                "voice detection needs 16 bits samples, got {}",
+                spec.bits
+            )));
+        }
+        let channels = spec.channels as usize;
+        let samples = u64::from(spec.rate) * u64::from(config.frame_ms) / 1000;
+        if samples == 0 || channels == 0 {
+            return Err(Other(format!(
+                "voice detection frame of {} ms at {} Hz has no samples",
+                config.frame_ms, spec.rate
+            )));
+        }
+        let samples = samples as usize;
+        let frames_of = |ms: u32| (ms / config.frame_ms) as usize;
+        let start_frames = frames_of(config.start_ms).max(1);
+        Ok(VoiceDetector {
+            config,
+            frame_size: samples * channels * 2,
+            frame_duration: Duration::from_millis(u64::from(config.frame_ms)),
+            channels,
+            start_frames,
+            hangover_frames: frames_of(config.hangover_ms).max(1),
+            pre_roll_frames: frames_of(config.pre_roll_ms).max(start_frames),
+            state: State::Silence,
+            pending: Vec::new(),
+            pre_roll: VecDeque::new(),
+            voiced_run: 0,
+            silent_run: 0,
+            frames: 0,
+        })
+    }
+
+    /// Configuration of the detector.
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    /// Check if speech is going on.
+    pub fn in_speech(&self) -> bool {
+        self.state != State::Silence
+    }
+
+    /// Feed input audio, append audio to be sent to output and return the
+    /// activities detected.
+    pub fn process(
+        &mut self,
+        data: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Vec<Activity> {
+        let mut activities = Vec::new();
+        self.pending.extend_from_slice(data);
+        let mut pos = 0;
+        while self.pending.len() - pos >= self.frame_size {
+            let frame = self.pending[pos..pos + self.frame_size].to_vec();
+            pos += self.frame_size;
+            if let Some(act) = self.process_frame(frame, output) {
+                activities.push(act);
+            }
+        }
+        self.pending.drain(..pos);
+        activities
+    }
+
+    /// End of input. Flush the partial frame if speech is going on.
+    pub fn finish(&mut self, output: &mut Vec<u8>) -> Option<Activity> {
+        let rest = std::mem::replace(&mut self.pending, Vec::new());
+        self.pre_roll.clear();
+        self.voiced_run = 0;
+        match self.state {
+            State::Silence => None,
+            State::Speech | State::Hangover => {
+                output.extend_from_slice(&rest);
+                self.state = State::Silence;
+                Some(Activity::End(self.offset(self.frames)))
+            }
+        }
+    }
+
+    /// Drop the state of current input to detect a new one.
+    pub fn reset(&mut self) {
+        self.state = State::Silence;
+        self.pending.clear();
+        self.pre_roll.clear();
+        self.voiced_run = 0;
+        self.silent_run = 0;
+        self.frames = 0;
+    }
+
+    fn offset(&self, frames: u64) -> Duration {
+        self.frame_duration * frames as u32
+    }
+
+    fn process_frame(
+        &mut self,
+        frame: Vec<u8>,
+        output: &mut Vec<u8>,
+    ) -> Option<Activity> {
+        let voiced = self.is_voiced(&frame);
+        self.frames += 1;
+        match self.state {
+            State::Silence => {
+                self.voiced_run = if voiced { self.voiced_run + 1 } else { 0 };
+                self.pre_roll.push_back(frame);
+                if self.pre_roll.len() > self.pre_roll_frames {
+                    self.pre_roll.pop_front();
+                }
+                if self.voiced_run < self.start_frames {
+                    return None;
+                }
+                for f in self.pre_roll.drain(..) {
+                    output.extend_from_slice(&f);
+                }
+                self.state = State::Speech;
+                self.voiced_run = 0;
+                let start = self.frames - self.start_frames as u64;
+                Some(Activity::Start(self.offset(start)))
+            }
+            State::Speech | State::Hangover => {
+                output.extend_from_slice(&frame);
+                if voiced {
+                    self.state = State::Speech;
+                    self.silent_run = 0;
+                    return None;
+                }
+                self.state = State::Hangover;
+                self.silent_run += 1;
+                if self.silent_run < self.hangover_frames {
+                    return None;
+                }
+                self.state = State::Silence;
+                let end = self.frames - self.silent_run as u64;
+                self.silent_run = 0;
+                Some(Activity::End(self.offset(end)))
+            }
+        }
+    }
+
+    /// Classify the frame by energy and zero crossing rate.
+    fn is_voiced(&self, frame: &[u8]) -> bool {
+        let samples: Vec<f32> = frame
+            .chunks_exact(2 * self.channels)
+            .map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0)
+            .collect();
+        if samples.is_empty() {
+            return false;
+        }
+        let energy =
+            samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        let db = 10.0 * energy.max(1e-10).log10();
+        if db < self.config.threshold {
+            return false;
+        }
+        match self.config.max_zero_crossing_rate {
+            Some(max) => zero_crossing_rate(&samples) <= max,
+            None => true,
+        }
+    }
+}
+
+/// Rate of sign changes between adjacent samples.
+fn zero_crossing_rate(samples: &[f32]) -> f32 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let crossings = samples
+        .windows(2)
+        .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+        .count();
+    crossings as f32 / (samples.len() - 1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+    /// Samples of a 20 ms frame at 16 kHz.
+    const FRAME: usize = 320;
+
+    fn spec() -> AudioSpec {
+        AudioSpec {
+            rate: RATE,
+            bits: 16,
+            channels: 1,
+        }
+    }
+
+    fn pcm(samples: impl Iterator<Item = i16>) -> Vec<u8> {
+        samples.flat_map(|s| s.to_le_bytes().to_vec()).collect()
+    }
+
+    fn silence(frames: usize) -> Vec<u8> {
+        vec![0; frames * FRAME * 2]
+    }
+
+    /// Sine of 440 Hz at half of full scale, about -9 dBFS.
+    fn tone(frames: usize) -> Vec<u8> {
+        pcm((0..frames * FRAME).map(|i| {
+            let t = i as f32 / RATE as f32;
+            (16384.0 * (2.0 * std::f32::consts::PI * 440.0 * t).sin()) as i16
+        }))
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn rejects_frames_without_samples() {
+        let config = VadConfig {
+            frame_ms: 20,
+            ..VadConfig::default()
+        };
+        let slow = AudioSpec {
+            rate: 8,
+            bits: 16,
+            channels: 1,
+        };
+        assert!(VoiceDetector::new(config, &slow).is_err());
+
+        let empty = VadConfig {
+            frame_ms: 0,
+            ..VadConfig::default()
+        };
+        assert!(VoiceDetector::new(empty, &spec()).is_err());
+    }
+
+    #[test]
+    fn large_rate_does_not_overflow() {
+        let config = VadConfig {
+            frame_ms: 1000,
+            ..VadConfig::default()
+        };
+        let fast = AudioSpec {
+            rate: 8_000_000,
+            bits: 16,
+            channels: 1,
+        };
+        let vad = VoiceDetector::new(config, &fast).unwrap();
+        assert_eq!(vad.frame_size, 16_000_000);
+    }
+
+    #[test]
+    fn detects_start_with_pre_roll_and_end_after_hangover() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let mut input = silence(20);
+        input.extend(tone(10));
+        input.extend(silence(40));
+
+        let mut output = Vec::new();
+        let activities = vad.process(&input, &mut output);
+        // Speech starts at frame 20, the 30 frames hangover ends at frame 60.
+        assert_eq!(
+            activities,
+            vec![Activity::Start(ms(400)), Activity::End(ms(600))]
+        );
+        assert!(!vad.in_speech());
+
+        // 10 frames of pre-roll, 7 more voiced frames and the hangover.
+        assert_eq!(output.len(), (10 + 7 + 30) * FRAME * 2);
+        let pre_roll = &output[..10 * FRAME * 2];
+        assert_eq!(&pre_roll[..7 * FRAME * 2], &silence(7)[..]);
+        assert_eq!(
+            &pre_roll[7 * FRAME * 2..],
+            &input[20 * FRAME * 2..23 * FRAME * 2]
+        );
+        assert_eq!(
+            &output[10 * FRAME * 2..17 * FRAME * 2],
+            &input[23 * FRAME * 2..30 * FRAME * 2]
+        );
+    }
+
+    #[test]
+    fn short_burst_does_not_start() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let mut input = silence(5);
+        input.extend(tone(2));
+        input.extend(silence(5));
+        let mut output = Vec::new();
+        assert!(vad.process(&input, &mut output).is_empty());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn pause_shorter_than_hangover_keeps_speech() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let mut output = Vec::new();
+        let mut activities = vad.process(&tone(5), &mut output);
+        activities.extend(vad.process(&silence(20), &mut output));
+        activities.extend(vad.process(&tone(5), &mut output));
+        assert_eq!(activities, vec![Activity::Start(ms(0))]);
+        assert!(vad.in_speech());
+        assert_eq!(output.len(), 30 * FRAME * 2);
+    }
+
+    #[test]
+    fn frames_split_across_writes() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let input = tone(4);
+        let mut output = Vec::new();
+        let mut activities = Vec::new();
+        for chunk in input.chunks(101) {
+            activities.extend(vad.process(chunk, &mut output));
+        }
+        assert_eq!(activities, vec![Activity::Start(ms(0))]);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn finish_flushes_partial_frame_of_speech() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let mut output = Vec::new();
+        vad.process(&tone(3), &mut output);
+        vad.process(&[1, 0, 2, 0], &mut output);
+        assert_eq!(vad.finish(&mut output), Some(Activity::End(ms(60))));
+        assert_eq!(output.len(), 3 * FRAME * 2 + 4);
+        assert_eq!(vad.finish(&mut output), None);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut vad =
+            VoiceDetector::new(VadConfig::default(), &spec()).unwrap();
+        let mut output = Vec::new();
+        vad.process(&silence(5), &mut output);
+        vad.process(&tone(3), &mut output);
+        vad.process(&[1, 0, 2, 0], &mut output);
+        assert!(vad.in_speech());
+
+        vad.reset();
+        assert!(!vad.in_speech());
+        let mut output = Vec::new();
+        let activities = vad.process(&tone(3), &mut output);
+        assert_eq!(activities, vec![Activity::Start(ms(0))]);
+        assert_eq!(output, tone(3));
+    }
+
+    #[test]
+    fn noisy_frames_are_rejected_by_zero_crossing_rate() {
+        let config = VadConfig {
+            max_zero_crossing_rate: Some(0.5),
+            ..VadConfig::default()
+        };
+        let mut vad = VoiceDetector::new(config, &spec()).unwrap();
+        let noise =
+            pcm((0..10 * FRAME)
+                .map(|i| if i % 2 == 0 { 16384 } else { -16384 }));
+        let mut output = Vec::new();
+        assert!(vad.process(&noise, &mut output).is_empty());
+        assert_eq!(
+            vad.process(&tone(3), &mut output),
+            vec![Activity::Start(ms(200))]
+        );
+    }
+}
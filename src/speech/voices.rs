@@ -0,0 +1,632 @@
+//! Catalog of the synthesis voices and selection by criteria.
+//!
+//! The catalog is fetched from the voices list of the service, or loaded
+//! from JSON of the same shape, e.g. a local fixture. Voices are selected by
+//! a query like "en-GB female neural", and the selected short name can be
+//! put into `RecognizerConfig` as the synthesis or translation voice.
+
+use crate::{
+    error::{InvalidConfig, Other, Poisoned},
+    Result, SpxError,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Gender of the voice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gender {
+    Female,
+    Male,
+    Neutral,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Kind of the voice model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoiceType {
+    Neural,
+    Standard,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Voice in the voices list of the service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VoiceInfo {
+    /// Full name, e.g. "Microsoft Server Speech Text to Speech Voice
+    /// (en-US, JessaRUS)".
+    pub name: String,
+    /// Short name, e.g. "en-US-JessaRUS".
+    pub short_name: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub local_name: String,
+    pub locale: String,
+    pub gender: Gender,
+    #[serde(default = "unknown_type")]
+    pub voice_type: VoiceType,
+    #[serde(default, deserialize_with = "from_str_or_number")]
+    pub sample_rate_hertz: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub style_list: Vec<String>,
+}
+
+fn unknown_type() -> VoiceType {
+    VoiceType::Unknown
+}
+
+/// The service reports the sample rate as a string.
+fn from_str_or_number<'de, D>(
+    deserializer: D,
+) -> std::result::Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Rate {
+        Number(u32),
+        Text(String),
+    }
+    match Rate::deserialize(deserializer)? {
+        Rate::Number(n) => Ok(n),
+        Rate::Text(s) => s.trim().parse().map_err(de::Error::custom),
+    }
+}
+
+impl VoiceInfo {
+    pub fn is_neural(&self) -> bool {
+        self.voice_type == VoiceType::Neural
+            || self.short_name.ends_with("Neural")
+    }
+
+    /// Check the voice speaks the style, e.g. "cheerful".
+    pub fn has_style(&self, style: &str) -> bool {
+        self.style_list
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(style))
+    }
+
+    /// Check the locale of the voice is the language, e.g. "en", or the
+    /// exact locale, e.g. "en-GB".
+    pub fn speaks(&self, language: &str) -> bool {
+        let locale = self.locale.to_ascii_lowercase();
+        let language = language.to_ascii_lowercase();
+        locale == language
+            || locale.split('-').next() == Some(language.as_str())
+    }
+}
+
+/// Criteria of voice selection, unset ones match any voice.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceQuery {
+    /// Language or locale, e.g. "en" or "en-GB".
+    pub locale: Option<String>,
+    pub gender: Option<Gender>,
+    /// Neural or standard voices, neural ones are preferred if unset.
+    pub neural: Option<bool>,
+    pub style: Option<String>,
+    /// Minimal sample rate in hertz.
+    pub sample_rate: Option<u32>,
+}
+
+impl VoiceQuery {
+    pub fn new() -> Self {
+        VoiceQuery::default()
+    }
+
+    pub fn locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    pub fn gender(mut self, gender: Gender) -> Self {
+        self.gender = Some(gender);
+        self
+    }
+
+    pub fn neural(mut self, neural: bool) -> Self {
+        self.neural = Some(neural);
+        self
+    }
+
+    pub fn style(mut self, style: &str) -> Self {
+        self.style = Some(style.to_string());
+        self
+    }
+
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    pub fn matches(&self, voice: &VoiceInfo) -> bool {
+        self.locale.as_ref().map_or(true, |l| voice.speaks(l))
+            && self.gender.map_or(true, |g| voice.gender == g)
+            && self.neural.map_or(true, |n| voice.is_neural() == n)
+            && self.style.as_ref().map_or(true, |s| voice.has_style(s))
+            && self
+                .sample_rate
+                .map_or(true, |r| voice.sample_rate_hertz >= r)
+    }
+
+    /// Preference among the matched voices, higher is better.
+    fn rank(&self, voice: &VoiceInfo) -> u8 {
+        let exact = self
+            .locale
+            .as_ref()
+            .map_or(false, |l| voice.locale.eq_ignore_ascii_case(l));
+        let neural = self.neural.is_none() && voice.is_neural();
+        (exact as u8) * 2 + neural as u8
+    }
+}
+
+/// Parse words of the criteria in any order, e.g. "en-GB female neural",
+/// "zh 24khz cheerful". The unknown word is taken as the style.
+impl FromStr for VoiceQuery {
+    type Err = SpxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut query = VoiceQuery::default();
+        for word in s.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() {
+                continue;
+            }
+            let lower = word.to_ascii_lowercase();
+            match lower.as_str() {
+                "female" => query.gender = Some(Gender::Female),
+                "male" => query.gender = Some(Gender::Male),
+                "neutral" => query.gender = Some(Gender::Neutral),
+                "neural" => query.neural = Some(true),
+                "standard" => query.neural = Some(false),
+                _ if lower.ends_with("khz") => {
+                    let khz: u32 = lower
+                        .trim_end_matches("khz")
+                        .parse()
+                        .map_err(|_| {
+                            InvalidConfig(
+                                String::from("voice query"),
+                                s.to_string(),
+                            )
+                        })?;
+                    query.sample_rate = Some(khz * 1000);
+                }
+                _ if is_locale(word) => query.locale = Some(word.to_string()),
+                _ if query.style.is_none() => query.style = Some(lower),
+                _ => {
+                    return Err(InvalidConfig(
+                        String::from("voice query"),
+                        s.to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Language like "en", or locale like "en-GB" and "zh-Hans-CN".
+fn is_locale(word: &str) -> bool {
+    let mut parts = word.split('-');
+    let language = parts.next().unwrap_or_default();
+    let rest: Vec<_> = parts.collect();
+    (language.len() == 2 || language.len() == 3)
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && (rest.is_empty() && language.len() == 2
+            || !rest.is_empty()
+                && rest.iter().all(|p| {
+                    !p.is_empty()
+                        && p.chars().all(|c| c.is_ascii_alphanumeric())
+                }))
+}
+
+impl fmt::Display for VoiceQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        if let Some(ref locale) = self.locale {
+            words.push(locale.clone());
+        }
+        if let Some(gender) = self.gender {
+            words.push(format!("{:?}", gender).to_ascii_lowercase());
+        }
+        match self.neural {
+            Some(true) => words.push(String::from("neural")),
+            Some(false) => words.push(String::from("standard")),
+            None => {}
+        }
+        if let Some(rate) = self.sample_rate {
+            words.push(format!("{}khz", rate / 1000));
+        }
+        if let Some(ref style) = self.style {
+            words.push(style.clone());
+        }
+        f.write_str(&words.join(" "))
+    }
+}
+
+/// Voices available to the subscription.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VoiceCatalog {
+    pub voices: Vec<VoiceInfo>,
+}
+
+impl VoiceCatalog {
+    /// Catalog of JSON in the shape of the voices list.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        VoiceCatalog::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Matched voices in order of the catalog.
+    pub fn filter<'a>(
+        &'a self,
+        query: &'a VoiceQuery,
+    ) -> impl Iterator<Item = &'a VoiceInfo> + 'a {
+        self.voices.iter().filter(move |v| query.matches(v))
+    }
+
+    /// The best matched voice, the exact locale and neural voices are
+    /// preferred, then the order of the catalog.
+    pub fn select(&self, query: &VoiceQuery) -> Option<&VoiceInfo> {
+        let mut best: Option<&VoiceInfo> = None;
+        for voice in self.voices.iter().filter(|v| query.matches(v)) {
+            if best.map_or(true, |b| query.rank(voice) > query.rank(b)) {
+                best = Some(voice);
+            }
+        }
+        best
+    }
+
+    /// Short name of the best matched voice, e.g. for
+    /// `RecognizerConfig::put_synth_voice_name`.
+    pub fn select_name(&self, query: &str) -> Result<String> {
+        let query: VoiceQuery = query.parse()?;
+        self.select(&query)
+            .map(|v| v.short_name.clone())
+            .ok_or_else(|| Other(format!("no voice matches \"{}\"", query)))
+    }
+
+    /// Voice of the short or full name.
+    pub fn find(&self, name: &str) -> Option<&VoiceInfo> {
+        self.voices
+            .iter()
+            .find(|v| v.short_name == name || v.name == name)
+    }
+
+    /// Distinct locales in order of the catalog.
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales: Vec<&str> = Vec::new();
+        for voice in &self.voices {
+            if !locales.contains(&voice.locale.as_str()) {
+                locales.push(&voice.locale);
+            }
+        }
+        locales
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+}
+
+/// Source of the voice catalog.
+pub trait VoiceSource: Send + Sync {
+    fn fetch(&self) -> Result<VoiceCatalog>;
+}
+
+/// Caller supplied source, e.g. loading a fixture.
+impl<F> VoiceSource for F
+where
+    F: Fn() -> Result<VoiceCatalog> + Send + Sync,
+{
+    fn fetch(&self) -> Result<VoiceCatalog> {
+        self()
+    }
+}
+
+/// Voices list endpoint of the service.
+pub struct VoicesEndpoint {
+    endpoint: String,
+    subscription: String,
+    client: reqwest::Client,
+}
+
+impl VoicesEndpoint {
+    /// Voices list of the region.
+    pub fn from_subscription(subscription: &str, region: &str) -> Self {
+        let endpoint = format!(
+            "https://{}.tts.speech.microsoft.com/cognitiveservices/voices/list",
+            region
+        );
+        VoicesEndpoint::from_endpoint(&endpoint, subscription)
+    }
+
+    /// Custom endpoint, e.g. of private deployments or a stand-in server.
+    pub fn from_endpoint(endpoint: &str, subscription: &str) -> Self {
+        VoicesEndpoint {
+            endpoint: endpoint.to_string(),
+            subscription: subscription.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+impl VoiceSource for VoicesEndpoint {
+    fn fetch(&self) -> Result<VoiceCatalog> {
+        let mut resp = self
+            .client
+            .get(&self.endpoint)
+            .header("Ocp-Apim-Subscription-Key", self.subscription.as_str())
+            .send()?;
+        let status = resp.status();
+        let body = resp.text()?;
+        if !status.is_success() {
+            return Err(Other(format!(
+                "voices list returned {}: {}",
+                status, body
+            )));
+        }
+        VoiceCatalog::from_json(&body)
+    }
+}
+
+/// Catalog fetched once and kept for the time to live. Clones share the
+/// catalog.
+#[derive(Clone)]
+pub struct Voices {
+    source: Arc<dyn VoiceSource>,
+    ttl: Duration,
+    cached: Arc<Mutex<Option<(Instant, Arc<VoiceCatalog>)>>>,
+}
+
+impl Voices {
+    pub fn new<S>(source: S, ttl: Duration) -> Self
+    where
+        S: VoiceSource + 'static,
+    {
+        Voices {
+            source: Arc::new(source),
+            ttl,
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The cached catalog, fetched again if expired. The expired catalog
+    /// is kept if fetching fails.
+    pub fn catalog(&self) -> Result<Arc<VoiceCatalog>> {
+        let mut cached = self.cached.lock().map_err(|_| Poisoned)?;
+        if let Some((fetched, ref catalog)) = *cached {
+            if fetched.elapsed() < self.ttl {
+                return Ok(catalog.clone());
+            }
+        }
+        match self.source.fetch() {
+            Ok(catalog) => {
+                let catalog = Arc::new(catalog);
+                *cached = Some((Instant::now(), catalog.clone()));
+                Ok(catalog)
+            }
+            Err(err) => match *cached {
+                Some((_, ref catalog)) => {
+                    log::warn!("failed to refresh voices: {}", err);
+                    Ok(catalog.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Fetch on next use.
+    pub fn invalidate(&self) -> Result {
+        *self.cached.lock().map_err(|_| Poisoned)? = None;
+        Ok(())
+    }
+
+    /// Short name of the best matched voice, e.g. "en-GB female neural".
+    pub fn select_name(&self, query: &str) -> Result<String> {
+        self.catalog()?.select_name(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FIXTURE: &str = include_str!("../../examples/fixtures/voices.json");
+
+    fn catalog() -> VoiceCatalog {
+        VoiceCatalog::from_json(FIXTURE).unwrap()
+    }
+
+    fn voice(short_name: &str, locale: &str, voice_type: &str) -> VoiceInfo {
+        serde_json::from_value(serde_json::json!({
+            "Name": short_name,
+            "ShortName": short_name,
+            "Locale": locale,
+            "Gender": "Female",
+            "VoiceType": voice_type,
+            "SampleRateHertz": 24000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_the_voices_list() {
+        let catalog = catalog();
+        assert_eq!(catalog.len(), 9);
+        assert_eq!(catalog.locales(), vec!["en-US", "en-GB", "zh-CN"]);
+        let aria = catalog.find("en-US-AriaNeural").unwrap();
+        assert_eq!(aria.gender, Gender::Female);
+        assert_eq!(aria.sample_rate_hertz, 24000);
+        assert!(aria.is_neural());
+        assert!(aria.has_style("Cheerful"));
+        assert!(aria.speaks("en") && aria.speaks("en-us") && !aria.speaks("e"));
+        assert_eq!(voice("x", "en", "Other").voice_type, VoiceType::Unknown);
+    }
+
+    #[test]
+    fn parses_queries() {
+        let cases: &[(&str, VoiceQuery)] = &[
+            ("", VoiceQuery::new()),
+            (
+                "en-GB female neural",
+                VoiceQuery::new()
+                    .locale("en-GB")
+                    .gender(Gender::Female)
+                    .neural(true),
+            ),
+            (
+                "zh 24khz, Cheerful",
+                VoiceQuery::new()
+                    .locale("zh")
+                    .sample_rate(24000)
+                    .style("cheerful"),
+            ),
+            (
+                "standard male zh-Hans-CN",
+                VoiceQuery::new()
+                    .neural(false)
+                    .gender(Gender::Male)
+                    .locale("zh-Hans-CN"),
+            ),
+        ];
+        for (text, query) in cases {
+            let parsed: VoiceQuery = text.parse().unwrap();
+            assert_eq!(parsed, *query, "{}", text);
+            assert_eq!(
+                parsed.to_string().parse::<VoiceQuery>().unwrap(),
+                parsed
+            );
+        }
+        for text in &["fastkhz", "cheerful sad", "EN-gb happy sad"] {
+            match text.parse::<VoiceQuery>() {
+                Err(SpxError::InvalidConfig(key, value)) => {
+                    assert_eq!(key, "voice query");
+                    assert_eq!(value, *text);
+                }
+                other => panic!("{}: {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn selects_voices() {
+        let catalog = catalog();
+        let cases = &[
+            ("en", "en-US-AriaNeural"),
+            ("en standard", "en-US-JessaRUS"),
+            ("en-GB", "en-GB-LibbyNeural"),
+            ("en-GB male", "en-GB-RyanNeural"),
+            ("zh cheerful", "zh-CN-XiaoxiaoNeural"),
+            ("male customerservice", "zh-CN-YunyangNeural"),
+            ("zh 16khz", "zh-CN-XiaoxiaoNeural"),
+        ];
+        for (query, name) in cases {
+            assert_eq!(catalog.select_name(query).unwrap(), *name, "{}", query);
+        }
+        let query = "en-GB".parse().unwrap();
+        let names: Vec<_> = catalog
+            .filter(&query)
+            .map(|v| v.short_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["en-GB-HazelRUS", "en-GB-LibbyNeural", "en-GB-RyanNeural"]
+        );
+        assert!(catalog.select_name("fr").is_err());
+        assert!(catalog.select_name("en 48khz").is_err());
+    }
+
+    #[test]
+    fn prefers_exact_locale_then_neural() {
+        let catalog = VoiceCatalog {
+            voices: vec![
+                voice("en-US-Neural", "en-US", "Neural"),
+                voice("en-Standard", "en", "Standard"),
+                voice("en-Neural", "en", "Neural"),
+            ],
+        };
+        let select = |query: &str| catalog.select_name(query).unwrap();
+        assert_eq!(select("en"), "en-Neural");
+        assert_eq!(select("en standard"), "en-Standard");
+        assert_eq!(select("en-US"), "en-US-Neural");
+        assert_eq!(select("en neural"), "en-Neural");
+    }
+
+    #[test]
+    fn keeps_the_catalog_for_the_ttl() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let count = fetched.clone();
+        let voices = Voices::new(
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(catalog())
+            },
+            Duration::from_secs(3600),
+        );
+        assert_eq!(voices.select_name("en").unwrap(), "en-US-AriaNeural");
+        let shared = voices.clone();
+        assert_eq!(shared.catalog().unwrap().len(), 9);
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+
+        voices.invalidate().unwrap();
+        voices.catalog().unwrap();
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        let count = fetched.clone();
+        let expired = Voices::new(
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(catalog())
+            },
+            Duration::from_secs(0),
+        );
+        expired.catalog().unwrap();
+        expired.catalog().unwrap();
+        assert_eq!(fetched.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn keeps_the_expired_catalog_on_error() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let count = fetched.clone();
+        let voices = Voices::new(
+            move || match count.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(catalog()),
+                _ => Err(Other(String::from("unavailable"))),
+            },
+            Duration::from_secs(0),
+        );
+        assert_eq!(voices.catalog().unwrap().len(), 9);
+        assert_eq!(voices.catalog().unwrap().len(), 9);
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        // Nothing to fall back on.
+        voices.invalidate().unwrap();
+        assert!(voices.catalog().is_err());
+        assert_eq!(fetched.load(Ordering::SeqCst), 3);
+    }
+}